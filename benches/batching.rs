@@ -0,0 +1,99 @@
+//! Measure what batching consecutive transactions by client could save:
+//! the cost of a transaction, the cost of an account lookup, and the number
+//! of times the client id is hashed per transaction.
+//!
+//! Run with `cargo bench --bench batching`.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use payments::{
+    account::Account, ids::ClientId, payments_engine::PaymentsEngine, transaction::Transaction,
+    transaction_kind::TransactionKind,
+};
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+const TRANSACTIONS: u32 = 2_000_000;
+const CLIENTS: u32 = 1_000;
+/// The consecutive transactions of each client, the best case for batching.
+const RUN: u32 = 10;
+
+static HASHES: AtomicU64 = AtomicU64::new(0);
+
+/// A client id counting how many times it's hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+struct Counted(u16);
+
+impl Hash for Counted {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        HASHES.fetch_add(1, Ordering::Relaxed);
+        self.0.hash(state);
+    }
+}
+
+impl fmt::Display for Counted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ClientId for Counted {
+    fn shard(self, shards: usize) -> usize {
+        self.0.shard(shards)
+    }
+}
+
+/// Get the client of the transaction of the given index.
+fn client(index: u32) -> u16 {
+    (index / RUN % CLIENTS + 1) as u16
+}
+
+/// Deposit on even indices and withdraw half of it on odd ones.
+fn transactions<C: ClientId>(client_id: impl Fn(u16) -> C) -> Vec<Transaction<C, u32>> {
+    (0..TRANSACTIONS)
+        .map(|id| {
+            let (kind, amount) = match id % 2 {
+                0 => (TransactionKind::Deposit, dec!(2)),
+                _ => (TransactionKind::Withdrawal, dec!(1)),
+            };
+            Transaction::new(kind, client_id(client(id)), id + 1, Some(amount))
+        })
+        .collect()
+}
+
+fn main() {
+    let txs = transactions(|client| client);
+    let mut engine = PaymentsEngine::new();
+    let started = Instant::now();
+    for tx in txs {
+        let _ = black_box(engine.execute(tx));
+    }
+    let execute = started.elapsed().as_nanos() / u128::from(TRANSACTIONS);
+    println!("PaymentsEngine::execute: {execute} ns per transaction");
+
+    let mut accounts: HashMap<u16, Account> = (1..=CLIENTS as u16)
+        .map(|client| (client, Account::new(client)))
+        .collect();
+    let started = Instant::now();
+    for index in 0..TRANSACTIONS {
+        black_box(accounts.get_mut(&black_box(client(index))));
+    }
+    let lookup = started.elapsed().as_nanos() / u128::from(TRANSACTIONS);
+    println!("HashMap::get_mut: {lookup} ns per lookup");
+
+    let txs = transactions(Counted);
+    let mut engine = PaymentsEngine::<Counted, u32>::default();
+    HASHES.store(0, Ordering::Relaxed);
+    for tx in txs {
+        let _ = black_box(engine.execute(tx));
+    }
+    let hashes = HASHES.load(Ordering::Relaxed) as f64 / f64::from(TRANSACTIONS);
+    println!("Client id hashes: {hashes:.2} per transaction");
+}
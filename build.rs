@@ -0,0 +1,13 @@
+//! Compile the protobuf definitions of the gRPC service, when enabled.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/payments.proto");
+
+    #[cfg(feature = "grpc")]
+    {
+        let descriptors = protox::compile(["proto/payments.proto"], ["proto"])?;
+        tonic_build::configure().compile_fds(descriptors)?;
+    }
+
+    Ok(())
+}
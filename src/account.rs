@@ -1,51 +1,222 @@
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use serde::Serialize;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{engine_config::SPEC_DECIMALS, ids::ClientId, transaction_error::TransactionError};
 
 /// A client account stating available, held and total funds, along with its
 /// locked/unlocked state flag and its identifier.
-#[derive(Debug, PartialEq, Serialize)]
-pub struct Account {
-    pub id: u16,
+///
+/// The `version` is incremented on every mutation, allowing external
+/// consumers to detect concurrent changes, while `last_activity` is the latest
+/// timestamp of the transactions applied to the account and `fees` the sum of
+/// the fees charged to it. They're not part of the CSV output, see
+/// `AccountActivity` to include the last activity or `AccountReport` for
+/// other columns, and neither is the `credit_limit` overriding the one of the
+/// engine for this account.
+///
+/// Accounts can be deserialized from the output format, e.g. to seed opening
+/// balances along with an optional `credit_limit` column. The total is then
+/// derived from the available and held funds.
+/// Amounts are serialized with exactly `SPEC_DECIMALS` decimal places, more
+/// precise balances being rounded half to even, see `AccountReport` to keep
+/// more places.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "AccountRecord<C>", bound(deserialize = "C: ClientId"))]
+pub struct Account<C = u16> {
+    pub id: C,
+    #[serde(serialize_with = "spec_decimals")]
+    pub available: Decimal,
+    #[serde(serialize_with = "spec_decimals")]
+    pub held: Decimal,
+    #[serde(serialize_with = "spec_decimals")]
+    pub total: Decimal,
+    pub locked: bool,
+    #[serde(skip)]
+    pub version: u64,
+    #[serde(skip)]
+    pub last_activity: Option<i64>,
+    #[serde(skip)]
+    pub fees: Decimal,
+    #[serde(skip)]
+    pub credit_limit: Option<Decimal>,
+}
+
+/// The deserialized form of an `Account`, the `total` column is optional.
+#[derive(Deserialize)]
+struct AccountRecord<C> {
+    #[serde(alias = "client")]
+    id: C,
+    available: Decimal,
+    held: Decimal,
+    total: Option<Decimal>,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    last_activity: Option<i64>,
+    #[serde(default)]
+    credit_limit: Option<Decimal>,
+}
+
+/// An account serialized along with its last activity, as an extra column
+/// which is empty for the accounts without any timestamped transaction.
+///
+/// # Example
+/// ```
+/// use payments::account::{Account, AccountActivity};
+///
+/// let mut account = Account::new(1);
+/// account.last_activity = Some(1_700_000_000);
+///
+/// let mut writer = csv::Writer::from_writer(vec![]);
+/// writer.serialize(AccountActivity::from(&account)).unwrap();
+/// let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+/// assert_eq!(
+///     csv,
+///     "id,available,held,total,locked,last_activity\n1,0.0000,0.0000,0.0000,false,1700000000\n"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountActivity<C = u16> {
+    pub id: C,
+    #[serde(serialize_with = "spec_decimals")]
     pub available: Decimal,
+    #[serde(serialize_with = "spec_decimals")]
     pub held: Decimal,
+    #[serde(serialize_with = "spec_decimals")]
     pub total: Decimal,
     pub locked: bool,
+    pub last_activity: Option<i64>,
+}
+
+impl<C: ClientId> From<&Account<C>> for AccountActivity<C> {
+    fn from(account: &Account<C>) -> Self {
+        Self {
+            id: account.id,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            last_activity: account.last_activity,
+        }
+    }
+}
+
+impl<C: ClientId> TryFrom<AccountRecord<C>> for Account<C> {
+    type Error = String;
+
+    fn try_from(record: AccountRecord<C>) -> Result<Self, Self::Error> {
+        let total = record
+            .available
+            .checked_add(record.held)
+            .ok_or_else(|| format!("client {}: total overflows", record.id))?;
+        if record.total.is_some_and(|t| t != total) {
+            return Err(format!(
+                "client {}: total doesn't match available and held",
+                record.id
+            ));
+        }
+
+        Ok(Self {
+            id: record.id,
+            available: record.available,
+            held: record.held,
+            total,
+            locked: record.locked,
+            version: 0,
+            last_activity: record.last_activity,
+            fees: dec!(0),
+            credit_limit: record.credit_limit,
+        })
+    }
+}
+
+/// Add two amounts, failing instead of overflowing.
+fn add(a: Decimal, b: Decimal) -> Result<Decimal, TransactionError> {
+    a.checked_add(b).ok_or(TransactionError::Overflow)
+}
+
+/// Subtract two amounts, failing instead of overflowing.
+fn sub(a: Decimal, b: Decimal) -> Result<Decimal, TransactionError> {
+    a.checked_sub(b).ok_or(TransactionError::Overflow)
+}
+
+/// Serialize an amount with exactly `SPEC_DECIMALS` decimal places.
+fn spec_decimals<S: Serializer>(amount: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    Serialize::serialize(&pad_decimals(amount.round_dp(SPEC_DECIMALS)), serializer)
+}
+
+/// Pad an amount with fewer than `SPEC_DECIMALS` decimal places to exactly
+/// that many. Amounts with more places are returned as is, rounding them is up
+/// to the caller, as `spec_decimals` does for the accounts output.
+pub(crate) fn pad_decimals(mut amount: Decimal) -> Decimal {
+    if amount.scale() < SPEC_DECIMALS {
+        amount.rescale(SPEC_DECIMALS);
+    }
+    amount
 }
 
 impl Account {
+    /// Open an empty account, see `Account::open` for other client ids.
     #[must_use]
     pub const fn new(id: u16) -> Self {
+        Self::open(id)
+    }
+}
+
+impl<C: ClientId> Account<C> {
+    /// Open an empty account for the client.
+    #[must_use]
+    pub const fn open(id: C) -> Self {
         Self {
             id,
             available: dec!(0),
             held: dec!(0),
             total: dec!(0),
             locked: false,
+            version: 0,
+            last_activity: None,
+            fees: dec!(0),
+            credit_limit: None,
         }
     }
 
     /// Deposit funds on the client account by increasing the available and
     /// total amounts.
     ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::Overflow` if the balances would overflow,
+    /// in which case the account is left untouched, as by every operation.
+    ///
     /// # Example
     /// ```
     /// use payments::account::Account;
+    /// use rust_decimal::Decimal;
     /// use rust_decimal_macros::dec;
     ///
     /// let mut account = Account::new(1);
-    /// account.deposit(dec!(1));
+    /// account.deposit(dec!(1)).unwrap();
     ///
     /// assert_eq!(account.available, dec!(1));
     /// assert_eq!(account.total, dec!(1));
+    /// assert!(account.deposit(Decimal::MAX).is_err());
     /// ```
-    pub fn deposit(&mut self, amount: Decimal) {
-        self.available += amount;
-        self.total += amount;
+    pub fn deposit(&mut self, amount: Decimal) -> Result<(), TransactionError> {
+        let available = add(self.available, amount)?;
+        self.total = add(self.total, amount)?;
+        self.available = available;
+        self.version += 1;
+        Ok(())
     }
 
     /// Withdraw funds on the client account by decreasing the available and
-    /// total amounts. The method has no effect if funds are insufficients.
+    /// total amounts, which can go negative down to minus the credit limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::InsufficientFunds` if the available funds
+    /// along with the credit limit don't cover the amount.
     ///
     /// # Example
     /// ```
@@ -53,94 +224,297 @@ impl Account {
     /// use rust_decimal_macros::dec;
     ///
     /// let mut account = Account::new(1);
-    /// account.deposit(dec!(1));
-    /// account.withdraw(dec!(1));
+    /// account.deposit(dec!(1)).unwrap();
+    /// account.withdraw(dec!(1), dec!(0)).unwrap();
+    /// assert!(account.withdraw(dec!(1), dec!(0)).is_err());
+    /// account.withdraw(dec!(1), dec!(5)).unwrap();
     ///
-    /// assert_eq!(account.available, dec!(0));
-    /// assert_eq!(account.total, dec!(0));
+    /// assert_eq!(account.available, dec!(-1));
+    /// assert_eq!(account.total, dec!(-1));
     /// ```
-    pub fn withdraw(&mut self, amount: Decimal) {
-        if amount > self.available {
-            return;
+    pub fn withdraw(
+        &mut self,
+        amount: Decimal,
+        credit_limit: Decimal,
+    ) -> Result<(), TransactionError> {
+        if amount > self.available.saturating_add(credit_limit) {
+            return Err(TransactionError::InsufficientFunds);
         }
 
-        self.available -= amount;
-        self.total -= amount;
+        let available = sub(self.available, amount)?;
+        self.total = sub(self.total, amount)?;
+        self.available = available;
+        self.version += 1;
+        Ok(())
     }
 
     /// Dispute a transaction by witholding funds.
     ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::InsufficientFunds` if the available funds
+    /// don't cover the amount.
+    ///
     /// # Example
     /// ```
     /// use payments::account::Account;
     /// use rust_decimal_macros::dec;
     ///
     /// let mut account = Account::new(1);
-    /// account.deposit(dec!(1));
-    /// account.dispute(dec!(1));
+    /// account.deposit(dec!(1)).unwrap();
+    /// account.dispute(dec!(1)).unwrap();
     ///
     /// assert_eq!(account.available, dec!(0));
     /// assert_eq!(account.held, dec!(1));
     /// assert_eq!(account.total, dec!(1));
     /// ```
-    pub fn dispute(&mut self, amount: Decimal) {
+    pub fn dispute(&mut self, amount: Decimal) -> Result<(), TransactionError> {
         if amount > self.available {
-            return;
+            return Err(TransactionError::InsufficientFunds);
         }
 
-        self.available -= amount;
-        self.held += amount;
+        self.force_dispute(amount)
+    }
+
+    /// Dispute a transaction by witholding funds, even if they are no longer
+    /// available. The available funds can then go negative, and a following
+    /// chargeback can leave a deficit on the account.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::Overflow` if the balances would overflow.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut account = Account::new(1);
+    /// account.deposit(dec!(1)).unwrap();
+    /// account.withdraw(dec!(1), dec!(0)).unwrap();
+    /// account.force_dispute(dec!(1)).unwrap();
+    /// account.chargeback(dec!(1)).unwrap();
+    ///
+    /// assert_eq!(account.total, dec!(-1));
+    /// assert_eq!(account.deficit(), dec!(1));
+    /// ```
+    pub fn force_dispute(&mut self, amount: Decimal) -> Result<(), TransactionError> {
+        let available = sub(self.available, amount)?;
+        self.held = add(self.held, amount)?;
+        self.available = available;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Get the outstanding deficit of the account, that is the amount needed
+    /// to bring its total back to zero. Future deposits offset it.
+    #[must_use]
+    pub fn deficit(&self) -> Decimal {
+        (-self.total).max(Decimal::ZERO)
     }
 
     /// Resolve a dispute by releasing funds.
     ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::InsufficientFunds` if the held funds don't
+    /// cover the amount.
+    ///
     /// # Example
     /// ```
     /// use payments::account::Account;
     /// use rust_decimal_macros::dec;
     ///
     /// let mut account = Account::new(1);
-    /// account.deposit(dec!(1));
-    /// account.dispute(dec!(1));
-    /// account.resolve(dec!(1));
+    /// account.deposit(dec!(1)).unwrap();
+    /// account.dispute(dec!(1)).unwrap();
+    /// account.resolve(dec!(1)).unwrap();
     ///
     /// assert_eq!(account.available, dec!(1));
     /// assert_eq!(account.held, dec!(0));
     /// assert_eq!(account.total, dec!(1));
     /// ```
-    pub fn resolve(&mut self, amount: Decimal) {
+    pub fn resolve(&mut self, amount: Decimal) -> Result<(), TransactionError> {
         if amount > self.held {
-            return;
+            return Err(TransactionError::InsufficientFunds);
         }
 
-        self.held -= amount;
-        self.available += amount;
+        let held = sub(self.held, amount)?;
+        self.available = add(self.available, amount)?;
+        self.held = held;
+        self.version += 1;
+        Ok(())
     }
 
     /// Resolve a dispute by charging funds back.
     ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::InsufficientFunds` if the held funds don't
+    /// cover the amount.
+    ///
     /// # Example
     /// ```
     /// use payments::account::Account;
     /// use rust_decimal_macros::dec;
     ///
     /// let mut account = Account::new(1);
-    /// account.deposit(dec!(1));
-    /// account.dispute(dec!(1));
-    /// account.chargeback(dec!(1));
+    /// account.deposit(dec!(1)).unwrap();
+    /// account.dispute(dec!(1)).unwrap();
+    /// account.chargeback(dec!(1)).unwrap();
     ///
     /// assert_eq!(account.available, dec!(0));
     /// assert_eq!(account.held, dec!(0));
     /// assert_eq!(account.total, dec!(0));
     /// ```
-    pub fn chargeback(&mut self, amount: Decimal) {
+    pub fn chargeback(&mut self, amount: Decimal) -> Result<(), TransactionError> {
         if amount > self.held {
-            return;
+            return Err(TransactionError::InsufficientFunds);
         }
 
-        self.held -= amount;
-        self.total -= amount;
+        let held = sub(self.held, amount)?;
+        self.total = sub(self.total, amount)?;
+        self.held = held;
         self.locked = true;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Dispute a withdrawal by crediting the withdrawn funds back as held,
+    /// pending the outcome of the dispute.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::Overflow` if the balances would overflow.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut account = Account::new(1);
+    /// account.deposit(dec!(1)).unwrap();
+    /// account.withdraw(dec!(1), dec!(0)).unwrap();
+    /// account.dispute_withdrawal(dec!(1)).unwrap();
+    ///
+    /// assert_eq!(account.available, dec!(0));
+    /// assert_eq!(account.held, dec!(1));
+    /// assert_eq!(account.total, dec!(1));
+    /// ```
+    pub fn dispute_withdrawal(&mut self, amount: Decimal) -> Result<(), TransactionError> {
+        let held = add(self.held, amount)?;
+        self.total = add(self.total, amount)?;
+        self.held = held;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Resolve a withdrawal dispute in favor of the withdrawal, releasing the
+    /// held funds as originally withdrawn.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::InsufficientFunds` if the held funds don't
+    /// cover the amount.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut account = Account::new(1);
+    /// account.dispute_withdrawal(dec!(1)).unwrap();
+    /// account.resolve_withdrawal(dec!(1)).unwrap();
+    ///
+    /// assert_eq!(account.held, dec!(0));
+    /// assert_eq!(account.total, dec!(0));
+    /// ```
+    pub fn resolve_withdrawal(&mut self, amount: Decimal) -> Result<(), TransactionError> {
+        if amount > self.held {
+            return Err(TransactionError::InsufficientFunds);
+        }
+
+        let held = sub(self.held, amount)?;
+        self.total = sub(self.total, amount)?;
+        self.held = held;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Resolve a withdrawal dispute by reversing the withdrawal, making the
+    /// held funds available again and locking the account.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::InsufficientFunds` if the held funds don't
+    /// cover the amount.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut account = Account::new(1);
+    /// account.dispute_withdrawal(dec!(1)).unwrap();
+    /// account.chargeback_withdrawal(dec!(1)).unwrap();
+    ///
+    /// assert_eq!(account.available, dec!(1));
+    /// assert_eq!(account.held, dec!(0));
+    /// assert!(account.locked);
+    /// ```
+    pub fn chargeback_withdrawal(&mut self, amount: Decimal) -> Result<(), TransactionError> {
+        if amount > self.held {
+            return Err(TransactionError::InsufficientFunds);
+        }
+
+        let held = sub(self.held, amount)?;
+        self.available = add(self.available, amount)?;
+        self.held = held;
+        self.locked = true;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Charge a fee by decreasing the available and total amounts, which can
+    /// go negative down to minus the credit limit, e.g. `Decimal::MAX` for
+    /// the fee of a chargeback.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::InsufficientFunds` if the available funds
+    /// along with the credit limit don't cover the fee.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use rust_decimal::Decimal;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut account = Account::new(1);
+    /// account.deposit(dec!(1)).unwrap();
+    /// account.charge_fee(dec!(0.5), dec!(0)).unwrap();
+    /// assert!(account.charge_fee(dec!(1), dec!(0)).is_err());
+    /// account.charge_fee(dec!(1), Decimal::MAX).unwrap();
+    ///
+    /// assert_eq!(account.total, dec!(-0.5));
+    /// assert_eq!(account.fees, dec!(1.5));
+    /// ```
+    pub fn charge_fee(
+        &mut self,
+        fee: Decimal,
+        credit_limit: Decimal,
+    ) -> Result<(), TransactionError> {
+        if fee > self.available.saturating_add(credit_limit) {
+            return Err(TransactionError::InsufficientFunds);
+        }
+
+        let available = sub(self.available, fee)?;
+        let total = sub(self.total, fee)?;
+        self.fees = add(self.fees, fee)?;
+        self.available = available;
+        self.total = total;
+        self.version += 1;
+        Ok(())
     }
 }
 
@@ -153,28 +527,116 @@ mod tests {
         let mut account = Account::new(1);
 
         // Deposit an integer amount
-        account.deposit(dec!(1));
+        account.deposit(dec!(1)).unwrap();
         assert_eq!(account.available, dec!(1));
         assert_eq!(account.total, dec!(1));
 
         // Deposit a decimal amount
-        account.deposit(dec!(0.0001));
+        account.deposit(dec!(0.0001)).unwrap();
         assert_eq!(account.available, dec!(1.0001));
         assert_eq!(account.total, dec!(1.0001));
     }
 
+    #[test]
+    fn test_high_precision() {
+        let mut account = Account::new(1);
+
+        // Deposit an amount with 18 decimal places, as used by token ledgers
+        account.deposit(dec!(1.000000000000000001)).unwrap();
+        assert_eq!(account.available, dec!(1.000000000000000001));
+        assert_eq!(account.total.scale(), 18);
+
+        // Withdraw the smallest unit and check nothing gets rounded away
+        account
+            .withdraw(dec!(0.000000000000000001), dec!(0))
+            .unwrap();
+        assert_eq!(account.available, dec!(1));
+        assert_eq!(account.total, dec!(1));
+
+        // Amounts up to the `rust_decimal` scale limit (28) are still exact
+        account.deposit(Decimal::new(1, 28)).unwrap();
+        assert_eq!(account.available.scale(), 28);
+    }
+
+    #[test]
+    fn test_version() {
+        let mut account = Account::new(1);
+        assert_eq!(account.version, 0);
+
+        // Every mutation bumps the version
+        account.deposit(dec!(2)).unwrap();
+        account.withdraw(dec!(1), dec!(0)).unwrap();
+        account.dispute(dec!(1)).unwrap();
+        assert_eq!(account.version, 3);
+
+        // Failed mutations leave it untouched
+        account.withdraw(dec!(1), dec!(0)).unwrap_err();
+        assert_eq!(account.version, 3);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let data = "client,available,held,locked\n1,1.5,0.5,true\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let account: Account = reader.deserialize().next().unwrap().unwrap();
+
+        // The total is derived from the other funds
+        assert_eq!(account.total, dec!(2));
+        assert!(account.locked);
+
+        // A stated total must be consistent
+        let data = "id,available,held,total,locked\n1,1.5,0.5,3,false\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        assert!(reader.deserialize::<Account>().next().unwrap().is_err());
+
+        // The last activity of the output can be read back
+        let data =
+            "id,available,held,total,locked,last_activity\n1,1,0,1,false,10\n2,1,0,1,false,\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let accounts: Vec<Account> = reader.deserialize().map(Result::unwrap).collect();
+        assert_eq!(accounts[0].last_activity, Some(10));
+        assert_eq!(accounts[1].last_activity, None);
+
+        // Seeds can state the credit limit of the account
+        let data = "client,available,held,credit_limit\n1,1,0,50\n2,1,0,\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let accounts: Vec<Account> = reader.deserialize().map(Result::unwrap).collect();
+        assert_eq!(accounts[0].credit_limit, Some(dec!(50)));
+        assert_eq!(accounts[1].credit_limit, None);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut account = Account::new(1);
+        account.deposit(dec!(1.5)).unwrap();
+        account.deposit(dec!(0.000001)).unwrap();
+
+        // Amounts have exactly four decimal places, rounded half to even
+        account.dispute(dec!(0.00005)).unwrap();
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&account).unwrap();
+        let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "id,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+        );
+    }
+
     #[test]
     fn test_withdraw() {
         let mut account = Account::new(1);
-        account.deposit(dec!(1));
+        account.deposit(dec!(1)).unwrap();
 
         // Try to withdraw an invalid amount
-        account.withdraw(dec!(2));
+        assert_eq!(
+            account.withdraw(dec!(2), dec!(0)),
+            Err(TransactionError::InsufficientFunds)
+        );
         assert_eq!(account.available, dec!(1));
         assert_eq!(account.total, dec!(1));
 
         // Withdraw a decimal amount
-        account.withdraw(dec!(0.5));
+        account.withdraw(dec!(0.5), dec!(0)).unwrap();
         assert_eq!(account.available, dec!(0.5));
         assert_eq!(account.total, dec!(0.5));
     }
@@ -182,16 +644,19 @@ mod tests {
     #[test]
     fn test_dispute() {
         let mut account = Account::new(1);
-        account.deposit(dec!(1));
+        account.deposit(dec!(1)).unwrap();
 
         // Try to dispute an invalid amount
-        account.dispute(dec!(2));
+        assert_eq!(
+            account.dispute(dec!(2)),
+            Err(TransactionError::InsufficientFunds)
+        );
         assert_eq!(account.available, dec!(1));
         assert_eq!(account.held, dec!(0));
         assert_eq!(account.total, dec!(1));
 
         // Dispute a valid amount
-        account.dispute(dec!(0.5));
+        account.dispute(dec!(0.5)).unwrap();
         assert_eq!(account.available, dec!(0.5));
         assert_eq!(account.held, dec!(0.5));
         assert_eq!(account.total, dec!(1));
@@ -200,22 +665,25 @@ mod tests {
     #[test]
     fn test_resolve() {
         let mut account = Account::new(1);
-        account.deposit(dec!(10));
+        account.deposit(dec!(10)).unwrap();
 
         // Dispute a valid amount
-        account.dispute(dec!(5));
+        account.dispute(dec!(5)).unwrap();
         assert_eq!(account.available, dec!(5));
         assert_eq!(account.held, dec!(5));
         assert_eq!(account.total, dec!(10));
 
         // Resolve a valid amount
-        account.resolve(dec!(5));
+        account.resolve(dec!(5)).unwrap();
         assert_eq!(account.available, dec!(10));
         assert_eq!(account.held, dec!(0));
         assert_eq!(account.total, dec!(10));
 
         // Try to resolve an invalid amount
-        account.resolve(dec!(10));
+        assert_eq!(
+            account.resolve(dec!(10)),
+            Err(TransactionError::InsufficientFunds)
+        );
         assert_eq!(account.available, dec!(10));
         assert_eq!(account.held, dec!(0));
         assert_eq!(account.total, dec!(10));
@@ -224,24 +692,81 @@ mod tests {
     #[test]
     fn test_chargeback() {
         let mut account = Account::new(1);
-        account.deposit(dec!(10));
+        account.deposit(dec!(10)).unwrap();
 
         // Dispute a valid amount
-        account.dispute(dec!(5));
+        account.dispute(dec!(5)).unwrap();
         assert_eq!(account.available, dec!(5));
         assert_eq!(account.held, dec!(5));
         assert_eq!(account.total, dec!(10));
 
         // Charge a valid amount back
-        account.chargeback(dec!(5));
+        account.chargeback(dec!(5)).unwrap();
         assert_eq!(account.available, dec!(5));
         assert_eq!(account.held, dec!(0));
         assert_eq!(account.total, dec!(5));
 
         // Try to charge an invalid amount back
-        account.chargeback(dec!(5));
+        assert_eq!(
+            account.chargeback(dec!(5)),
+            Err(TransactionError::InsufficientFunds)
+        );
         assert_eq!(account.available, dec!(5));
         assert_eq!(account.held, dec!(0));
         assert_eq!(account.total, dec!(5));
     }
+
+    #[test]
+    fn test_withdrawal_dispute() {
+        let mut account = Account::new(1);
+        account.deposit(dec!(10)).unwrap();
+        account.withdraw(dec!(4), dec!(0)).unwrap();
+
+        // Dispute the withdrawal, the funds are back but held
+        account.dispute_withdrawal(dec!(4)).unwrap();
+        assert_eq!(account.available, dec!(6));
+        assert_eq!(account.held, dec!(4));
+        assert_eq!(account.total, dec!(10));
+
+        // Try to resolve an invalid amount
+        assert_eq!(
+            account.resolve_withdrawal(dec!(5)),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        // Resolve in favor of the withdrawal
+        account.resolve_withdrawal(dec!(4)).unwrap();
+        assert_eq!(account.available, dec!(6));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(6));
+
+        // Dispute again and reverse the withdrawal
+        account.dispute_withdrawal(dec!(4)).unwrap();
+        account.chargeback_withdrawal(dec!(4)).unwrap();
+        assert_eq!(account.available, dec!(10));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(10));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_deficit() {
+        let mut account = Account::new(1);
+        account.deposit(dec!(10)).unwrap();
+        account.withdraw(dec!(8), dec!(0)).unwrap();
+        assert_eq!(account.deficit(), dec!(0));
+
+        // Charge the whole deposit back
+        account.force_dispute(dec!(10)).unwrap();
+        account.chargeback(dec!(10)).unwrap();
+        assert_eq!(account.available, dec!(-8));
+        assert_eq!(account.deficit(), dec!(8));
+
+        // Deposits offset the deficit first
+        account.deposit(dec!(5)).unwrap();
+        assert_eq!(account.deficit(), dec!(3));
+        account.deposit(dec!(5)).unwrap();
+        assert_eq!(account.deficit(), dec!(0));
+        assert_eq!(account.available, dec!(2));
+    }
 }
@@ -0,0 +1,309 @@
+//! Configurable output of the accounts, decoupled from their internal state.
+//!
+//! Reports have the columns of the spec by default, and may add the open
+//! disputes, the last transaction, the last activity and the currency of the
+//! accounts, see `PaymentsEngine::reports`:
+//!
+//! ```
+//! use payments::account_report::{AccountField, ReportFormat};
+//! use payments::payments_engine::PaymentsEngine;
+//! use payments::transaction::Transaction;
+//! use payments::transaction_kind::TransactionKind;
+//! use rust_decimal_macros::dec;
+//!
+//! let mut engine = PaymentsEngine::new();
+//! engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)))).unwrap();
+//! engine.execute(Transaction::new(TransactionKind::Dispute, 1, 1, None)).unwrap();
+//!
+//! let format = ReportFormat {
+//!     fields: vec![AccountField::Id, AccountField::Held, AccountField::Disputes],
+//!     decimals: 2,
+//!     ..ReportFormat::default()
+//! };
+//! let mut writer = csv::Writer::from_writer(vec![]);
+//! for report in engine.reports(engine.accounts_sorted(), &format) {
+//!     writer.serialize(report).unwrap();
+//! }
+//! let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+//! assert_eq!(csv, "id,held,disputes\n1,2.00,1\n");
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::{
+    account::Account,
+    engine_config::SPEC_DECIMALS,
+    ids::{ClientId, TxId},
+    money::{Currency, Locale, Money},
+};
+
+/// A column of the accounts report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccountField {
+    Id,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// The number of transactions of the client currently under dispute.
+    Disputes,
+    /// The id of the latest transaction applied to the account, see
+    /// `PaymentsEngine::last_transaction`.
+    LastTx,
+    /// The timestamp of the latest transaction applied to the account.
+    LastActivity,
+    /// The currency of the format, empty if it has none.
+    Currency,
+}
+
+impl AccountField {
+    pub const ALL: [Self; 9] = [
+        Self::Id,
+        Self::Available,
+        Self::Held,
+        Self::Total,
+        Self::Locked,
+        Self::Disputes,
+        Self::LastTx,
+        Self::LastActivity,
+        Self::Currency,
+    ];
+
+    /// The columns of the spec, the default ones.
+    pub const SPEC: [Self; 5] = [
+        Self::Id,
+        Self::Available,
+        Self::Held,
+        Self::Total,
+        Self::Locked,
+    ];
+
+    /// Get the name of the column.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Available => "available",
+            Self::Held => "held",
+            Self::Total => "total",
+            Self::Locked => "locked",
+            Self::Disputes => "disputes",
+            Self::LastTx => "last_tx",
+            Self::LastActivity => "last_activity",
+            Self::Currency => "currency",
+        }
+    }
+}
+
+impl FromStr for AccountField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|field| field.as_str() == s)
+            .ok_or_else(|| format!("Unknown account field: {s}"))
+    }
+}
+
+impl fmt::Display for AccountField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The columns of the accounts report and how its amounts are rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportFormat {
+    /// The columns of the report, in order. Without any, the accounts are
+    /// reported as empty records.
+    pub fields: Vec<AccountField>,
+    /// The decimal places of the amounts, more precise ones being rounded
+    /// half to even.
+    pub decimals: u32,
+    /// The currency of the accounts.
+    pub currency: Option<Currency>,
+    /// Render the amounts along with the currency with the conventions of
+    /// the locale instead of as plain decimals, padding them to the minor
+    /// units of the currency. Ignored without a currency.
+    pub locale: Option<Locale>,
+}
+
+impl Default for ReportFormat {
+    /// The format of the spec: its columns and amounts with `SPEC_DECIMALS`
+    /// decimal places.
+    fn default() -> Self {
+        Self {
+            fields: AccountField::SPEC.to_vec(),
+            decimals: SPEC_DECIMALS,
+            currency: None,
+            locale: None,
+        }
+    }
+}
+
+/// An account as serialized in the accounts report, only the columns of its
+/// format being part of the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountReport<'a, C = u16, T = u32> {
+    pub id: C,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub disputes: usize,
+    pub last_tx: Option<T>,
+    pub last_activity: Option<i64>,
+    pub format: &'a ReportFormat,
+}
+
+impl<'a, C: ClientId, T: TxId> AccountReport<'a, C, T> {
+    /// Report the balances of the account, along with neither disputes nor
+    /// last transaction.
+    #[must_use]
+    pub const fn new(account: &Account<C>, format: &'a ReportFormat) -> Self {
+        Self {
+            id: account.id,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            disputes: 0,
+            last_tx: None,
+            last_activity: account.last_activity,
+            format,
+        }
+    }
+
+    /// Serialize an amount as configured by the format.
+    fn amount<S: SerializeStruct>(
+        &self,
+        state: &mut S,
+        key: &'static str,
+        amount: Decimal,
+    ) -> Result<(), S::Error> {
+        if let (Some(currency), Some(locale)) = (&self.format.currency, self.format.locale) {
+            let money = Money::new(amount, currency.clone());
+            return state.serialize_field(key, &money.format(locale));
+        }
+
+        let mut amount = amount.round_dp(self.format.decimals);
+        if amount.scale() < self.format.decimals {
+            amount.rescale(self.format.decimals);
+        }
+        state.serialize_field(key, &amount)
+    }
+}
+
+impl<C: ClientId, T: TxId> Serialize for AccountReport<'_, C, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AccountReport", self.format.fields.len())?;
+        for &field in &self.format.fields {
+            let key = field.as_str();
+            match field {
+                AccountField::Id => state.serialize_field(key, &self.id)?,
+                AccountField::Available => self.amount(&mut state, key, self.available)?,
+                AccountField::Held => self.amount(&mut state, key, self.held)?,
+                AccountField::Total => self.amount(&mut state, key, self.total)?,
+                AccountField::Locked => state.serialize_field(key, &self.locked)?,
+                AccountField::Disputes => state.serialize_field(key, &self.disputes)?,
+                AccountField::LastTx => state.serialize_field(key, &self.last_tx)?,
+                AccountField::LastActivity => state.serialize_field(key, &self.last_activity)?,
+                AccountField::Currency => {
+                    let code = self.format.currency.as_ref().map(Currency::code);
+                    state.serialize_field(key, &code)?;
+                }
+            }
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        payments_engine::PaymentsEngine, transaction::Transaction,
+        transaction_kind::TransactionKind,
+    };
+
+    #[test]
+    fn test_reports() {
+        let mut engine = PaymentsEngine::new();
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1234.5))),
+            Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1))).with_timestamp(10),
+            Transaction::new(TransactionKind::Transfer, 1, 3, Some(dec!(0.5))).with_recipient(2),
+            Transaction::new(TransactionKind::Dispute, 1, 2, None),
+            Transaction::new(TransactionKind::AssertBalance, 2, 0, Some(dec!(0.5))),
+        ];
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        let write = |format: &ReportFormat| {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for report in engine.reports(engine.accounts_sorted(), format) {
+                writer.serialize(report).unwrap();
+            }
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+
+        // The default format is the one of the spec
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for account in engine.accounts_sorted() {
+            writer.serialize(account).unwrap();
+        }
+        let spec = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(write(&ReportFormat::default()), spec);
+
+        // Every column, the assertion doesn't count as the last transaction
+        let fields = AccountField::ALL.to_vec();
+        let currency = Some("EUR".parse().unwrap());
+        let format = ReportFormat { fields, currency, ..ReportFormat::default() };
+        assert_eq!(
+            write(&format),
+            "id,available,held,total,locked,disputes,last_tx,last_activity,currency\n\
+             1,1234.0000,1.0000,1235.0000,false,1,2,10,EUR\n\
+             2,0.5000,0.0000,0.5000,false,0,3,,EUR\n"
+        );
+
+        // Amounts rendered in a locale
+        let fields = vec![AccountField::Id, AccountField::Available];
+        let locale = Some(Locale::De);
+        let format = ReportFormat { fields, locale, ..format };
+        assert_eq!(
+            write(&format),
+            "id,available\n1,\"1.234,00 €\"\n2,\"0,50 €\"\n"
+        );
+
+        assert_eq!("last_tx".parse(), Ok(AccountField::LastTx));
+        assert_eq!(
+            "fees".parse::<AccountField>(),
+            Err("Unknown account field: fees".into())
+        );
+    }
+
+    #[test]
+    fn test_empty_report() {
+        let engine = PaymentsEngine::with_accounts([Account::new(1)]);
+        let format = ReportFormat { fields: Vec::new(), ..ReportFormat::default() };
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for report in engine.reports(engine.accounts_sorted(), &format) {
+            writer.serialize(report).unwrap();
+        }
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        // Each account still gets a row, without any column
+        assert_eq!(csv, "\"\"\n");
+        let report = engine
+            .reports(engine.accounts_sorted(), &format)
+            .next()
+            .unwrap();
+        assert_eq!(serde_json::to_string(&report).unwrap(), "{}");
+    }
+}
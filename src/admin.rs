@@ -0,0 +1,226 @@
+//! Administrative actions on the accounts, outside of any transaction.
+//!
+//! Actions are applied in batches by `PaymentsEngine::administer`, e.g. to
+//! lock every client involved in an incident at once: either every action of
+//! the batch is applied or none is, and the whole batch is audited by a single
+//! `AdminBatch` record.
+
+use std::{error::Error, fmt};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{ids::ClientId, transaction_error::TransactionError};
+
+/// An administrative action on an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AdminAction<C = u16> {
+    /// Lock the account, opening it if missing, e.g. to freeze a client under
+    /// investigation before any transaction.
+    Lock { client: C },
+    /// Unlock the account, missing accounts are left missing.
+    Unlock { client: C },
+    /// Credit the available funds of the account, or debit them if the amount
+    /// is negative, against the adjustments account of the ledger.
+    Adjust { client: C, amount: Decimal },
+}
+
+impl<C: ClientId> AdminAction<C> {
+    /// Get the client whose account the action applies to.
+    #[must_use]
+    pub const fn client(self) -> C {
+        match self {
+            Self::Lock { client } | Self::Unlock { client } | Self::Adjust { client, .. } => client,
+        }
+    }
+}
+
+/// The audit record of an applied batch, a single one whatever its size.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminBatch<C = u16> {
+    /// The clients of the actions, in ascending order.
+    pub clients: Vec<C>,
+    pub locks: usize,
+    pub unlocks: usize,
+    pub adjustments: usize,
+    /// The net amount of the adjustments.
+    pub adjusted: Decimal,
+}
+
+/// A batch rejected because one of its actions failed, leaving every account
+/// untouched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminError<C = u16> {
+    /// The position of the failed action in the batch.
+    pub index: usize,
+    pub client: C,
+    pub error: TransactionError,
+}
+
+impl<C: ClientId> fmt::Display for AdminError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "action {} on client {}: {}",
+            self.index, self.client, self.error
+        )
+    }
+}
+
+impl<C: ClientId> Error for AdminError<C> {}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        engine_config::EngineConfig, engine_event::EngineEvent, ledger::LedgerAccount,
+        payments_engine::PaymentsEngine, reconciliation, transaction::Transaction,
+        transaction_kind::TransactionKind,
+    };
+
+    #[test]
+    fn test_administer() {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                1,
+                1,
+                Some(dec!(5)),
+            ))
+            .unwrap();
+        let events = engine.subscribe();
+
+        // Missing accounts are opened to be locked
+        let batch = engine
+            .administer(&[
+                AdminAction::Lock { client: 2 },
+                AdminAction::Lock { client: 1 },
+                AdminAction::Unlock { client: 3 },
+            ])
+            .unwrap();
+        assert_eq!(batch.clients, vec![1, 2, 3]);
+        assert_eq!((batch.locks, batch.unlocks), (2, 1));
+        assert_eq!(engine.locked_accounts().count(), 2);
+        assert!(engine.account(3).is_none());
+
+        // A failed action rejects the whole batch
+        let error = engine
+            .administer(&[
+                AdminAction::Unlock { client: 1 },
+                AdminAction::Adjust { client: 1, amount: dec!(2) },
+                AdminAction::Adjust { client: 2, amount: dec!(-1) },
+            ])
+            .unwrap_err();
+        assert_eq!(
+            error,
+            AdminError {
+                index: 2,
+                client: 2,
+                error: TransactionError::InsufficientFunds,
+            }
+        );
+        let account = engine.account(1).unwrap();
+        assert_eq!((account.total, account.locked), (dec!(5), true));
+
+        // Adjustments are journaled against their own ledger account
+        let batch = engine
+            .administer(&[
+                AdminAction::Adjust { client: 1, amount: dec!(-1.5) },
+                AdminAction::Adjust { client: 2, amount: dec!(1) },
+            ])
+            .unwrap();
+        assert_eq!((batch.adjustments, batch.adjusted), (2, dec!(-0.5)));
+        assert_eq!(engine.account(1).unwrap().available, dec!(3.5));
+        assert_eq!(
+            engine.ledger().balance(LedgerAccount::Adjustments),
+            dec!(0.5)
+        );
+        assert!(reconciliation::reconcile(&engine).is_empty());
+
+        // Each applied batch is notified once
+        let batches: Vec<_> = events
+            .try_iter()
+            .filter(|event| matches!(event, EngineEvent::AdminBatchApplied(_)))
+            .collect();
+        assert_eq!(batches.len(), 2);
+    }
+
+    #[test]
+    fn test_administer_errors() {
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig { contain_panics: true, ..EngineConfig::default() });
+        engine.add_listener(|event: &EngineEvent| {
+            let panics = matches!(event, EngineEvent::DepositApplied { client: 3, .. });
+            assert!(!panics, "injected panic");
+        });
+        engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                1,
+                1,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+
+        // Unknown clients can't be debited, nor are their accounts opened
+        let error = engine
+            .administer(&[AdminAction::Adjust { client: 2, amount: dec!(-1) }])
+            .unwrap_err();
+        assert_eq!(error.error, TransactionError::InsufficientFunds);
+        assert!(engine.account(2).is_none());
+
+        // Locked accounts are still adjusted, locking them again changes nothing
+        engine
+            .administer(&[AdminAction::Lock { client: 1 }])
+            .unwrap();
+        let version = engine.account(1).unwrap().version;
+        let batch = engine
+            .administer(&[
+                AdminAction::Lock { client: 1 },
+                AdminAction::Adjust { client: 1, amount: dec!(2) },
+            ])
+            .unwrap();
+        assert_eq!((batch.locks, batch.adjustments), (1, 1));
+        let account = engine.account(1).unwrap();
+        assert_eq!((account.available, account.locked), (dec!(3), true));
+        assert_eq!(account.version, version + 1);
+
+        // Overflowing adjustments are rejected
+        let error = engine
+            .administer(&[AdminAction::Adjust { client: 1, amount: Decimal::MAX }])
+            .unwrap_err();
+        assert_eq!(error.error, TransactionError::Overflow);
+        assert_eq!(engine.account(1).unwrap().available, dec!(3));
+
+        // Deleted and quarantined clients are left alone
+        engine.delete_account(1);
+        let error = engine
+            .administer(&[AdminAction::Unlock { client: 1 }])
+            .unwrap_err();
+        assert_eq!(error.error, TransactionError::AccountDeleted);
+        assert!(engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                3,
+                2,
+                Some(dec!(1))
+            ))
+            .is_err());
+        let error = engine
+            .administer(&[AdminAction::Lock { client: 3 }])
+            .unwrap_err();
+        assert_eq!(
+            error,
+            AdminError {
+                index: 0,
+                client: 3,
+                error: TransactionError::ClientQuarantined,
+            }
+        );
+        assert!(!engine.account(3).is_some_and(|account| account.locked));
+    }
+}
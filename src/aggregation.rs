@@ -0,0 +1,133 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+
+use crate::account::Account;
+
+/// Buckets counting less accounts than this are suppressed by default.
+pub const DEFAULT_MIN_COUNT: usize = 10;
+
+/// The statistics of the accounts whose total falls within a bucket. The
+/// lower bound is inclusive, the upper one exclusive, and a missing bound is
+/// unbounded.
+///
+/// The count and the sum of the totals are missing when the bucket counts
+/// too few accounts to be released without exposing individual clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    pub lower: Option<Decimal>,
+    pub upper: Option<Decimal>,
+    pub accounts: Option<usize>,
+    pub total: Option<Decimal>,
+}
+
+/// Get the default bucket bounds, by order of magnitude.
+#[must_use]
+pub fn default_bounds() -> Vec<Decimal> {
+    vec![dec!(0), dec!(100), dec!(1000), dec!(10000), dec!(100000)]
+}
+
+/// Aggregate the totals of the accounts into the buckets delimited by the
+/// ascending bounds, suppressing the buckets with less than `min_count`
+/// accounts. Empty buckets are never suppressed, as there is nothing to
+/// expose, and the totals saturate at the bounds of the amounts.
+///
+/// # Example
+/// ```
+/// use payments::aggregation;
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let mut engine = PaymentsEngine::new();
+/// for client in 1..=3 {
+///     let tx = Transaction::new(TransactionKind::Deposit, client, client.into(), Some(dec!(1)));
+///     engine.execute(tx).unwrap();
+/// }
+///
+/// let buckets = aggregation::buckets(engine.accounts_sorted(), &[dec!(0), dec!(10)], 2);
+/// assert_eq!(buckets[1].accounts, Some(3));
+/// assert_eq!(buckets[1].total, Some(dec!(3)));
+///
+/// let buckets = aggregation::buckets(engine.accounts_sorted(), &[dec!(0), dec!(10)], 4);
+/// assert!(buckets[1].accounts.is_none());
+/// ```
+#[must_use]
+pub fn buckets<'a>(
+    accounts: impl IntoIterator<Item = &'a Account>,
+    bounds: &[Decimal],
+    min_count: usize,
+) -> Vec<Bucket> {
+    let mut buckets: Vec<_> = iter_bounds(bounds)
+        .map(|(lower, upper)| Bucket {
+            lower,
+            upper,
+            accounts: Some(0),
+            total: Some(Decimal::ZERO),
+        })
+        .collect();
+
+    for account in accounts {
+        let index = bounds.partition_point(|bound| *bound <= account.total);
+        let bucket = &mut buckets[index];
+        bucket.accounts = bucket.accounts.map(|count| count + 1);
+        bucket.total = bucket
+            .total
+            .map(|total| total.saturating_add(account.total));
+    }
+
+    for bucket in &mut buckets {
+        if bucket
+            .accounts
+            .is_some_and(|count| count > 0 && count < min_count)
+        {
+            bucket.accounts = None;
+            bucket.total = None;
+        }
+    }
+
+    buckets
+}
+
+/// Iterate over the lower and upper bounds of every bucket, from the
+/// unbounded one below the first bound to the unbounded one above the last.
+fn iter_bounds(
+    bounds: &[Decimal],
+) -> impl Iterator<Item = (Option<Decimal>, Option<Decimal>)> + '_ {
+    let lowers = std::iter::once(None).chain(bounds.iter().copied().map(Some));
+    let uppers = bounds
+        .iter()
+        .copied()
+        .map(Some)
+        .chain(std::iter::once(None));
+    lowers.zip(uppers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        // Create a single account below zero and two within the first bucket
+        let accounts: Vec<_> = [dec!(-1), dec!(5), dec!(5)]
+            .into_iter()
+            .zip(1..)
+            .map(|(total, id)| {
+                let mut account = Account::new(id);
+                account.total = total;
+                account
+            })
+            .collect();
+
+        // The lonely negative balance is suppressed, the upper bound is
+        // exclusive
+        let buckets = buckets(&accounts, &[dec!(0), dec!(5), dec!(10)], 2);
+        let counts: Vec<_> = buckets.iter().map(|bucket| bucket.accounts).collect();
+        assert_eq!(counts, vec![None, Some(0), Some(2), Some(0)]);
+        assert_eq!(buckets[0].total, None);
+        assert_eq!(buckets[2].total, Some(dec!(10)));
+        assert_eq!((buckets[3].lower, buckets[3].upper), (Some(dec!(10)), None));
+    }
+}
@@ -0,0 +1,494 @@
+use std::{path::PathBuf, str::FromStr};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    account::Account,
+    admin::{AdminAction, AdminBatch, AdminError},
+    payments_engine::PaymentsEngine,
+    transaction::Transaction,
+    transaction_error::TransactionError,
+};
+
+/// How many interactive requests are served for each backfill one, when both
+/// are waiting.
+pub const INTERACTIVE_WEIGHT: usize = 8;
+
+/// The capacity of each request queue, senders waiting once it's reached.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The priority class of the requests sent through a handle, see
+/// `AsyncPaymentsEngine::with_priority`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    /// Requests waiting on a reply, e.g. API traffic, this is the default.
+    #[default]
+    Interactive,
+    /// Bulk requests using the spare capacity, e.g. backfill streams.
+    Backfill,
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interactive" => Ok(Self::Interactive),
+            "backfill" => Ok(Self::Backfill),
+            _ => Err(format!("Unknown priority: {s}")),
+        }
+    }
+}
+
+/// Requests sent to the task owning the engine.
+enum Command {
+    Execute(Transaction, oneshot::Sender<Result<(), TransactionError>>),
+    Simulate(
+        Transaction,
+        oneshot::Sender<Result<Vec<Account>, TransactionError>>,
+    ),
+    Account(u16, oneshot::Sender<Option<Account>>),
+    Accounts(oneshot::Sender<Vec<Account>>),
+    Tag(u16, String, oneshot::Sender<Result<bool, TransactionError>>),
+    Untag(u16, String, oneshot::Sender<Result<bool, TransactionError>>),
+    Tags(u16, oneshot::Sender<Vec<String>>),
+    Administer(
+        Vec<AdminAction>,
+        oneshot::Sender<Result<AdminBatch, AdminError>>,
+    ),
+    Stop(oneshot::Sender<PaymentsEngine>),
+}
+
+/// An asynchronous handle to a `PaymentsEngine` running on its own Tokio task,
+/// so that transactions coming from async sources (sockets, queues, HTTP) can
+/// be fed without blocking. Handles can be cloned and shared across tasks,
+/// transactions are executed in the order they are received.
+///
+/// Requests are queued by priority class: backfill requests are served when
+/// no interactive one is waiting, and once every `INTERACTIVE_WEIGHT`
+/// interactive ones otherwise, so that a backfill can saturate the engine
+/// without delaying interactive requests nor being starved by them. Each
+/// queue has its own capacity, a full backfill queue never blocks interactive
+/// senders. The order is only kept within a class.
+///
+/// # Example
+/// ```
+/// use payments::async_engine::AsyncPaymentsEngine;
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+/// let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+///
+/// engine.execute(tx).await.unwrap();
+/// assert_eq!(engine.account(1).await.unwrap().available, dec!(1));
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct AsyncPaymentsEngine {
+    interactive: mpsc::Sender<Command>,
+    backfill: mpsc::Sender<Command>,
+    priority: Priority,
+}
+
+impl AsyncPaymentsEngine {
+    /// Move the engine to a new task, must be called within a Tokio runtime.
+    #[must_use]
+    pub fn spawn(engine: PaymentsEngine) -> Self {
+        Self::spawn_with(engine, None)
+    }
+
+    /// Move the engine to a new task like `AsyncPaymentsEngine::spawn`, saving
+    /// it to the path after every applied transaction, tag change and
+    /// administrative batch before replying, so that no acknowledged change
+    /// is lost if the process crashes. Each save writes the whole state, see
+    /// `PaymentsEngine::save`.
+    ///
+    /// Changes which can't be saved are answered with
+    /// `TransactionError::StateNotSaved`. They are kept in memory nonetheless,
+    /// and saved along with the next change.
+    #[must_use]
+    pub fn spawn_persistent(engine: PaymentsEngine, path: impl Into<PathBuf>) -> Self {
+        Self::spawn_with(engine, Some(path.into()))
+    }
+
+    fn spawn_with(mut engine: PaymentsEngine, path: Option<PathBuf>) -> Self {
+        let (interactive, mut interactive_receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let (backfill, mut backfill_receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let persist = move |engine: &PaymentsEngine| match &path {
+            Some(path) => engine
+                .save(path)
+                .map_err(|error| TransactionError::StateNotSaved { reason: error.to_string() }),
+            None => Ok(()),
+        };
+
+        tokio::spawn(async move {
+            let mut served = 0;
+            while let Some(command) = next_command(
+                &mut interactive_receiver,
+                &mut backfill_receiver,
+                &mut served,
+            )
+            .await
+            {
+                match command {
+                    Command::Execute(tx, reply) => {
+                        let result = engine.execute(tx).and_then(|()| persist(&engine));
+                        let _ = reply.send(result);
+                    }
+                    Command::Simulate(tx, reply) => {
+                        let _ = reply.send(engine.simulate(&tx));
+                    }
+                    Command::Account(id, reply) => {
+                        let _ = reply.send(engine.account(id).cloned());
+                    }
+                    Command::Accounts(reply) => {
+                        let _ = reply.send(engine.accounts_sorted().cloned().collect());
+                    }
+                    Command::Tag(id, tag, reply) => {
+                        let tagged = engine.tag_account(id, tag);
+                        let _ = reply.send(saved(tagged, &persist, &engine));
+                    }
+                    Command::Untag(id, tag, reply) => {
+                        let untagged = engine.untag_account(id, &tag);
+                        let _ = reply.send(saved(untagged, &persist, &engine));
+                    }
+                    Command::Tags(id, reply) => {
+                        let _ = reply.send(engine.account_tags(id).map(Into::into).collect());
+                    }
+                    Command::Administer(actions, reply) => {
+                        let result = engine.administer(&actions).and_then(|batch| {
+                            match (actions.last(), persist(&engine)) {
+                                (Some(action), Err(error)) => Err(AdminError {
+                                    index: actions.len(),
+                                    client: action.client(),
+                                    error,
+                                }),
+                                _ => Ok(batch),
+                            }
+                        });
+                        let _ = reply.send(result);
+                    }
+                    Command::Stop(reply) => {
+                        let _ = reply.send(engine);
+                        return;
+                    }
+                }
+            }
+        });
+
+        Self {
+            interactive,
+            backfill,
+            priority: Priority::Interactive,
+        }
+    }
+
+    /// Get a handle sending its requests with the given priority, e.g. for a
+    /// backfill job.
+    #[must_use]
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self { priority, ..self.clone() }
+    }
+
+    /// Execute the transaction, see `PaymentsEngine::execute`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason for which the transaction was rejected, or
+    /// `TransactionError::StateNotSaved` if it was applied but the state
+    /// couldn't be saved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn execute(&self, tx: Transaction) -> Result<(), TransactionError> {
+        self.request(|reply| Command::Execute(tx, reply)).await
+    }
+
+    /// Evaluate the transaction without applying it, see
+    /// `PaymentsEngine::simulate`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason for which the transaction would be rejected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn simulate(&self, tx: Transaction) -> Result<Vec<Account>, TransactionError> {
+        self.request(|reply| Command::Simulate(tx, reply)).await
+    }
+
+    /// Get a copy of the current state of an account.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn account(&self, id: u16) -> Option<Account> {
+        self.request(|reply| Command::Account(id, reply)).await
+    }
+
+    /// Get a copy of the current state of every account, in ascending id
+    /// order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn accounts(&self) -> Vec<Account> {
+        self.request(Command::Accounts).await
+    }
+
+    /// Attach a tag to an account, see `PaymentsEngine::tag_account`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::StateNotSaved` if the tag was added but the
+    /// state couldn't be saved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn tag_account(
+        &self,
+        id: u16,
+        tag: impl Into<String>,
+    ) -> Result<bool, TransactionError> {
+        let tag = tag.into();
+        self.request(|reply| Command::Tag(id, tag, reply)).await
+    }
+
+    /// Remove a tag from an account, see `PaymentsEngine::untag_account`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::StateNotSaved` if the tag was removed but
+    /// the state couldn't be saved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn untag_account(
+        &self,
+        id: u16,
+        tag: impl Into<String>,
+    ) -> Result<bool, TransactionError> {
+        let tag = tag.into();
+        self.request(|reply| Command::Untag(id, tag, reply)).await
+    }
+
+    /// Get the tags of an account, in alphabetical order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn account_tags(&self, id: u16) -> Vec<String> {
+        self.request(|reply| Command::Tags(id, reply)).await
+    }
+
+    /// Apply a batch of administrative actions, see
+    /// `PaymentsEngine::administer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first failed action, in which case none was applied. If
+    /// the batch was applied but the state couldn't be saved, the error is
+    /// `TransactionError::StateNotSaved` and its index the size of the batch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn administer(&self, actions: Vec<AdminAction>) -> Result<AdminBatch, AdminError> {
+        self.request(|reply| Command::Administer(actions, reply))
+            .await
+    }
+
+    /// Stop the engine task once the pending transactions are executed, and
+    /// get the engine back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine task is no longer running.
+    pub async fn stop(self) -> PaymentsEngine {
+        self.request(Command::Stop).await
+    }
+
+    /// Send a command to the engine task and wait for its reply.
+    // Using a handle once the task has stopped is a misuse, documented as a
+    // panic on every request
+    #[allow(clippy::expect_used)]
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> T {
+        let (reply, response) = oneshot::channel();
+        let sender = match self.priority {
+            Priority::Interactive => &self.interactive,
+            Priority::Backfill => &self.backfill,
+        };
+        let sent = sender.send(command(reply)).await;
+        assert!(sent.is_ok(), "engine task stopped");
+        response.await.expect("engine task stopped")
+    }
+}
+
+/// Save the state of a persistent engine if the tags were changed.
+fn saved(
+    changed: bool,
+    persist: impl Fn(&PaymentsEngine) -> Result<(), TransactionError>,
+    engine: &PaymentsEngine,
+) -> Result<bool, TransactionError> {
+    if changed {
+        persist(engine)?;
+    }
+    Ok(changed)
+}
+
+/// Wait for the next command, favoring the interactive ones, see
+/// `INTERACTIVE_WEIGHT`. `served` counts the interactive commands served since
+/// the last backfill one.
+async fn next_command(
+    interactive: &mut mpsc::Receiver<Command>,
+    backfill: &mut mpsc::Receiver<Command>,
+    served: &mut usize,
+) -> Option<Command> {
+    if *served >= INTERACTIVE_WEIGHT {
+        if let Ok(command) = backfill.try_recv() {
+            *served = 0;
+            return Some(command);
+        }
+    }
+
+    tokio::select! {
+        biased;
+        Some(command) = interactive.recv() => {
+            *served += 1;
+            Some(command)
+        }
+        Some(command) = backfill.recv() => {
+            *served = 0;
+            Some(command)
+        }
+        else => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction_kind::TransactionKind;
+
+    #[tokio::test]
+    async fn test_concurrent_sources() {
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+
+        // Feed deposits from several tasks at once
+        let tasks = (0..10).map(|id| {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                let tx = Transaction::new(TransactionKind::Deposit, 1, id, Some(dec!(1)));
+                engine.execute(tx).await
+            })
+        });
+        for task in tasks.collect::<Vec<_>>() {
+            task.await.unwrap().unwrap();
+        }
+
+        // Rejections are reported back to the caller
+        let tx = Transaction::new(TransactionKind::Withdrawal, 1, 10, Some(dec!(11)));
+        assert_eq!(
+            engine.execute(tx).await,
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        // The engine can be retrieved once done
+        let engine = engine.stop().await;
+        assert_eq!(engine.account(1).unwrap().total, dec!(10));
+    }
+
+    #[tokio::test]
+    async fn test_priorities() {
+        let (interactive, mut interactive_receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let (backfill, mut backfill_receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let command = |id| Command::Account(id, oneshot::channel().0);
+        for id in 0..20 {
+            interactive.try_send(command(id)).unwrap();
+        }
+        for id in 100..103 {
+            backfill.try_send(command(id)).unwrap();
+        }
+        drop((interactive, backfill));
+
+        // Backfills take a slot after every weight of interactive commands,
+        // then the spare capacity
+        let mut order = Vec::new();
+        let mut served = 0;
+        while let Some(command) = next_command(
+            &mut interactive_receiver,
+            &mut backfill_receiver,
+            &mut served,
+        )
+        .await
+        {
+            if let Command::Account(id, _) = command {
+                order.push(id);
+            }
+        }
+        let backfills: Vec<_> = order
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| **id >= 100)
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(
+            backfills,
+            vec![INTERACTIVE_WEIGHT, 2 * INTERACTIVE_WEIGHT + 1, 22]
+        );
+
+        // Handles of any priority reach the same engine
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+        let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let backfill = engine.with_priority(Priority::Backfill);
+        backfill.execute(tx).await.unwrap();
+        assert_eq!(engine.account(1).await.unwrap().total, dec!(1));
+    }
+
+    #[tokio::test]
+    async fn test_persistent() {
+        let dir = std::env::temp_dir().join(format!("payments-persistent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+        let engine = AsyncPaymentsEngine::spawn_persistent(PaymentsEngine::new(), &path);
+
+        // Changes are saved before being acknowledged
+        let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        engine.execute(tx).await.unwrap();
+        assert_eq!(engine.tag_account(1, "vip").await, Ok(true));
+        let saved = PaymentsEngine::load(&path).unwrap();
+        assert_eq!(saved.account(1).unwrap().total, dec!(1));
+        assert_eq!(saved.account_tags(1).collect::<Vec<_>>(), vec!["vip"]);
+
+        // Rejected transactions and unchanged tags aren't saved
+        std::fs::remove_file(&path).unwrap();
+        let tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(2)));
+        assert!(engine.execute(tx).await.is_err());
+        assert_eq!(engine.untag_account(1, "new").await, Ok(false));
+        assert!(!path.exists());
+
+        // Failed saves are reported to the caller, the engine keeps serving
+        std::fs::remove_dir_all(&dir).unwrap();
+        let tx = Transaction::new(TransactionKind::Deposit, 1, 3, Some(dec!(1)));
+        let error = engine.execute(tx).await.unwrap_err();
+        assert_eq!(error.code(), "PE021");
+        assert!(matches!(
+            engine.untag_account(1, "vip").await,
+            Err(TransactionError::StateNotSaved { .. })
+        ));
+        let actions = vec![AdminAction::Lock { client: 1 }];
+        let error = engine.administer(actions).await.unwrap_err();
+        assert_eq!((error.index, error.error.code()), (1, "PE021"));
+        assert_eq!(engine.account(1).await.unwrap().total, dec!(2));
+    }
+}
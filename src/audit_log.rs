@@ -0,0 +1,139 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    account::Account,
+    ids::{ClientId, TxId},
+    transaction::Transaction,
+    transaction_kind::TransactionKind,
+};
+
+/// The balances of an account before and after a transaction altered them,
+/// missing accounts having no funds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditEntry<C = u16, T = u32> {
+    pub client: C,
+    pub tx: T,
+    pub kind: TransactionKind,
+    pub available_before: Decimal,
+    pub held_before: Decimal,
+    pub total_before: Decimal,
+    pub available_after: Decimal,
+    pub held_after: Decimal,
+    pub total_after: Decimal,
+    /// The metadata of the transaction, passed through as is.
+    pub reference: Option<String>,
+    pub memo: Option<String>,
+}
+
+impl<C: ClientId, T: TxId> AuditEntry<C, T> {
+    /// Compare the account before and after the transaction, getting an
+    /// entry only if any of its balances changed.
+    #[must_use]
+    pub fn new(
+        tx: T,
+        kind: TransactionKind,
+        before: &Account<C>,
+        after: &Account<C>,
+    ) -> Option<Self> {
+        let unchanged = before.available == after.available
+            && before.held == after.held
+            && before.total == after.total;
+        (!unchanged).then_some(Self {
+            client: after.id,
+            tx,
+            kind,
+            available_before: before.available,
+            held_before: before.held,
+            total_before: before.total,
+            available_after: after.available,
+            held_after: after.held,
+            total_after: after.total,
+            reference: None,
+            memo: None,
+        })
+    }
+
+    /// Attach the reference and the memo of the transaction to the entry.
+    #[must_use]
+    pub fn with_metadata(self, tx: &Transaction<C, T>) -> Self {
+        Self {
+            reference: tx.reference.clone(),
+            memo: tx.memo.clone(),
+            ..self
+        }
+    }
+}
+
+/// A sink recording every balance mutation of the `PaymentsEngine`, one
+/// entry per altered account, e.g. both sides of a transfer. Unlike the
+/// listeners, the audit log is still fed when backfilling. Administrative
+/// batches aren't transactions, they're audited by their `AdminBatch` record
+/// instead.
+///
+/// Any `FnMut(&AuditEntry)` closure is an audit log too, which makes it easy
+/// to forward entries to a channel or any other sink.
+pub trait AuditLog<C = u16, T = u32>: Send {
+    fn record(&mut self, entry: &AuditEntry<C, T>);
+}
+
+impl<C, T, F: FnMut(&AuditEntry<C, T>) + Send> AuditLog<C, T> for F {
+    fn record(&mut self, entry: &AuditEntry<C, T>) {
+        self(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::payments_engine::PaymentsEngine;
+
+    #[test]
+    fn test_audit_log() {
+        let mut engine = PaymentsEngine::new();
+        let entries = engine.audit();
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2))).with_memo("payroll"),
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(1))).with_recipient(2),
+            Transaction::new(TransactionKind::AssertBalance, 1, 3, Some(dec!(1))),
+            Transaction::new(TransactionKind::Withdrawal, 1, 4, Some(dec!(5))),
+            Transaction::new(TransactionKind::Deposit, 1, 5, Some(dec!(1))),
+            Transaction::new(TransactionKind::Dispute, 1, 5, None).with_reference("CASE-1"),
+        ];
+        for tx in txs {
+            let _ = engine.execute(tx);
+        }
+
+        // Assertions and rejected transactions don't alter the balances
+        let entries: Vec<_> = entries.try_iter().collect();
+        let trail: Vec<_> = entries.iter().map(|e| (e.tx, e.client)).collect();
+        assert_eq!(trail, vec![(1, 1), (2, 1), (2, 2), (5, 1), (5, 1)]);
+        assert_eq!(
+            entries[0],
+            AuditEntry {
+                client: 1,
+                tx: 1,
+                kind: TransactionKind::Deposit,
+                available_before: dec!(0),
+                held_before: dec!(0),
+                total_before: dec!(0),
+                available_after: dec!(2),
+                held_after: dec!(0),
+                total_after: dec!(2),
+                reference: None,
+                memo: Some("payroll".into()),
+            }
+        );
+        assert_eq!(
+            (entries[2].total_before, entries[2].total_after),
+            (dec!(0), dec!(1))
+        );
+        assert_eq!(
+            (entries[4].held_before, entries[4].held_after),
+            (dec!(0), dec!(1))
+        );
+        assert_eq!(entries[4].reference.as_deref(), Some("CASE-1"));
+    }
+}
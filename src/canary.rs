@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use crate::account::Account;
+
+/// A column of an account differing between the baseline and the candidate
+/// builds, a missing value meaning that the build has no such account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub client: u16,
+    pub column: &'static str,
+    pub baseline: Option<String>,
+    pub candidate: Option<String>,
+}
+
+/// Compare the accounts output by two builds for the same input, returning
+/// the differing columns by client. Versions and activity timestamps are not
+/// part of the output, and are ignored.
+///
+/// # Example
+/// ```
+/// use payments::account::Account;
+/// use payments::canary;
+///
+/// let baseline = [Account::new(1)];
+/// let mut candidate = [Account::new(1), Account::new(2)];
+/// candidate[0].locked = true;
+///
+/// let differences = canary::compare(&baseline, &candidate);
+/// assert_eq!(differences[0].column, "locked");
+/// assert_eq!(differences[0].candidate.as_deref(), Some("true"));
+/// assert!(differences[1..].iter().all(|d| d.client == 2 && d.baseline.is_none()));
+/// ```
+#[must_use]
+pub fn compare<'a>(
+    baseline: impl IntoIterator<Item = &'a Account>,
+    candidate: impl IntoIterator<Item = &'a Account>,
+) -> Vec<Difference> {
+    let mut clients: BTreeMap<u16, (Option<&Account>, Option<&Account>)> = BTreeMap::new();
+    for account in baseline {
+        clients.entry(account.id).or_default().0 = Some(account);
+    }
+    for account in candidate {
+        clients.entry(account.id).or_default().1 = Some(account);
+    }
+
+    let mut differences = Vec::new();
+    for (client, (baseline, candidate)) in clients {
+        let baseline = baseline.map(columns);
+        let candidate = candidate.map(columns);
+        for (i, column) in COLUMNS.into_iter().enumerate() {
+            let baseline = baseline.as_ref().map(|values| values[i].clone());
+            let candidate = candidate.as_ref().map(|values| values[i].clone());
+            if baseline != candidate {
+                differences.push(Difference { client, column, baseline, candidate });
+            }
+        }
+    }
+
+    differences
+}
+
+/// The compared columns of the accounts output.
+const COLUMNS: [&str; 4] = ["available", "held", "total", "locked"];
+
+/// Get the values of the compared columns, normalized so that the scale of
+/// the amounts doesn't matter.
+fn columns(account: &Account) -> [String; 4] {
+    [
+        account.available.normalize().to_string(),
+        account.held.normalize().to_string(),
+        account.total.normalize().to_string(),
+        account.locked.to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let mut baseline = Account::new(1);
+        baseline.available = dec!(1.5000);
+        baseline.total = dec!(1.5000);
+        baseline.version = 3;
+
+        // Neither the scale nor the version are differences
+        let mut candidate = baseline.clone();
+        candidate.available = dec!(1.5);
+        candidate.version = 4;
+        assert!(compare([&baseline], [&candidate]).is_empty());
+
+        candidate.held = dec!(1);
+        candidate.total = dec!(2.5);
+        let differences = compare([&baseline], [&candidate]);
+        let columns: Vec<_> = differences.iter().map(|d| d.column).collect();
+        assert_eq!(columns, vec!["held", "total"]);
+        assert_eq!(differences[1].baseline.as_deref(), Some("1.5"));
+        assert_eq!(differences[1].candidate.as_deref(), Some("2.5"));
+
+        // Every column of a missing account differs
+        let differences = compare([&baseline], []);
+        assert_eq!(differences.len(), COLUMNS.len());
+        assert!(differences.iter().all(|d| d.candidate.is_none()));
+    }
+}
@@ -0,0 +1,233 @@
+use std::{error::Error, fmt, io};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    transaction::Transaction, transaction_error::TransactionError,
+    transaction_kind::TransactionKind,
+};
+
+/// Writes transaction streams in the format read by the engine, so that
+/// producer services don't have to replicate the schema.
+///
+/// Transactions are validated before being written, rows which the engine
+/// would reject regardless of the account state are refused.
+///
+/// # Example
+/// ```
+/// use payments::client::TransactionWriter;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let mut writer = TransactionWriter::csv(Vec::new());
+/// let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1.5)));
+/// writer.write(&tx).unwrap();
+///
+/// let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+/// assert_eq!(csv, "type,client,tx,amount,to_client,group,reference,timestamp,memo\ndeposit,1,1,1.5,,,,,\n");
+/// ```
+pub struct TransactionWriter<W: io::Write> {
+    inner: Inner<W>,
+}
+
+enum Inner<W: io::Write> {
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(W),
+}
+
+impl<W: io::Write> TransactionWriter<W> {
+    /// Write a CSV file with headers, as read from the command line.
+    #[must_use]
+    pub fn csv(writer: W) -> Self {
+        Self {
+            inner: Inner::Csv(Box::new(csv::Writer::from_writer(writer))),
+        }
+    }
+
+    /// Write a JSON object per line, as accepted by the REST server.
+    #[must_use]
+    pub const fn jsonl(writer: W) -> Self {
+        Self { inner: Inner::Jsonl(writer) }
+    }
+
+    /// Validate and write a transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WriteError::Invalid` if the transaction is malformed, in which
+    /// case nothing is written, or `WriteError::Io` if writing fails.
+    pub fn write(&mut self, tx: &Transaction) -> Result<(), WriteError> {
+        validate(tx).map_err(WriteError::Invalid)?;
+
+        match &mut self.inner {
+            Inner::Csv(writer) => writer.serialize(tx).map_err(io::Error::from)?,
+            Inner::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, tx).map_err(io::Error::from)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flush the buffered transactions to the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Inner::Csv(writer) => writer.flush(),
+            Inner::Jsonl(writer) => writer.flush(),
+        }
+    }
+
+    /// Flush the buffered transactions and get the underlying writer back.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.inner {
+            Inner::Csv(writer) => writer
+                .into_inner()
+                .map_err(|e| io::Error::new(e.error().kind(), e.error().to_string())),
+            Inner::Jsonl(mut writer) => writer.flush().map(|()| writer),
+        }
+    }
+}
+
+/// Reasons for which a `TransactionWriter` can fail.
+#[derive(Debug)]
+pub enum WriteError {
+    /// The transaction would be rejected by the engine.
+    Invalid(TransactionError),
+    /// The underlying writer failed.
+    Io(io::Error),
+}
+
+impl From<io::Error> for WriteError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(error) => write!(f, "invalid transaction: {error}"),
+            Self::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for WriteError {}
+
+/// Check the fields required by the transaction type and the sign of the
+/// amounts, the same way the engine does before looking at the accounts.
+///
+/// # Errors
+///
+/// Returns the reason for which the engine would reject the transaction.
+pub fn validate(tx: &Transaction) -> Result<(), TransactionError> {
+    use TransactionKind::{AssertBalance, Deposit, Transfer, Withdrawal};
+
+    // Only deposits and withdrawals can be part of a group
+    if tx.group.is_some() && !matches!(tx.kind, Deposit | Withdrawal) {
+        return Err(TransactionError::InvalidGroup);
+    }
+
+    match tx.kind {
+        Deposit | Withdrawal | Transfer | AssertBalance if tx.amount.is_none() => {
+            Err(TransactionError::MissingAmount)
+        }
+        Deposit | Withdrawal | Transfer if tx.amount.is_some_and(|a| a <= Decimal::ZERO) => {
+            Err(TransactionError::NonPositiveAmount)
+        }
+        Transfer if tx.to_client.is_none_or(|to| to == tx.client_id) => {
+            Err(TransactionError::InvalidRecipient)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let txs = [
+            Transaction::new(
+                TransactionKind::Deposit,
+                1,
+                1,
+                Some(dec!(1.000000000000000001)),
+            ),
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(1))).with_recipient(2),
+            Transaction::new(TransactionKind::Dispute, 1, 1, None).with_reference("CASE-1"),
+            Transaction::new(TransactionKind::AssertBalance, 1, 0, Some(dec!(0))),
+        ];
+
+        // The CSV is read back as the command line does
+        let mut writer = TransactionWriter::csv(Vec::new());
+        for tx in &txs {
+            writer.write(tx).unwrap();
+        }
+        let csv = writer.into_inner().unwrap();
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_slice());
+        let read: Vec<Transaction> = reader.deserialize().map(Result::unwrap).collect();
+        assert_eq!(read, txs);
+
+        // The lines are read back as the REST server does
+        let mut writer = TransactionWriter::jsonl(Vec::new());
+        for tx in &txs {
+            writer.write(tx).unwrap();
+        }
+        let jsonl = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let read: Vec<Transaction> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(read, txs);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut writer = TransactionWriter::csv(Vec::new());
+        let invalid = [
+            (
+                Transaction::new(TransactionKind::Withdrawal, 1, 1, None),
+                TransactionError::MissingAmount,
+            ),
+            (
+                Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(1))),
+                TransactionError::InvalidRecipient,
+            ),
+            (
+                Transaction::new(TransactionKind::Deposit, 1, 4, Some(dec!(-1))),
+                TransactionError::NonPositiveAmount,
+            ),
+            (
+                Transaction::new(TransactionKind::Transfer, 1, 3, Some(dec!(1))).with_recipient(1),
+                TransactionError::InvalidRecipient,
+            ),
+        ];
+
+        // Nothing is written for rejected transactions, not even the headers
+        for (tx, expected) in invalid {
+            match writer.write(&tx) {
+                Err(WriteError::Invalid(error)) => assert_eq!(error, expected),
+                other => panic!("unexpected result: {other:?}"),
+            }
+        }
+        assert!(writer.into_inner().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::payments_engine::PaymentsEngine;
+
+/// An expected closing total, either for a single client or, when the client
+/// is missing, for all the accounts together.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ControlTotal {
+    pub client: Option<u16>,
+    pub total: Decimal,
+}
+
+/// A control total not matching the engine state, the delta is the actual
+/// total minus the expected one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlMismatch {
+    pub client: Option<u16>,
+    pub expected: Decimal,
+    pub actual: Decimal,
+    pub delta: Decimal,
+}
+
+/// Check the control totals against the engine state, returning the
+/// mismatches. Missing accounts are considered empty, and the sums saturate
+/// at the bounds of the amounts.
+///
+/// # Example
+/// ```
+/// use payments::control_totals::{self, ControlTotal};
+/// use payments::payments_engine::PaymentsEngine;
+/// use rust_decimal_macros::dec;
+///
+/// let engine = PaymentsEngine::new();
+/// let totals = [ControlTotal { client: Some(1), total: dec!(1) }];
+///
+/// let mismatches = control_totals::check(&engine, totals);
+/// assert_eq!(mismatches[0].delta, dec!(-1));
+/// ```
+pub fn check(
+    engine: &PaymentsEngine,
+    totals: impl IntoIterator<Item = ControlTotal>,
+) -> Vec<ControlMismatch> {
+    totals
+        .into_iter()
+        .filter_map(|control| {
+            let actual = match control.client {
+                Some(id) => engine.account(id).map_or(Decimal::ZERO, |a| a.total),
+                None => engine
+                    .accounts()
+                    .fold(Decimal::ZERO, |sum, a| sum.saturating_add(a.total)),
+            };
+
+            (actual != control.total).then_some(ControlMismatch {
+                client: control.client,
+                expected: control.total,
+                actual,
+                delta: actual.saturating_sub(control.total),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{transaction::Transaction, transaction_kind::TransactionKind};
+
+    #[test]
+    fn test_check() {
+        // Create test engine with two accounts
+        let mut engine = PaymentsEngine::new();
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        engine.execute(deposit_tx).unwrap();
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 2, 2, Some(dec!(2)));
+        engine.execute(deposit_tx).unwrap();
+
+        // Only the second client and the aggregate mismatch
+        let totals = [
+            ControlTotal { client: Some(1), total: dec!(1) },
+            ControlTotal { client: Some(2), total: dec!(2.5) },
+            ControlTotal { client: None, total: dec!(3.5) },
+        ];
+        assert_eq!(
+            check(&engine, totals),
+            vec![
+                ControlMismatch {
+                    client: Some(2),
+                    expected: dec!(2.5),
+                    actual: dec!(2),
+                    delta: dec!(-0.5),
+                },
+                ControlMismatch {
+                    client: None,
+                    expected: dec!(3.5),
+                    actual: dec!(3),
+                    delta: dec!(-0.5),
+                },
+            ]
+        );
+    }
+}
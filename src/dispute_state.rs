@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine_config::RedisputePolicy, transaction_error::TransactionError,
+    transaction_kind::TransactionKind,
+};
+
+/// Where a transaction stands in the dispute process.
+///
+/// A dispute opens the process, which is then closed by either a resolve or
+/// a chargeback. Charged back transactions can't be claimed anymore, while
+/// resolved ones can be disputed again as far as the `RedisputePolicy`
+/// allows it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    /// The transaction was never disputed.
+    #[default]
+    None,
+    Open,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    /// Get the state following a claim of the given kind, on a transaction
+    /// disputed this many times so far.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason for which the claim is not allowed in this state.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::dispute_state::DisputeState;
+    /// use payments::engine_config::RedisputePolicy;
+    /// use payments::transaction_kind::TransactionKind;
+    ///
+    /// let state = DisputeState::None.claim(TransactionKind::Dispute, RedisputePolicy::Deny, 0);
+    /// assert_eq!(state, Ok(DisputeState::Open));
+    /// ```
+    pub fn claim(
+        self,
+        kind: TransactionKind,
+        redisputes: RedisputePolicy,
+        disputes: u32,
+    ) -> Result<Self, TransactionError> {
+        match (kind, self) {
+            (_, Self::ChargedBack) => Err(TransactionError::DisputeClosed),
+            (TransactionKind::Dispute, Self::Open) => Err(TransactionError::AlreadyDisputed),
+            (TransactionKind::Dispute, Self::Resolved) if !redisputes.allows(disputes) => {
+                Err(TransactionError::DisputeClosed)
+            }
+            (TransactionKind::Dispute, _) => Ok(Self::Open),
+            (TransactionKind::Resolve, Self::Open) => Ok(Self::Resolved),
+            (TransactionKind::Chargeback, Self::Open) => Ok(Self::ChargedBack),
+            _ => Err(TransactionError::NotDisputed),
+        }
+    }
+}
+
+/// A dispute opened on a transaction, along with how and when it was closed.
+/// Claims without a timestamp leave the times unknown.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisputeRecord {
+    /// `Open` until the dispute is resolved or charged back.
+    pub state: DisputeState,
+    #[serde(default)]
+    pub opened_at: Option<i64>,
+    #[serde(default)]
+    pub closed_at: Option<i64>,
+    /// The latest case reference stated by the claims of the dispute.
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+impl DisputeRecord {
+    /// Record a dispute opened at the given time, if known.
+    #[must_use]
+    pub const fn open(opened_at: Option<i64>, reference: Option<String>) -> Self {
+        Self {
+            state: DisputeState::Open,
+            opened_at,
+            closed_at: None,
+            reference,
+        }
+    }
+
+    /// Close the dispute with the state following a resolve or a chargeback.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::dispute_state::{DisputeRecord, DisputeState};
+    ///
+    /// let mut dispute = DisputeRecord::open(Some(10), Some("C1".to_string()));
+    /// dispute.close(DisputeState::Resolved, Some(20), None);
+    ///
+    /// assert_eq!(dispute.state, DisputeState::Resolved);
+    /// assert_eq!(dispute.closed_at, Some(20));
+    /// assert_eq!(dispute.reference.as_deref(), Some("C1"));
+    /// ```
+    pub fn close(
+        &mut self,
+        state: DisputeState,
+        closed_at: Option<i64>,
+        reference: Option<&String>,
+    ) {
+        self.state = state;
+        self.closed_at = closed_at;
+        if let Some(reference) = reference {
+            self.reference = Some(reference.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let allow = RedisputePolicy::Allow;
+        let claim = |state: DisputeState, kind| state.claim(kind, allow, 1);
+
+        // Claims only follow an open dispute
+        assert_eq!(
+            claim(DisputeState::None, TransactionKind::Resolve),
+            Err(TransactionError::NotDisputed)
+        );
+        assert_eq!(
+            claim(DisputeState::Open, TransactionKind::Dispute),
+            Err(TransactionError::AlreadyDisputed)
+        );
+        assert_eq!(
+            claim(DisputeState::Open, TransactionKind::Chargeback),
+            Ok(DisputeState::ChargedBack)
+        );
+
+        // Chargebacks are final
+        for kind in [
+            TransactionKind::Dispute,
+            TransactionKind::Resolve,
+            TransactionKind::Chargeback,
+        ] {
+            assert_eq!(
+                claim(DisputeState::ChargedBack, kind),
+                Err(TransactionError::DisputeClosed)
+            );
+        }
+
+        // Resolved transactions can be disputed again, unless denied
+        assert_eq!(
+            claim(DisputeState::Resolved, TransactionKind::Dispute),
+            Ok(DisputeState::Open)
+        );
+        assert_eq!(
+            DisputeState::Resolved.claim(TransactionKind::Dispute, RedisputePolicy::Deny, 1),
+            Err(TransactionError::DisputeClosed)
+        );
+
+        // Or limited to a number of new disputes, after the first one
+        let redispute = |times, disputes| {
+            DisputeState::Resolved.claim(
+                TransactionKind::Dispute,
+                RedisputePolicy::AllowTimes(times),
+                disputes,
+            )
+        };
+        assert_eq!(redispute(0, 1), Err(TransactionError::DisputeClosed));
+        assert_eq!(redispute(1, 1), Ok(DisputeState::Open));
+        assert_eq!(redispute(1, 2), Err(TransactionError::DisputeClosed));
+        assert_eq!(redispute(2, 2), Ok(DisputeState::Open));
+        assert_eq!(redispute(2, 3), Err(TransactionError::DisputeClosed));
+    }
+}
@@ -0,0 +1,486 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{transaction_error::TransactionError, transaction_kind::TransactionKind};
+
+/// Number of decimal places used by the spec-style CSVs.
+pub const SPEC_DECIMALS: u32 = 4;
+
+/// Behavioral policies of the `PaymentsEngine`.
+///
+/// The configuration can be deserialized, e.g. from a TOML file, missing
+/// fields take their default value and policies are named as on the command
+/// line.
+///
+/// # Example
+/// ```
+/// use payments::engine_config::{EngineConfig, LockedAccountPolicy};
+/// use rust_decimal_macros::dec;
+///
+/// let config: EngineConfig = toml::from_str(
+///     r#"
+///     locked_accounts = "reject-withdrawals"
+///     max_amount = "1000"
+///     "#,
+/// )
+/// .unwrap();
+/// assert_eq!(config.locked_accounts, LockedAccountPolicy::RejectWithdrawals);
+/// assert_eq!(config.max_amount, Some(dec!(1000)));
+/// ```
+///
+/// Fees are configured per transaction type:
+/// ```
+/// use payments::engine_config::EngineConfig;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let config: EngineConfig = toml::from_str(
+///     r#"
+///     [fees.withdrawal]
+///     flat = "0.5"
+///     percentage = "1"
+///     "#,
+/// )
+/// .unwrap();
+/// assert_eq!(config.fees[&TransactionKind::Withdrawal].amount(dec!(10)), dec!(0.6));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub locked_accounts: LockedAccountPolicy,
+    pub withdrawal_disputes: WithdrawalDisputePolicy,
+    /// Spec-compliant mode: disputes hold the deposited funds even if they
+    /// were already spent, so chargebacks can drive the total negative.
+    pub allow_negative_balances: bool,
+    /// Containment mode: a panic while handling a transaction quarantines its
+    /// client instead of aborting, the other clients are still processed.
+    pub contain_panics: bool,
+    /// Invariant checking mode: every account is checked after each
+    /// transaction, panicking on violations, e.g. to catch bugs when
+    /// debugging. Each check goes over the whole state, which makes it slow,
+    /// and release builds skip it.
+    pub check_invariants: bool,
+    pub excess_precision: ExcessPrecisionPolicy,
+    pub duplicate_transactions: DuplicatePolicy,
+    pub redisputes: RedisputePolicy,
+    /// Backfill mode: events and rejections aren't forwarded to the listeners
+    /// and the reject handler, so that reprocessing historical transactions
+    /// doesn't notify downstream systems again.
+    pub backfill: bool,
+    /// Reject deposits, withdrawals and transfers moving more than this.
+    pub max_amount: Option<Decimal>,
+    pub out_of_order: OutOfOrderPolicy,
+    /// The fees charged to the client for each applied transaction type.
+    pub fees: BTreeMap<TransactionKind, Fee>,
+    /// Let withdrawals and outgoing transfers take the available funds
+    /// negative down to minus this, unless the account states its own
+    /// `Account::credit_limit`.
+    pub credit_limit: Decimal,
+}
+
+/// A fee charged to the available funds of a client along with a
+/// transaction, credited to the fees account of the ledger. Only the fees of
+/// chargebacks can leave a deficit, the other transactions are rejected if
+/// their client can't afford the fee.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fee {
+    pub flat: Decimal,
+    /// The percentage of the amount moved by the transaction, e.g. the
+    /// disputed amount for claims.
+    pub percentage: Decimal,
+}
+
+impl Fee {
+    /// Get the fee for a transaction moving the amount, rounded to
+    /// `SPEC_DECIMALS` decimal places, half to even.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::engine_config::Fee;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let fee = Fee { flat: dec!(0.25), percentage: dec!(1.5) };
+    /// assert_eq!(fee.amount(dec!(0.01)), dec!(0.2502));
+    /// ```
+    #[must_use]
+    pub fn amount(self, amount: Decimal) -> Decimal {
+        let percentage = amount.saturating_mul(self.percentage) / Decimal::ONE_HUNDRED;
+        self.flat.saturating_add(percentage).round_dp(SPEC_DECIMALS)
+    }
+}
+
+impl FromStr for Fee {
+    type Err = String;
+
+    /// Parse a flat fee, a percentage or both, e.g. `0.5`, `1%` or `0.5+1%`.
+    /// The parts of the same kind add up, e.g. `1%+1%` is `2%`.
+    ///
+    /// # Errors
+    /// Returns an error if a part isn't a non-negative decimal, or if the
+    /// parts overflow.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::engine_config::Fee;
+    /// use rust_decimal::Decimal;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let fee: Fee = "0.5+1%+1%".parse().unwrap();
+    /// assert_eq!(fee, Fee { flat: dec!(0.5), percentage: dec!(2) });
+    /// assert!("-1%".parse::<Fee>().is_err());
+    /// assert!(format!("{}+{}", Decimal::MAX, Decimal::MAX).parse::<Fee>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fee = Self::default();
+        for part in s.split('+') {
+            let (amount, percentage) = match part.trim().strip_suffix('%') {
+                Some(amount) => (amount, true),
+                None => (part.trim(), false),
+            };
+            let amount: Decimal = amount.parse().map_err(|_| format!("Unknown fee: {s}"))?;
+            if amount.is_sign_negative() {
+                return Err(format!("Negative fee: {s}"));
+            }
+            let total = if percentage {
+                &mut fee.percentage
+            } else {
+                &mut fee.flat
+            };
+            *total = total
+                .checked_add(amount)
+                .ok_or_else(|| format!("Fee overflow: {s}"))?;
+        }
+        Ok(fee)
+    }
+}
+
+/// What the engine does with transactions on locked accounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockedAccountPolicy {
+    /// Reject every transaction, this is the default.
+    #[default]
+    RejectAll,
+    /// Only reject withdrawals and outgoing transfers, deposits and claims are
+    /// still processed.
+    RejectWithdrawals,
+    /// Process transactions as if the account wasn't locked.
+    Allow,
+}
+
+impl LockedAccountPolicy {
+    /// Check whether a transaction of the given kind is rejected on a locked
+    /// account. Balance assertions are never rejected, as they don't alter the
+    /// account.
+    #[must_use]
+    pub fn rejects(self, kind: TransactionKind) -> bool {
+        match self {
+            Self::RejectAll => kind != TransactionKind::AssertBalance,
+            Self::RejectWithdrawals => {
+                matches!(
+                    kind,
+                    TransactionKind::Withdrawal | TransactionKind::Transfer
+                )
+            }
+            Self::Allow => false,
+        }
+    }
+}
+
+/// How the engine handles disputes on withdrawals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WithdrawalDisputePolicy {
+    /// Withdrawals have their own funds flow: a dispute credits the withdrawn
+    /// funds back as held, a resolve lets the withdrawal stand and a
+    /// chargeback reverses it. This is the default.
+    #[default]
+    Reverse,
+    /// Legacy behavior: withdrawals can't be disputed.
+    Deny,
+}
+
+/// Whether resolved transactions can be disputed again, as tracked by the
+/// dispute history of each transaction, e.g. to stop clients abusing
+/// repeated dispute and resolve cycles. The first dispute is always allowed,
+/// the policy only limits the following ones.
+///
+/// # Example
+/// ```
+/// use payments::engine_config::RedisputePolicy;
+///
+/// let policy: RedisputePolicy = "allow-2".parse().unwrap();
+/// assert_eq!(policy, RedisputePolicy::AllowTimes(2));
+///
+/// // The first dispute is always allowed, then two more
+/// assert!(policy.allows(2));
+/// assert!(!policy.allows(3));
+///
+/// // So that allowing none is the same as denying
+/// assert!(!RedisputePolicy::AllowTimes(0).allows(1));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RedisputePolicy {
+    /// Allow a new dispute, this is the default.
+    #[default]
+    Allow,
+    /// Allow this many new disputes of each transaction after the first one,
+    /// i.e. `N + 1` disputes in total, named `allow-N`.
+    AllowTimes(u32),
+    /// Reject it, a resolve closes the dispute process for good.
+    Deny,
+}
+
+impl RedisputePolicy {
+    /// Whether a transaction disputed this many times so far can be disputed
+    /// again once resolved.
+    #[must_use]
+    pub const fn allows(self, disputes: u32) -> bool {
+        match self {
+            Self::Allow => true,
+            Self::AllowTimes(times) => disputes <= times,
+            Self::Deny => false,
+        }
+    }
+}
+
+impl FromStr for RedisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let times = s
+            .strip_prefix("allow-")
+            .and_then(|times| times.parse().ok());
+        match (s, times) {
+            (_, Some(times)) => Ok(Self::AllowTimes(times)),
+            ("allow", _) => Ok(Self::Allow),
+            ("deny", _) => Ok(Self::Deny),
+            _ => Err(format!("Unknown redispute policy: {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for RedisputePolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// What the engine does with deposits, withdrawals and transfers reusing the
+/// id of a previous one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Reject the transaction, this is the default.
+    #[default]
+    Reject,
+    /// Skip the transaction as if it was already applied, e.g. when the same
+    /// file may be delivered twice.
+    Ignore,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "ignore" => Ok(Self::Ignore),
+            _ => Err(format!("Unknown duplicate transaction policy: {s}")),
+        }
+    }
+}
+
+/// What the engine does with timestamped transactions older than the last
+/// activity of their account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutOfOrderPolicy {
+    /// Apply the transaction, this is the default.
+    #[default]
+    Allow,
+    /// Apply the transaction, emitting an `EngineEvent::OutOfOrder` event.
+    Warn,
+    /// Reject the transaction, leaving the account untouched.
+    Reject,
+}
+
+impl FromStr for OutOfOrderPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "warn" => Ok(Self::Warn),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("Unknown out-of-order policy: {s}")),
+        }
+    }
+}
+
+/// What the engine does with deposits, withdrawals and transfers stating
+/// more than `SPEC_DECIMALS` decimal places.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcessPrecisionPolicy {
+    /// Keep the amount as is, e.g. for assets with more decimal places. The
+    /// outputs still round the balances unless they're given more places.
+    Keep,
+    /// Round the amount to `SPEC_DECIMALS` decimal places, half to even, this
+    /// is the default.
+    #[default]
+    Round,
+    /// Reject the transaction.
+    Reject,
+}
+
+impl ExcessPrecisionPolicy {
+    /// Normalize the amount of a deposit, withdrawal or transfer according to
+    /// the policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::ExcessPrecision` if the amount is rejected.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::engine_config::ExcessPrecisionPolicy;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let policy = ExcessPrecisionPolicy::Round;
+    /// assert_eq!(policy.normalize(dec!(1.00005)), Ok(dec!(1.0000)));
+    /// assert!(ExcessPrecisionPolicy::Reject.normalize(dec!(1.00005)).is_err());
+    /// ```
+    pub fn normalize(self, amount: Decimal) -> Result<Decimal, TransactionError> {
+        if amount.scale() <= SPEC_DECIMALS {
+            return Ok(amount);
+        }
+
+        match self {
+            Self::Keep => Ok(amount),
+            Self::Round => Ok(amount.round_dp(SPEC_DECIMALS)),
+            Self::Reject => Err(TransactionError::ExcessPrecision),
+        }
+    }
+}
+
+impl FromStr for ExcessPrecisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "round" => Ok(Self::Round),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("Unknown excess precision policy: {s}")),
+        }
+    }
+}
+
+impl FromStr for WithdrawalDisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reverse" => Ok(Self::Reverse),
+            "deny" => Ok(Self::Deny),
+            _ => Err(format!("Unknown withdrawal dispute policy: {s}")),
+        }
+    }
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject-all" => Ok(Self::RejectAll),
+            "reject-withdrawals" => Ok(Self::RejectWithdrawals),
+            "allow" => Ok(Self::Allow),
+            _ => Err(format!("Unknown locked account policy: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_policy_names() {
+        // The command line and the configuration files name policies alike
+        let config: EngineConfig = toml::from_str(
+            r#"
+            locked_accounts = "allow"
+            withdrawal_disputes = "deny"
+            excess_precision = "reject"
+            duplicate_transactions = "ignore"
+            redisputes = "allow-1"
+            out_of_order = "warn"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.locked_accounts, "allow".parse().unwrap());
+        assert_eq!(config.withdrawal_disputes, "deny".parse().unwrap());
+        assert_eq!(config.excess_precision, "reject".parse().unwrap());
+        assert_eq!(config.duplicate_transactions, "ignore".parse().unwrap());
+        assert_eq!(config.redisputes, "allow-1".parse().unwrap());
+        assert_eq!(config.out_of_order, "warn".parse().unwrap());
+
+        assert!(toml::from_str::<EngineConfig>("locked_accounts = \"deny\"").is_err());
+        assert!(toml::from_str::<EngineConfig>("redisputes = \"allow-x\"").is_err());
+        assert!(toml::from_str::<EngineConfig>("max_amounts = \"1\"").is_err());
+        assert_eq!(
+            toml::from_str::<EngineConfig>(""),
+            Ok(EngineConfig::default())
+        );
+    }
+
+    #[test]
+    fn test_locked_accounts() {
+        for kind in TransactionKind::ALL {
+            let withdrawing = matches!(
+                kind,
+                TransactionKind::Withdrawal | TransactionKind::Transfer
+            );
+            assert_eq!(
+                LockedAccountPolicy::RejectAll.rejects(kind),
+                kind != TransactionKind::AssertBalance
+            );
+            assert_eq!(
+                LockedAccountPolicy::RejectWithdrawals.rejects(kind),
+                withdrawing
+            );
+            assert!(!LockedAccountPolicy::Allow.rejects(kind));
+        }
+    }
+
+    #[test]
+    fn test_fees() {
+        let fee = Fee { flat: dec!(1), percentage: dec!(0.5) };
+        assert_eq!(fee.amount(dec!(0)), dec!(1));
+        assert_eq!(fee.amount(dec!(3)), dec!(1.015));
+        // Rounded half to even
+        assert_eq!(
+            Fee { flat: dec!(0), percentage: dec!(1) }.amount(dec!(0.0125)),
+            dec!(0.0001)
+        );
+        // Saturated rather than overflowing
+        assert_eq!(
+            Fee { flat: Decimal::MAX, percentage: dec!(100) }.amount(Decimal::MAX),
+            Decimal::MAX
+        );
+
+        assert_eq!("2%".parse(), Ok(Fee { flat: dec!(0), percentage: dec!(2) }));
+        assert!("".parse::<Fee>().is_err());
+        assert!("1%%".parse::<Fee>().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Domain events emitted by the `PaymentsEngine` to its listeners.
+//!
+//! # Evolution policy
+//!
+//! Events are meant to be persisted and consumed by other services, so their
+//! serialized form is a contract. Every event is wrapped in a
+//! `VersionedEvent` carrying `EVENT_SCHEMA_VERSION`, and the following rules
+//! apply:
+//!
+//! - adding an event variant or an optional field is backwards compatible and
+//!   keeps the current version, consumers must ignore what they don't know:
+//!   unknown fields are skipped and unknown variants read as
+//!   `EngineEvent::Unknown`;
+//! - renaming or removing a variant or a field, or changing the meaning of an
+//!   existing field, bumps the version.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::AdminBatch, transaction_error::TransactionError, transaction_kind::TransactionKind,
+};
+
+/// Version of the serialized event schema, see the module documentation.
+pub const EVENT_SCHEMA_VERSION: u16 = 1;
+
+/// Something that happened inside the engine. Events about an account state
+/// its `account_version` after the event, see `Account::version`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum EngineEvent<C = u16, T = u32> {
+    DepositApplied {
+        client: C,
+        tx: T,
+        amount: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    WithdrawalApplied {
+        client: C,
+        tx: T,
+        amount: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    TransferApplied {
+        client: C,
+        to_client: C,
+        tx: T,
+        amount: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    DisputeOpened {
+        client: C,
+        tx: T,
+        amount: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    DisputeResolved {
+        client: C,
+        tx: T,
+        amount: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    ChargebackApplied {
+        client: C,
+        tx: T,
+        amount: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    BalanceAsserted {
+        client: C,
+        total: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    /// The first transaction of a client, or the first transfer to it,
+    /// opened its account.
+    AccountCreated {
+        client: C,
+    },
+    /// A chargeback locked the account.
+    AccountLocked {
+        client: C,
+        tx: T,
+        #[serde(default)]
+        account_version: u64,
+    },
+    ClientQuarantined {
+        client: C,
+        tx: T,
+    },
+    AccountDeleted {
+        client: C,
+    },
+    AccountRestored {
+        client: C,
+    },
+    TransactionRejected {
+        client: C,
+        tx: T,
+        kind: TransactionKind,
+        reason: TransactionError,
+    },
+    /// A transaction older than the last activity of the account was applied
+    /// anyway, under `OutOfOrderPolicy::Warn`.
+    OutOfOrder {
+        client: C,
+        tx: T,
+        timestamp: i64,
+        last_activity: i64,
+    },
+    /// A fee was charged along with the transaction, see `EngineConfig::fees`.
+    FeeCharged {
+        client: C,
+        tx: T,
+        kind: TransactionKind,
+        amount: Decimal,
+        #[serde(default)]
+        account_version: u64,
+    },
+    /// A batch of administrative actions was applied, see
+    /// `PaymentsEngine::administer`.
+    AdminBatchApplied(AdminBatch<C>),
+    /// An event added by a later schema, read along with its envelope instead
+    /// of failing, see the evolution policy. It's never emitted.
+    #[serde(other)]
+    Unknown,
+}
+
+impl<C, T> EngineEvent<C, T> {
+    /// Wrap the event in its versioned envelope, ready to be serialized.
+    #[must_use]
+    pub const fn versioned(self) -> VersionedEvent<C, T> {
+        VersionedEvent { version: EVENT_SCHEMA_VERSION, event: self }
+    }
+}
+
+/// The serialized form of an `EngineEvent`, stating the schema version.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionedEvent<C = u16, T = u32> {
+    pub version: u16,
+    #[serde(flatten)]
+    pub event: EngineEvent<C, T>,
+}
+
+/// A listener notified of every event emitted by the engine.
+///
+/// Any `FnMut(&EngineEvent)` closure is a listener too, which makes it easy
+/// to forward events to a channel or any other sink.
+pub trait EventListener<C = u16, T = u32>: Send {
+    fn on_event(&mut self, event: &EngineEvent<C, T>);
+}
+
+impl<C, T, F: FnMut(&EngineEvent<C, T>) + Send> EventListener<C, T> for F {
+    fn on_event(&mut self, event: &EngineEvent<C, T>) {
+        self(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let event = EngineEvent::DepositApplied {
+            client: 1,
+            tx: 2,
+            amount: dec!(1.5),
+            account_version: 1,
+        };
+        let json = serde_json::to_string(&event.clone().versioned()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":1,"event":"deposit_applied","client":1,"tx":2,"amount":"1.5","account_version":1}"#
+        );
+
+        // The envelope can be read back
+        let versioned: VersionedEvent = serde_json::from_str(&json).unwrap();
+        assert_eq!(versioned.event, event);
+    }
+
+    #[test]
+    fn test_unknown_event() {
+        // Events of later versions of the same schema are read anyway
+        let json = r#"{"version":1,"event":"account_frozen","client":1,"until":"tomorrow"}"#;
+        let versioned: VersionedEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(versioned.event, EngineEvent::Unknown);
+
+        // And so are unknown fields of the known ones
+        let json = r#"{"version":1,"event":"account_created","client":1,"region":"eu"}"#;
+        let versioned: VersionedEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(versioned.event, EngineEvent::AccountCreated { client: 1 });
+    }
+
+    #[test]
+    fn test_rejection_serialization() {
+        let event = EngineEvent::TransactionRejected {
+            client: 1,
+            tx: 2,
+            kind: TransactionKind::Withdrawal,
+            reason: TransactionError::InsufficientFunds,
+        };
+        let json = serde_json::to_string(&event.versioned()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":1,"event":"transaction_rejected","client":1,"tx":2,"kind":"withdrawal","reason":"insufficient_funds"}"#
+        );
+    }
+}
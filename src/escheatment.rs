@@ -0,0 +1,120 @@
+use rust_decimal::Decimal;
+
+use crate::payments_engine::PaymentsEngine;
+
+/// Prefix of the account tags stating the jurisdiction of the client, e.g.
+/// `jurisdiction:NY`.
+pub const JURISDICTION_TAG: &str = "jurisdiction:";
+
+/// Average length of a Gregorian year, in seconds.
+const YEAR_SECONDS: i64 = 31_556_952;
+
+/// The available funds of an account untouched for too long, which may have
+/// to be escheated to the jurisdiction of the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgedBalance {
+    pub client: u16,
+    /// The jurisdiction of the client, from its `JURISDICTION_TAG` tag.
+    pub jurisdiction: Option<String>,
+    /// The timestamp of the last transaction applied to the account.
+    pub last_activity: i64,
+    pub amount: Decimal,
+}
+
+/// Find the active accounts with available funds whose last activity is at
+/// least the given number of years before `as_of`, ordered by jurisdiction and
+/// client id. Accounts without any timestamped transaction are left out, as
+/// their age is unknown, and so are held funds, which are under dispute.
+///
+/// # Example
+/// ```
+/// use payments::escheatment;
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let mut engine = PaymentsEngine::new();
+/// let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1))).with_timestamp(0);
+/// engine.execute(tx).unwrap();
+/// engine.tag_account(1, "jurisdiction:NY");
+///
+/// let aged = escheatment::aged_balances(&engine, 200_000_000, 5);
+/// assert_eq!(aged[0].jurisdiction.as_deref(), Some("NY"));
+/// assert!(escheatment::aged_balances(&engine, 100_000_000, 5).is_empty());
+/// ```
+#[must_use]
+pub fn aged_balances(engine: &PaymentsEngine, as_of: i64, years: u32) -> Vec<AgedBalance> {
+    let cutoff = as_of.saturating_sub(i64::from(years).saturating_mul(YEAR_SECONDS));
+
+    let mut aged: Vec<_> = engine
+        .accounts_sorted()
+        .filter(|account| account.available > Decimal::ZERO)
+        .filter_map(|account| {
+            let last_activity = account.last_activity.filter(|t| *t <= cutoff)?;
+            let jurisdiction = engine
+                .account_tags(account.id)
+                .find_map(|tag| tag.strip_prefix(JURISDICTION_TAG))
+                .map(Into::into);
+
+            Some(AgedBalance {
+                client: account.id,
+                jurisdiction,
+                last_activity,
+                amount: account.available,
+            })
+        })
+        .collect();
+    aged.sort_by(|a, b| {
+        a.jurisdiction
+            .cmp(&b.jurisdiction)
+            .then(a.client.cmp(&b.client))
+    });
+    aged
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{transaction::Transaction, transaction_kind::TransactionKind};
+
+    #[test]
+    fn test_aged_balances() {
+        // Create transactions, the second client is active again later on
+        let deposit = |client, tx, timestamp| {
+            Transaction::new(TransactionKind::Deposit, client, tx, Some(dec!(1)))
+                .with_timestamp(timestamp)
+        };
+        let untimed_tx = Transaction::new(TransactionKind::Deposit, 3, 4, Some(dec!(1)));
+        let assert_tx = Transaction::new(TransactionKind::AssertBalance, 1, 0, Some(dec!(1)))
+            .with_timestamp(3 * YEAR_SECONDS);
+
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit(1, 1, 0)).unwrap();
+        engine.execute(deposit(2, 2, 0)).unwrap();
+        engine.execute(deposit(2, 3, 2 * YEAR_SECONDS)).unwrap();
+        engine.execute(untimed_tx).unwrap();
+        engine.execute(assert_tx).unwrap();
+        engine.tag_account(2, "jurisdiction:CA");
+
+        // Assertions don't count as activity, untimed accounts are left out
+        let aged = aged_balances(&engine, 3 * YEAR_SECONDS, 2);
+        assert_eq!(
+            aged,
+            vec![AgedBalance {
+                client: 1,
+                jurisdiction: None,
+                last_activity: 0,
+                amount: dec!(1),
+            }]
+        );
+
+        // Clients without a jurisdiction come first
+        let aged = aged_balances(&engine, 3 * YEAR_SECONDS, 1);
+        assert_eq!(aged.len(), 2);
+        assert_eq!(aged[1].jurisdiction.as_deref(), Some("CA"));
+        assert_eq!(aged[1].amount, dec!(2));
+    }
+}
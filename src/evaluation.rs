@@ -0,0 +1,158 @@
+use std::{panic, thread};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    account::Account, engine_config::EngineConfig, payments_engine::PaymentsEngine,
+    transaction::Transaction,
+};
+
+/// The outcome of the transactions under one of the evaluated
+/// configurations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub name: String,
+    pub applied: usize,
+    pub rejected: usize,
+    /// The amount of the rejected transactions stating one, e.g. deposits
+    /// and withdrawals.
+    pub rejected_amount: Decimal,
+    pub locked_accounts: usize,
+    /// The final accounts, sorted by client.
+    pub accounts: Vec<Account>,
+}
+
+/// Execute the same transactions with every named configuration, each on its
+/// own engine built by `new_engine` and its own thread, e.g. to compare
+/// policies before choosing one. Consecutive legs sharing a group are
+/// executed as a composite transaction, as when processing a file.
+///
+/// # Panics
+///
+/// Panics if an engine panicked, without containment mode.
+///
+/// # Example
+/// ```
+/// use payments::engine_config::EngineConfig;
+/// use payments::evaluation;
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let txs = [
+///     Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(5))),
+///     Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(20))),
+/// ];
+/// let limited = EngineConfig { max_amount: Some(dec!(10)), ..EngineConfig::default() };
+/// let configs = [("default", EngineConfig::default()), ("limited", limited)];
+///
+/// let evaluations = evaluation::evaluate(&txs, configs, PaymentsEngine::new);
+/// assert_eq!(evaluations[0].rejected, 0);
+/// assert_eq!(evaluations[1].rejected_amount, dec!(20));
+/// ```
+pub fn evaluate(
+    txs: &[Transaction],
+    configs: impl IntoIterator<Item = (impl Into<String>, EngineConfig)>,
+    new_engine: impl Fn() -> PaymentsEngine + Sync,
+) -> Vec<Evaluation> {
+    let new_engine = &new_engine;
+    thread::scope(|scope| {
+        let workers: Vec<_> = configs
+            .into_iter()
+            .map(|(name, config)| {
+                let name = name.into();
+                scope.spawn(move || {
+                    let mut engine = new_engine();
+                    engine.set_config(config);
+                    run(name, &mut engine, txs)
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+            .collect()
+    })
+}
+
+/// Execute the transactions, tallying the rejected ones.
+fn run(name: String, engine: &mut PaymentsEngine, txs: &[Transaction]) -> Evaluation {
+    let (mut applied, mut rejected, mut rejected_amount) = (0, 0, Decimal::ZERO);
+    for legs in txs.chunk_by(|a, b| a.group.is_some() && a.group == b.group) {
+        let result = match legs {
+            [tx] if tx.group.is_none() => engine.execute(tx.clone()),
+            _ => engine.execute_group(legs.to_vec()),
+        };
+
+        if result.is_ok() {
+            applied += legs.len();
+        } else {
+            rejected += legs.len();
+            for amount in legs.iter().filter_map(|tx| tx.amount) {
+                rejected_amount = rejected_amount.saturating_add(amount);
+            }
+        }
+    }
+
+    let accounts: Vec<_> = engine.accounts_sorted().cloned().collect();
+    Evaluation {
+        name,
+        applied,
+        rejected,
+        rejected_amount,
+        locked_accounts: accounts.iter().filter(|account| account.locked).count(),
+        accounts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{engine_config::LockedAccountPolicy, transaction_kind::TransactionKind};
+
+    #[test]
+    fn test_evaluate() {
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2))),
+            Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1))),
+            Transaction::new(TransactionKind::Dispute, 1, 2, None),
+            Transaction::new(TransactionKind::Chargeback, 1, 2, None),
+            Transaction::new(TransactionKind::Deposit, 1, 3, Some(dec!(3))),
+            // A group of legs is applied or rejected as a whole
+            Transaction::new(TransactionKind::Withdrawal, 2, 4, Some(dec!(1))),
+            Transaction::new(TransactionKind::Deposit, 2, 5, Some(dec!(1))),
+        ]
+        .map(|tx| Transaction { group: (tx.client_id == 2).then_some(4), ..tx });
+        let configs = [
+            ("strict", EngineConfig::default()),
+            (
+                "lenient",
+                EngineConfig {
+                    locked_accounts: LockedAccountPolicy::Allow,
+                    ..EngineConfig::default()
+                },
+            ),
+        ];
+
+        let evaluations = evaluate(&txs, configs, PaymentsEngine::new);
+        let strict = &evaluations[0];
+        assert_eq!(strict.name, "strict");
+        assert_eq!((strict.applied, strict.rejected), (4, 3));
+        assert_eq!(strict.rejected_amount, dec!(5));
+        assert_eq!(strict.locked_accounts, 1);
+        assert_eq!(strict.accounts[0].total, dec!(2));
+
+        // Deposits on the locked account are still applied
+        let lenient = &evaluations[1];
+        assert_eq!((lenient.applied, lenient.rejected), (5, 2));
+        assert_eq!(lenient.accounts[0].total, dec!(5));
+    }
+}
@@ -0,0 +1,414 @@
+use std::{borrow::Cow, cmp::Ordering, iter::Peekable, str::Chars, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::transaction::Transaction;
+
+/// A column of the transaction files which filters can refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    ToClient,
+    Group,
+    Reference,
+    Timestamp,
+}
+
+impl Column {
+    /// Every column, in the order of the CSV format.
+    pub const ALL: [Self; 8] = [
+        Self::Type,
+        Self::Client,
+        Self::Tx,
+        Self::Amount,
+        Self::ToClient,
+        Self::Group,
+        Self::Reference,
+        Self::Timestamp,
+    ];
+
+    /// Get the header of the column in the CSV format.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Type => "type",
+            Self::Client => "client",
+            Self::Tx => "tx",
+            Self::Amount => "amount",
+            Self::ToClient => "to_client",
+            Self::Group => "group",
+            Self::Reference => "reference",
+            Self::Timestamp => "timestamp",
+        }
+    }
+
+    /// Get the value of the column in the transaction, if any.
+    #[must_use]
+    pub fn value(self, tx: &Transaction) -> Option<String> {
+        match self {
+            Self::Type => Some(tx.kind.as_str().into()),
+            Self::Client => Some(tx.client_id.to_string()),
+            Self::Tx => Some(tx.id.to_string()),
+            Self::Amount => tx.amount.map(|amount| amount.to_string()),
+            Self::ToClient => tx.to_client.map(|id| id.to_string()),
+            Self::Group => tx.group.map(|id| id.to_string()),
+            Self::Reference => tx.reference.clone(),
+            Self::Timestamp => tx.timestamp.map(|t| t.to_string()),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            // The field name of the transaction type is accepted too
+            "kind" => Ok(Self::Type),
+            _ => Self::ALL
+                .into_iter()
+                .find(|column| column.name() == s)
+                .ok_or_else(|| format!("Unknown column: {s}")),
+        }
+    }
+}
+
+/// A side of a comparison, literals are parsed along with the filter so that
+/// evaluating it only converts the values of the columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Column(Column),
+    Number(Decimal),
+    String(String),
+}
+
+/// A comparison operator. Numbers are compared with columns holding a number
+/// as such, anything else is compared as strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Self::Eq => ordering.is_eq(),
+            Self::Ne => ordering.is_ne(),
+            Self::Lt => ordering.is_lt(),
+            Self::Le => ordering.is_le(),
+            Self::Gt => ordering.is_gt(),
+            Self::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+/// A boolean expression over the columns of a row, used to only process a
+/// subset of the transactions, e.g. `client == 42 || kind == 'chargeback'`.
+///
+/// Comparisons between columns and literals (numbers, or strings within
+/// single or double quotes) are combined with `&&`, `||`, `!` and
+/// parentheses, `=` being accepted for `==`. Missing values are empty strings,
+/// and comparing a column with a string never parses it as a number.
+///
+/// # Example
+/// ```
+/// use payments::filter::Filter;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let filter: Filter = "client == 42 || kind == 'chargeback'".parse().unwrap();
+/// let tx = Transaction::new(TransactionKind::Deposit, 42, 1, Some(dec!(1)));
+/// assert!(filter.matches_transaction(&tx));
+///
+/// let tx = Transaction::new(TransactionKind::Deposit, 7, 2, Some(dec!(1)));
+/// assert!(!filter.matches_transaction(&tx));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Compare(Operand, Operator, Operand),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    /// Evaluate the filter, getting the values of the columns from the
+    /// lookup function, e.g. straight from the fields of a CSV record so that
+    /// rows can be skipped without being parsed.
+    pub fn matches<S: AsRef<str>>(&self, lookup: &impl Fn(Column) -> Option<S>) -> bool {
+        match self {
+            Self::Compare(left, operator, right) => {
+                let (left, right) = (Value::of(left, lookup), Value::of(right, lookup));
+                operator.holds(left.compare(&right))
+            }
+            Self::Not(filter) => !filter.matches(lookup),
+            Self::And(left, right) => left.matches(lookup) && right.matches(lookup),
+            Self::Or(left, right) => left.matches(lookup) || right.matches(lookup),
+        }
+    }
+
+    /// Evaluate the filter against a parsed transaction.
+    #[must_use]
+    pub fn matches_transaction(&self, tx: &Transaction) -> bool {
+        self.matches(&|column: Column| column.value(tx))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, position: 0 };
+        let filter = parser.or()?;
+        match parser.next() {
+            None => Ok(filter),
+            Some(token) => Err(format!("Unexpected {token:?} in filter")),
+        }
+    }
+}
+
+/// The value of an operand within a row.
+enum Value<'a, S> {
+    Column(Option<S>),
+    Number(Decimal),
+    String(&'a str),
+}
+
+impl<'a, S: AsRef<str>> Value<'a, S> {
+    fn of(operand: &'a Operand, lookup: &impl Fn(Column) -> Option<S>) -> Self {
+        match operand {
+            Operand::Column(column) => Self::Column(lookup(*column)),
+            Operand::Number(number) => Self::Number(*number),
+            Operand::String(string) => Self::String(string),
+        }
+    }
+
+    /// Compare the values as numbers if both of them are, as strings
+    /// otherwise, columns being only parsed when compared with a number or
+    /// another column.
+    fn compare(&self, other: &Self) -> Ordering {
+        if !matches!(self, Self::String(_)) && !matches!(other, Self::String(_)) {
+            if let (Some(left), Some(right)) = (self.as_number(), other.as_number()) {
+                return left.cmp(&right);
+            }
+        }
+        self.as_str().cmp(&other.as_str())
+    }
+
+    fn as_number(&self) -> Option<Decimal> {
+        match self {
+            Self::Column(value) => value.as_ref()?.as_ref().parse().ok(),
+            Self::Number(number) => Some(*number),
+            Self::String(_) => None,
+        }
+    }
+
+    fn as_str(&self) -> Cow<'_, str> {
+        match self {
+            Self::Column(value) => value.as_ref().map_or("", AsRef::as_ref).into(),
+            Self::Number(number) => number.to_string().into(),
+            Self::String(string) => (*string).into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Not,
+    And,
+    Or,
+    Operator(Operator),
+    Operand(Operand),
+}
+
+/// Split the filter into tokens, checking the column names.
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut chars = s.chars().peekable();
+    let mut tokens = Vec::new();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' | '|' => {
+                if chars.next_if_eq(&c).is_none() {
+                    return Err(format!("Expected {c}{c} in filter"));
+                }
+                if c == '&' {
+                    Token::And
+                } else {
+                    Token::Or
+                }
+            }
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Ne),
+            '!' => Token::Not,
+            '=' => {
+                chars.next_if_eq(&'=');
+                Token::Operator(Operator::Eq)
+            }
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Le),
+            '<' => Token::Operator(Operator::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Ge),
+            '>' => Token::Operator(Operator::Gt),
+            '\'' | '"' => {
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some(next) if next == c => break Token::Operand(Operand::String(literal)),
+                        Some(next) => literal.push(next),
+                        None => return Err("Unterminated string in filter".into()),
+                    }
+                }
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let number = take_word(c, &mut chars, |next| next.is_ascii_digit() || next == '.');
+                let number = number
+                    .parse()
+                    .map_err(|_| format!("Invalid number in filter: {number}"))?;
+                Token::Operand(Operand::Number(number))
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let name = take_word(c, &mut chars, |next| next.is_alphanumeric() || next == '_');
+                Token::Operand(Operand::Column(name.parse()?))
+            }
+            c => return Err(format!("Unexpected character in filter: {c}")),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Collect the word starting with the character, as long as the following
+/// ones satisfy the predicate.
+fn take_word(first: char, chars: &mut Peekable<Chars>, predicate: impl Fn(char) -> bool) -> String {
+    let mut word = String::from(first);
+    while let Some(next) = chars.next_if(|&next| predicate(next)) {
+        word.push(next);
+    }
+    word
+}
+
+/// A recursive descent parser, `!` binds tighter than `&&`, which binds
+/// tighter than `||`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_if_eq(&mut self, token: &Token) -> bool {
+        let matches = self.tokens.get(self.position) == Some(token);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+        while self.next_if_eq(&Token::Or) {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.unary()?;
+        while self.next_if_eq(&Token::And) {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        if self.next_if_eq(&Token::Not) {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if self.next_if_eq(&Token::Open) {
+            let filter = self.or()?;
+            if !self.next_if_eq(&Token::Close) {
+                return Err("Expected ) in filter".into());
+            }
+            return Ok(filter);
+        }
+
+        let left = self.operand()?;
+        let operator = match self.next() {
+            Some(Token::Operator(operator)) => operator,
+            Some(token) => return Err(format!("Expected an operator, found {token:?}")),
+            None => return Err("Unexpected end of filter".into()),
+        };
+        Ok(Filter::Compare(left, operator, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Operand(operand)) => Ok(operand),
+            Some(token) => Err(format!("Expected a column or a value, found {token:?}")),
+            None => Err("Unexpected end of filter".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction_kind::TransactionKind;
+
+    #[test]
+    fn test_matches() {
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 42, 1, Some(dec!(1.50)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 7, 1, None);
+        let matches = |filter: &str, tx| filter.parse::<Filter>().unwrap().matches_transaction(tx);
+
+        // Numbers are compared as such, missing values are empty
+        assert!(matches("amount == 1.5 && amount < 10", &deposit_tx));
+        assert!(matches("amount = ''", &dispute_tx));
+        assert!(!matches("amount >= 0", &dispute_tx));
+
+        // Strings are never compared as numbers
+        assert!(!matches("amount == '1.5'", &deposit_tx));
+        assert!(matches("amount == '1.50'", &deposit_tx));
+
+        // Conjunctions bind tighter than disjunctions, negations tighter still
+        let filter = "client == 7 || client == 42 && !(type = \"deposit\")";
+        assert!(matches(filter, &dispute_tx));
+        assert!(!matches(filter, &deposit_tx));
+        assert!(matches("kind != 'dispute' && tx <= 1", &deposit_tx));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (filter, error) in [
+            ("account == 1", "Unknown column: account"),
+            ("client = 'unterminated", "Unterminated string in filter"),
+            ("client & 1", "Expected && in filter"),
+            ("(client == 1", "Expected ) in filter"),
+            ("client ==", "Unexpected end of filter"),
+            ("amount > 1.2.3", "Invalid number in filter: 1.2.3"),
+        ] {
+            assert_eq!(filter.parse::<Filter>(), Err(error.into()));
+        }
+    }
+}
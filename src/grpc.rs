@@ -0,0 +1,190 @@
+//! A gRPC service over an `AsyncPaymentsEngine`, enabled by the `grpc`
+//! feature. The protobuf definitions are in `proto/payments.proto`.
+
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::{account::Account, async_engine::AsyncPaymentsEngine, transaction::Transaction};
+
+/// The protobuf messages and the generated client and server.
+#[allow(clippy::all, clippy::pedantic, clippy::nursery)]
+pub mod proto {
+    tonic::include_proto!("payments");
+}
+
+/// Implementation of the `Payments` service.
+///
+/// # Example
+/// ```no_run
+/// use payments::async_engine::AsyncPaymentsEngine;
+/// use payments::grpc::PaymentsService;
+/// use payments::payments_engine::PaymentsEngine;
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+/// tonic::transport::Server::builder()
+///     .add_service(PaymentsService::new(engine).into_server())
+///     .serve("127.0.0.1:50051".parse().unwrap())
+///     .await
+///     .unwrap();
+/// # });
+/// ```
+pub struct PaymentsService {
+    engine: AsyncPaymentsEngine,
+}
+
+impl PaymentsService {
+    #[must_use]
+    pub const fn new(engine: AsyncPaymentsEngine) -> Self {
+        Self { engine }
+    }
+
+    /// Wrap the service in its generated server, to be added to a router.
+    #[must_use]
+    pub fn into_server(self) -> proto::payments_server::PaymentsServer<Self> {
+        proto::payments_server::PaymentsServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::payments_server::Payments for PaymentsService {
+    async fn submit_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<proto::SubmitSummary>, Status> {
+        let mut stream = request.into_inner();
+        let mut summary = proto::SubmitSummary::default();
+
+        while let Some(message) = stream.message().await? {
+            let tx = Transaction::try_from(message)?;
+            let id = tx.id;
+            match self.engine.execute(tx).await {
+                Ok(()) => summary.accepted += 1,
+                Err(error) => summary.rejections.push(proto::Rejection {
+                    tx: id,
+                    code: error.code().into(),
+                    reason: error.to_string(),
+                }),
+            }
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let id = client_id(request.into_inner().id).map_err(Status::invalid_argument)?;
+        let account = self.engine.account(id).await;
+        account
+            .map(|account| Response::new(account.into()))
+            .ok_or_else(|| Status::not_found(format!("unknown client {id}")))
+    }
+
+    async fn list_accounts(
+        &self,
+        _request: Request<proto::ListAccountsRequest>,
+    ) -> Result<Response<proto::ListAccountsResponse>, Status> {
+        let accounts = self.engine.accounts().await;
+        let accounts = accounts.into_iter().map(Into::into).collect();
+        Ok(Response::new(proto::ListAccountsResponse { accounts }))
+    }
+}
+
+impl TryFrom<proto::Transaction> for Transaction {
+    type Error = Status;
+
+    fn try_from(message: proto::Transaction) -> Result<Self, Self::Error> {
+        let kind = message.r#type.parse().map_err(Status::invalid_argument)?;
+        let amount = message
+            .amount
+            .map(|amount| Decimal::from_str(&amount))
+            .transpose()
+            .map_err(|error| Status::invalid_argument(format!("invalid amount: {error}")))?;
+
+        let client = client_id(message.client).map_err(Status::invalid_argument)?;
+        let mut tx = Self::new(kind, client, message.tx, amount);
+        tx.to_client = message
+            .to_client
+            .map(client_id)
+            .transpose()
+            .map_err(Status::invalid_argument)?;
+        tx.reference = message.reference;
+        tx.memo = message.memo;
+        Ok(tx)
+    }
+}
+
+impl From<Account> for proto::Account {
+    fn from(account: Account) -> Self {
+        Self {
+            id: account.id.into(),
+            available: account.available.to_string(),
+            held: account.held.to_string(),
+            total: account.total.to_string(),
+            locked: account.locked,
+        }
+    }
+}
+
+/// Check a protobuf client id fits the engine ones.
+fn client_id(id: u32) -> Result<u16, String> {
+    u16::try_from(id).map_err(|_| format!("invalid client {id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    use super::*;
+    use crate::payments_engine::PaymentsEngine;
+
+    #[tokio::test]
+    async fn test_service() {
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(PaymentsService::new(engine).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut client = proto::payments_client::PaymentsClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+
+        // Stream a deposit and a withdrawal which can't be covered
+        let transaction = |r#type: &str, tx, amount: &str| proto::Transaction {
+            r#type: r#type.into(),
+            client: 1,
+            tx,
+            amount: Some(amount.into()),
+            ..proto::Transaction::default()
+        };
+        let transactions = vec![
+            transaction("deposit", 1, "1.5"),
+            transaction("withdrawal", 2, "2"),
+        ];
+        let summary = client
+            .submit_transactions(tokio_stream::iter(transactions))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.accepted, 1);
+        assert_eq!(summary.rejections[0].code, "PE001");
+
+        // Query the accounts
+        let request = proto::GetAccountRequest { id: 1 };
+        let account = client.get_account(request).await.unwrap().into_inner();
+        assert_eq!(account.available, "1.5");
+        let request = proto::GetAccountRequest { id: 2 };
+        let status = client.get_account(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let request = proto::ListAccountsRequest {};
+        let accounts = client.list_accounts(request).await.unwrap().into_inner();
+        assert_eq!(accounts.accounts.len(), 1);
+    }
+}
@@ -0,0 +1,164 @@
+//! The identifiers of the clients and of the transactions.
+//!
+//! The engine and the types it works with are generic over them, defaulting
+//! to the `u16` clients and `u32` transactions of the CSV format, so that the
+//! engine can be embedded in systems using wider ids. The constructors such as
+//! `PaymentsEngine::new` or `Account::new` take the default ids, engines with
+//! other ones are created with `default`, `from_reader` or by collecting their
+//! accounts, and accounts with `Account::open`:
+//!
+//! ```
+//! use payments::account::Account;
+//! use payments::payments_engine::PaymentsEngine;
+//! use payments::transaction::Transaction;
+//! use payments::transaction_kind::TransactionKind;
+//! use rust_decimal_macros::dec;
+//!
+//! let mut engine = PaymentsEngine::<u64, u128>::default();
+//! let tx = Transaction::new(TransactionKind::Deposit, 1 << 40, 1 << 100, Some(dec!(2)));
+//! engine.execute(tx).unwrap();
+//!
+//! let account: &Account<u64> = engine.account(1 << 40).unwrap();
+//! assert_eq!(account.total, dec!(2));
+//! ```
+
+use std::{
+    fmt::{Debug, Display},
+    hash::Hash,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The identifier of a client, which its transactions are routed by.
+pub trait ClientId:
+    Copy
+    + Eq
+    + Ord
+    + Hash
+    + Debug
+    + Display
+    + Default
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+    + 'static
+{
+    /// Get the shard of the client among the given number of shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no shards.
+    fn shard(self, shards: usize) -> usize;
+}
+
+macro_rules! client_id {
+    ($($id:ty),*) => {
+        $(impl ClientId for $id {
+            fn shard(self, shards: usize) -> usize {
+                let shards = u64::try_from(shards).unwrap_or(u64::MAX);
+                // The remainder is lower than the shards, so it fits a usize
+                usize::try_from(u64::from(self) % shards).unwrap_or_default()
+            }
+        })*
+    };
+}
+
+client_id!(u16, u32, u64);
+
+/// The identifier of a transaction, unique across the clients.
+///
+/// Any type having the required traits is one, e.g. `uuid::Uuid` with its
+/// `serde` feature. The default value is the id of the rows which don't have
+/// any, such as balance assertions.
+pub trait TxId:
+    Copy
+    + Eq
+    + Ord
+    + Hash
+    + Debug
+    + Display
+    + Default
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl<T> TxId for T where
+    T: Copy
+        + Eq
+        + Ord
+        + Hash
+        + Debug
+        + Display
+        + Default
+        + Serialize
+        + DeserializeOwned
+        + Send
+        + Sync
+        + 'static
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        engine_event::EngineEvent, payments_engine::PaymentsEngine, reconciliation,
+        transaction::Transaction,
+    };
+
+    #[test]
+    fn test_wide_ids() {
+        const CLIENT: u64 = u64::MAX;
+        const TX: u128 = u128::MAX;
+
+        let data = format!(
+            "type,client,tx,amount,to_client\n\
+             deposit,{CLIENT},{TX},3,\n\
+             deposit,{CLIENT},1,2,\n\
+             transfer,{CLIENT},2,1,2\n\
+             dispute,{CLIENT},{TX},,\n\
+             assert_balance,2,,1,\n"
+        );
+        let txs: Vec<Transaction<u64, u128>> = csv::Reader::from_reader(data.as_bytes())
+            .deserialize()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(txs[4].id, 0);
+
+        let mut engine = PaymentsEngine::<u64, u128>::default();
+        let events = engine.subscribe();
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        let account = engine.account(CLIENT).unwrap();
+        assert_eq!((account.available, account.held), (dec!(1), dec!(3)));
+        assert!(events.try_iter().any(|event| event
+            == EngineEvent::DisputeOpened {
+                client: CLIENT,
+                tx: TX,
+                amount: dec!(3),
+                account_version: 4,
+            }));
+        assert!(reconciliation::reconcile(&engine).is_empty());
+
+        // The state is saved along with the wide ids
+        let path = std::env::temp_dir().join("payments-test-wide-ids.json");
+        engine.save(&path).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let restored = PaymentsEngine::<u64, u128>::from_reader(file).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.account(CLIENT), engine.account(CLIENT));
+        assert!(restored.transaction(TX).is_some());
+
+        // Clients are routed by their id, whatever its width
+        assert_eq!(CLIENT.shard(16), 15);
+        assert_eq!(17u16.shard(16), 1);
+    }
+}
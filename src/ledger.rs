@@ -0,0 +1,437 @@
+//! Double-entry ledger backing the balances of the `PaymentsEngine`.
+//!
+//! Every applied transaction is journaled as balanced postings: the funds of
+//! the clients are liabilities, split between their available and held
+//! accounts, and every change of their balances is offset by another client
+//! or by an internal account. The accounts of the engine are then derived
+//! from the postings, which the trial balance proves.
+
+use std::{collections::BTreeMap, fmt};
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    admin::AdminAction,
+    audit_log::AuditEntry,
+    ids::{ClientId, TxId},
+    transaction_kind::TransactionKind,
+};
+
+/// An account of the ledger, client funds being credit-normal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount<C = u16> {
+    /// The funds available to the client.
+    Available(C),
+    /// The funds of the client held by disputes.
+    Held(C),
+    /// The funds deposited and withdrawn by the clients.
+    Settlement,
+    /// The funds charged back to the payers.
+    Chargebacks,
+    /// The fees charged to the clients.
+    Fees,
+    /// The funds credited or debited by the administrative adjustments.
+    Adjustments,
+    /// The funds without any other counterpart, e.g. the opening balances.
+    Suspense,
+}
+
+impl<C: ClientId> fmt::Display for LedgerAccount<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Available(client) => write!(f, "available:{client}"),
+            Self::Held(client) => write!(f, "held:{client}"),
+            Self::Settlement => write!(f, "settlement"),
+            Self::Chargebacks => write!(f, "chargebacks"),
+            Self::Fees => write!(f, "fees"),
+            Self::Adjustments => write!(f, "adjustments"),
+            Self::Suspense => write!(f, "suspense"),
+        }
+    }
+}
+
+/// A leg of a journal entry, either debiting or crediting the amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting<C = u16> {
+    pub account: LedgerAccount<C>,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+impl<C: ClientId> Posting<C> {
+    /// Post a signed change of a credit-normal account.
+    fn change(account: LedgerAccount<C>, change: Decimal) -> Option<Self> {
+        match change {
+            _ if change.is_zero() => None,
+            _ if change.is_sign_positive() => {
+                Some(Self { account, debit: dec!(0), credit: change })
+            }
+            _ => Some(Self { account, debit: -change, credit: dec!(0) }),
+        }
+    }
+}
+
+/// The balanced postings of a transaction, or of the opening balances or an
+/// administrative batch when it has no id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry<C = u16, T = u32> {
+    pub tx: Option<T>,
+    pub postings: Vec<Posting<C>>,
+}
+
+impl<C: ClientId, T: TxId> JournalEntry<C, T> {
+    /// Journal the changes of the accounts altered by a transaction, offset
+    /// by the fees account for the fee charged, then by the settlement for
+    /// deposits and withdrawals, by the chargebacks for chargebacks and by
+    /// the suspense account otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::audit_log::AuditEntry;
+    /// use payments::ledger::{JournalEntry, LedgerAccount};
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut account = Account::new(1);
+    /// account.deposit(dec!(1)).unwrap();
+    /// let change = AuditEntry::new(1, TransactionKind::Deposit, &Account::new(1), &account);
+    ///
+    /// let kind = TransactionKind::Deposit;
+    /// let entry = JournalEntry::new(Some(1), kind, &[change.unwrap()], dec!(0));
+    /// assert_eq!(entry.postings[0].account, LedgerAccount::Available(1));
+    /// assert_eq!(entry.postings[0].credit, dec!(1));
+    /// assert_eq!(entry.postings[1].account, LedgerAccount::Settlement);
+    /// assert_eq!(entry.postings[1].debit, dec!(1));
+    /// ```
+    #[must_use]
+    pub fn new(
+        tx: Option<T>,
+        kind: TransactionKind,
+        changes: &[AuditEntry<C, T>],
+        fee: Decimal,
+    ) -> Self {
+        let mut postings = Vec::new();
+        let mut net = dec!(0);
+        for change in changes {
+            let available = change
+                .available_after
+                .saturating_sub(change.available_before);
+            let held = change.held_after.saturating_sub(change.held_before);
+            postings.extend(Posting::change(
+                LedgerAccount::Available(change.client),
+                available,
+            ));
+            postings.extend(Posting::change(LedgerAccount::Held(change.client), held));
+            net = net.saturating_add(available).saturating_add(held);
+        }
+
+        let counterpart = match kind {
+            TransactionKind::Deposit | TransactionKind::Withdrawal => LedgerAccount::Settlement,
+            TransactionKind::Chargeback => LedgerAccount::Chargebacks,
+            _ => LedgerAccount::Suspense,
+        };
+        postings.extend(Posting::change(LedgerAccount::Fees, fee));
+        postings.extend(Posting::change(counterpart, -net.saturating_add(fee)));
+
+        Self { tx, postings }
+    }
+
+    /// Journal the adjustments of an administrative batch, offset by the
+    /// adjustments account, the other actions not moving any funds.
+    #[must_use]
+    pub fn adjustments(actions: &[AdminAction<C>]) -> Self {
+        let mut postings = Vec::new();
+        let mut net = dec!(0);
+        for action in actions {
+            if let AdminAction::Adjust { client, amount } = *action {
+                postings.extend(Posting::change(LedgerAccount::Available(client), amount));
+                net = net.saturating_add(amount);
+            }
+        }
+        postings.extend(Posting::change(LedgerAccount::Adjustments, -net));
+
+        Self { tx: None, postings }
+    }
+
+    /// Journal the balances of an account seeded without any transaction,
+    /// offset by the suspense account.
+    #[must_use]
+    pub fn opening(account: &Account<C>) -> Self {
+        let net = account.available.saturating_add(account.held);
+        let postings = [
+            Posting::change(LedgerAccount::Available(account.id), account.available),
+            Posting::change(LedgerAccount::Held(account.id), account.held),
+            Posting::change(LedgerAccount::Suspense, -net),
+        ];
+
+        Self {
+            tx: None,
+            postings: postings.into_iter().flatten().collect(),
+        }
+    }
+}
+
+/// The balances of the ledger accounts, credits net of debits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger<C = u16> {
+    balances: BTreeMap<LedgerAccount<C>, Decimal>,
+}
+
+impl<C: ClientId> Ledger<C> {
+    /// Post the legs of the entry. The client balances are checked by the
+    /// accounts already, the internal ones saturate instead of overflowing.
+    pub fn post<T: TxId>(&mut self, entry: &JournalEntry<C, T>) {
+        for posting in &entry.postings {
+            let balance = self.balances.entry(posting.account).or_default();
+            *balance = balance
+                .saturating_add(posting.credit)
+                .saturating_sub(posting.debit);
+        }
+    }
+
+    /// Get the balance of a ledger account, credits net of debits.
+    #[must_use]
+    pub fn balance(&self, account: LedgerAccount<C>) -> Decimal {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    /// Iterate over the ledger accounts having been posted to, in order.
+    pub fn balances(&self) -> impl Iterator<Item = (LedgerAccount<C>, Decimal)> + '_ {
+        self.balances
+            .iter()
+            .map(|(account, balance)| (*account, *balance))
+    }
+
+    /// Derive the funds of a client from the postings. The lock flag and the
+    /// version aren't ledger concerns, and are left to their default.
+    #[must_use]
+    pub fn account(&self, client: C) -> Account<C> {
+        let mut account = Account::open(client);
+        account.available = self.balance(LedgerAccount::Available(client));
+        account.held = self.balance(LedgerAccount::Held(client));
+        account.total = account.available.saturating_add(account.held);
+        account
+    }
+
+    /// Restore the balances saved from `Ledger::balances`.
+    pub(crate) fn from_balances(
+        balances: impl IntoIterator<Item = (LedgerAccount<C>, Decimal)>,
+    ) -> Self {
+        Self { balances: balances.into_iter().collect() }
+    }
+
+    /// Move the balances of another ledger into this one.
+    pub(crate) fn merge(&mut self, other: Self) {
+        for (account, balance) in other.balances {
+            let merged = self.balances.entry(account).or_default();
+            *merged = merged.saturating_add(balance);
+        }
+    }
+
+    /// Get the debit and credit balances of every ledger account, proving
+    /// the given accounts against the postings.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction::Transaction;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)))).unwrap();
+    /// engine.execute(Transaction::new(TransactionKind::Dispute, 1, 1, None)).unwrap();
+    ///
+    /// let trial_balance = engine.ledger().trial_balance(engine.accounts());
+    /// assert!(trial_balance.is_balanced());
+    /// assert_eq!((trial_balance.debits, trial_balance.credits), (dec!(2), dec!(2)));
+    /// ```
+    pub fn trial_balance<'a>(
+        &self,
+        accounts: impl IntoIterator<Item = &'a Account<C>>,
+    ) -> TrialBalance<C> {
+        let mut trial_balance = TrialBalance::default();
+        for (account, balance) in self.balances() {
+            let (debit, credit) = if balance.is_sign_negative() {
+                (-balance, dec!(0))
+            } else {
+                (dec!(0), balance)
+            };
+            trial_balance.debits = trial_balance.debits.saturating_add(debit);
+            trial_balance.credits = trial_balance.credits.saturating_add(credit);
+            trial_balance
+                .rows
+                .push(TrialBalanceRow { account, debit, credit });
+        }
+
+        for account in accounts {
+            let derived = self.account(account.id);
+            if (derived.available, derived.held) != (account.available, account.held) {
+                trial_balance.mismatches.push(account.id);
+            }
+        }
+        trial_balance.mismatches.sort_unstable();
+
+        trial_balance
+    }
+}
+
+/// A row of the trial balance, stating the balance of a ledger account on
+/// its side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrialBalanceRow<C = u16> {
+    pub account: LedgerAccount<C>,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// The balances of the ledger along with their totals, and the clients whose
+/// account doesn't match the postings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrialBalance<C = u16> {
+    pub rows: Vec<TrialBalanceRow<C>>,
+    pub debits: Decimal,
+    pub credits: Decimal,
+    pub mismatches: Vec<C>,
+}
+
+impl<C> TrialBalance<C> {
+    /// Check whether the debits equal the credits and every account matches
+    /// the postings.
+    #[must_use]
+    pub fn is_balanced(&self) -> bool {
+        self.debits == self.credits && self.mismatches.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payments_engine::PaymentsEngine, transaction::Transaction};
+
+    #[test]
+    fn test_ledger() {
+        let mut opening = Account::new(3);
+        opening.deposit(dec!(5)).unwrap();
+        let mut engine = PaymentsEngine::with_accounts([opening]);
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(3))),
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(1))).with_recipient(2),
+            Transaction::new(TransactionKind::Withdrawal, 2, 3, Some(dec!(0.5))),
+            Transaction::new(TransactionKind::Dispute, 1, 1, None),
+            Transaction::new(TransactionKind::Withdrawal, 1, 4, Some(dec!(1))),
+        ];
+        for tx in txs {
+            let _ = engine.execute(tx);
+        }
+        engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                1,
+                5,
+                Some(dec!(2)),
+            ))
+            .unwrap();
+        engine
+            .execute(Transaction::new(TransactionKind::Dispute, 1, 5, None))
+            .unwrap();
+        engine
+            .execute(Transaction::new(TransactionKind::Chargeback, 1, 5, None))
+            .unwrap();
+
+        // The accounts are derived from the postings
+        let ledger = engine.ledger();
+        for account in engine.accounts() {
+            let derived = ledger.account(account.id);
+            assert_eq!(
+                (derived.available, derived.held),
+                (account.available, account.held)
+            );
+        }
+        assert_eq!(ledger.balance(LedgerAccount::Settlement), dec!(-3.5));
+        assert_eq!(ledger.balance(LedgerAccount::Chargebacks), dec!(2));
+        assert_eq!(ledger.balance(LedgerAccount::Suspense), dec!(-5));
+
+        let trial_balance = ledger.trial_balance(engine.accounts());
+        assert!(trial_balance.is_balanced());
+        assert_eq!(trial_balance.debits, dec!(8.5));
+
+        // The ledger is part of the saved state
+        let path = std::env::temp_dir().join("payments-test-ledger.json");
+        engine.save(&path).unwrap();
+        assert_eq!(
+            PaymentsEngine::load(&path).unwrap().ledger(),
+            engine.ledger()
+        );
+
+        // Altering an account outside of the engine is caught
+        engine
+            .accounts
+            .get_mut(&2)
+            .unwrap()
+            .deposit(dec!(1))
+            .unwrap();
+        let trial_balance = engine.ledger().trial_balance(engine.accounts());
+        assert_eq!(trial_balance.mismatches, vec![2]);
+    }
+
+    #[test]
+    fn test_imbalance() {
+        // An empty ledger is balanced, and holds no funds
+        let mut ledger = Ledger::default();
+        assert!(ledger.trial_balance([]).is_balanced());
+        assert_eq!(ledger.account(1).total, dec!(0));
+
+        // Entries journaled by the engine are balanced, adjustments included
+        let actions = [
+            AdminAction::Adjust { client: 1, amount: dec!(2) },
+            AdminAction::Lock { client: 1 },
+            AdminAction::Adjust { client: 2, amount: dec!(-0.5) },
+        ];
+        ledger.post(&JournalEntry::<u16, u32>::adjustments(&actions));
+        assert_eq!(ledger.balance(LedgerAccount::Adjustments), dec!(-1.5));
+        let trial_balance = ledger.trial_balance([]);
+        assert!(trial_balance.is_balanced());
+        assert_eq!(
+            (trial_balance.debits, trial_balance.credits),
+            (dec!(2), dec!(2))
+        );
+
+        // While a one-sided entry leaves the debits and the credits apart
+        let posting = Posting {
+            account: LedgerAccount::Available(1),
+            debit: dec!(0),
+            credit: dec!(1),
+        };
+        ledger.post(&JournalEntry::<u16, u32> { tx: Some(1), postings: vec![posting] });
+        let trial_balance = ledger.trial_balance([]);
+        assert!(!trial_balance.is_balanced());
+        assert!(trial_balance.mismatches.is_empty());
+        assert_eq!(
+            (trial_balance.debits, trial_balance.credits),
+            (dec!(2), dec!(3))
+        );
+    }
+
+    #[test]
+    fn test_saturation() {
+        // Internal accounts saturate instead of overflowing
+        let mut ledger = Ledger::default();
+        let mut account = Account::new(1);
+        account.deposit(Decimal::MAX).unwrap();
+        for _ in 0..2 {
+            ledger.post(&JournalEntry::<u16, u32>::opening(&account));
+        }
+        assert_eq!(ledger.balance(LedgerAccount::Suspense), Decimal::MIN);
+        assert_eq!(ledger.balance(LedgerAccount::Available(1)), Decimal::MAX);
+
+        // Empty accounts aren't journaled at all
+        let entry = JournalEntry::<u16, u32>::opening(&Account::new(2));
+        assert!(entry.postings.is_empty());
+    }
+}
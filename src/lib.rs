@@ -1,4 +1,43 @@
+// The engine processes untrusted input, which must never make it panic
+#![cfg_attr(
+    not(test),
+    deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)
+)]
+
 pub mod account;
+pub mod account_report;
+pub mod admin;
+pub mod aggregation;
+#[cfg(feature = "tokio")]
+pub mod async_engine;
+pub mod audit_log;
+pub mod canary;
+pub mod client;
+pub mod control_totals;
+pub mod dispute_state;
+pub mod engine_config;
+pub mod engine_event;
+pub mod escheatment;
+pub mod evaluation;
+pub mod filter;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod ids;
+pub mod ledger;
+pub mod money;
+#[cfg(feature = "parquet")]
+pub mod parquet_io;
 pub mod payments_engine;
+pub mod prelude;
+pub mod reconciliation;
+pub mod reject_handler;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sharded_engine;
+mod snapshot;
+pub mod soak;
+pub mod summary;
 pub mod transaction;
+pub mod transaction_error;
 pub mod transaction_kind;
+pub mod webhook;
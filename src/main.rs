@@ -1,42 +1,2713 @@
-use std::{error::Error, fs::File, env, io};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet},
+    env,
+    error::Error,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    mem,
+    path::Path,
+    process,
+    str::FromStr,
+    sync::mpsc::Receiver,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use csv::ReaderBuilder;
-use payments::{transaction::Transaction, payments_engine::PaymentsEngine};
+use clap::{Args, Parser, Subcommand};
+use csv::{Position, Reader, ReaderBuilder, StringRecord};
+use payments::{
+    account::Account,
+    account_report::{AccountField, ReportFormat},
+    admin::AdminAction,
+    aggregation,
+    audit_log::AuditEntry,
+    canary, client, control_totals,
+    engine_config::{
+        DuplicatePolicy, EngineConfig, ExcessPrecisionPolicy, Fee, LockedAccountPolicy,
+        OutOfOrderPolicy, RedisputePolicy, WithdrawalDisputePolicy, SPEC_DECIMALS,
+    },
+    engine_event::EngineEvent,
+    escheatment, evaluation,
+    filter::{Column, Filter},
+    money::{Currency, Locale},
+    payments_engine::PaymentsEngine,
+    reconciliation, sharded_engine,
+    soak::{self, Generator, Histogram},
+    summary,
+    transaction::Transaction,
+    transaction_error::TransactionError,
+    transaction_kind::TransactionKind,
+    webhook::{self, DeliveryStats, Webhook},
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// A row of the disputes report.
+#[derive(Serialize)]
+struct DisputeRow<'a> {
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    reference: Option<&'a str>,
+}
+
+/// A row of the outstanding negative balances report.
+#[derive(Serialize)]
+struct DeficitRow {
+    client: u16,
+    deficit: Decimal,
+}
+
+/// A row of the fees report.
+#[derive(Serialize)]
+struct FeeRow {
+    client: u16,
+    fees: Decimal,
+}
+
+/// A row of the aged funds report.
+#[derive(Serialize)]
+struct AgedRow<'a> {
+    jurisdiction: Option<&'a str>,
+    client: u16,
+    last_activity: i64,
+    amount: Decimal,
+}
+
+/// A row of the trial balance report.
+#[derive(Serialize)]
+struct TrialBalanceRow {
+    account: String,
+    debit: Decimal,
+    credit: Decimal,
+}
+
+/// A row of the diagnostics file, the line and the client are only set when
+/// relevant.
+#[derive(Serialize)]
+struct DiagnosticRow<'a> {
+    line: Option<u64>,
+    client: Option<u16>,
+    code: Option<&'a str>,
+    message: String,
+}
+
+/// Where per-row diagnostics are reported, either the standard error or a
+/// CSV file.
+enum Diagnostics {
+    Stderr,
+    Csv(Box<csv::Writer<File>>),
+}
+
+impl Diagnostics {
+    /// Report to the file, if any, otherwise to the standard error.
+    fn create(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        Ok(match path {
+            Some(path) => Self::Csv(Box::new(csv::Writer::from_path(path)?)),
+            None => Self::Stderr,
+        })
+    }
+
+    fn report(
+        &mut self,
+        line: Option<u64>,
+        client: Option<u16>,
+        code: Option<&str>,
+        message: &dyn Display,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Stderr => {
+                let prefix = match (line, client) {
+                    (Some(line), Some(client)) => format!("Line {line}, client {client}: "),
+                    (Some(line), None) => format!("Line {line}: "),
+                    (None, Some(client)) => format!("Client {client}: "),
+                    (None, None) => String::new(),
+                };
+                let code = code.map_or(String::new(), |code| format!("{code} "));
+                eprintln!("{prefix}{code}{message}");
+            }
+            Self::Csv(writer) => writer.serialize(DiagnosticRow {
+                line,
+                client,
+                code,
+                message: message.to_string(),
+            })?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Self::Csv(writer) = self {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// A row of the summary report.
+#[derive(Serialize)]
+struct SummaryRow {
+    metric: &'static str,
+    value: String,
+}
+
+/// A row of the file of an administrative batch, the amount being only
+/// used by adjustments.
+#[derive(Deserialize)]
+struct AdminRow {
+    client: u16,
+    amount: Option<Decimal>,
+}
+
+/// A row of the account tags sidecar file.
+#[derive(Deserialize)]
+struct TagRow {
+    client: u16,
+    tag: String,
+}
+
+/// Writer of the audit log, as CSV or as JSON lines.
+enum AuditLogFile {
+    Csv(Box<csv::Writer<File>>),
+    Json(io::BufWriter<File>),
+}
+
+impl AuditLogFile {
+    /// Create the audit log file in the format of the accounts output.
+    fn create(path: &str, format: Format) -> Result<Self, Box<dyn Error>> {
+        match format {
+            Format::Csv => Ok(Self::Csv(Box::new(csv::Writer::from_path(path)?))),
+            Format::Json => Ok(Self::Json(io::BufWriter::new(File::create(path)?))),
+            Format::Parquet => Err("--audit-log requires CSV or JSON output".into()),
+        }
+    }
+
+    /// Write the entries received from the engine since the last call.
+    fn write(&mut self, entries: &Receiver<AuditEntry>) -> Result<(), Box<dyn Error>> {
+        for entry in entries.try_iter() {
+            match self {
+                Self::Csv(writer) => writer.serialize(entry)?,
+                Self::Json(writer) => {
+                    serde_json::to_writer(&mut *writer, &entry)?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Csv(writer) => writer.flush(),
+            Self::Json(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Writer of the records which failed to be parsed or applied, along with
+/// their error. The file can be processed again once the issues are fixed.
+struct Quarantine {
+    writer: csv::Writer<File>,
+    width: usize,
+    error_column: usize,
+}
+
+impl Quarantine {
+    /// Create the quarantine file, the `error` column is appended to the
+    /// input headers unless they already have one (e.g. when requeuing).
+    fn create(path: &str, headers: &StringRecord) -> Result<Self, Box<dyn Error>> {
+        let mut headers = headers.clone();
+        let error_column = match headers.iter().position(|header| header == "error") {
+            Some(column) => column,
+            None => {
+                headers.push_field("error");
+                headers.len() - 1
+            }
+        };
+
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(&headers)?;
+        Ok(Self { writer, width: headers.len(), error_column })
+    }
+
+    /// Write the record along with its error code and message.
+    fn write(
+        &mut self,
+        record: &StringRecord,
+        code: &str,
+        error: &dyn Display,
+    ) -> Result<(), Box<dyn Error>> {
+        let error = format!("{code} {error}");
+        let fields = (0..self.width).map(|column| match column {
+            _ if column == self.error_column => error.as_str(),
+            _ => record.get(column).unwrap_or_default(),
+        });
+
+        self.writer.write_record(fields)?;
+        Ok(())
+    }
+}
+
+/// The format of the transactions input and the accounts output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Format {
+    /// Comma-separated values with headers, this is the default.
+    #[default]
+    Csv,
+    /// A JSON object per line.
+    Json,
+    /// Apache Parquet, available with the `parquet` feature.
+    Parquet,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(format!("Unknown format: {s}")),
+        }
+    }
+}
+
+/// The format of the logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum LogFormat {
+    /// Human-readable lines, this is the default.
+    #[default]
+    Text,
+    /// A JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown log format: {s}")),
+        }
+    }
+}
+
+/// What the transactions are split by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum SplitKey {
+    /// The client id, routed the same way as by `ShardedEngine`.
+    #[default]
+    Client,
+}
+
+impl FromStr for SplitKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Self::Client),
+            _ => Err(format!("Unknown split key: {s}")),
+        }
+    }
+}
+
+/// A row of the differences between two builds.
+#[derive(Serialize)]
+struct DifferenceRow<'a> {
+    client: u16,
+    column: &'a str,
+    baseline: Option<&'a str>,
+    candidate: Option<&'a str>,
+}
+
+/// A row of the statistics of a soak test, the latencies being the ones of
+/// the transactions of the interval.
+#[derive(Serialize)]
+struct SoakRow {
+    elapsed: u64,
+    transactions: u64,
+    rate: u64,
+    resident_memory: Option<u64>,
+    p50_us: f64,
+    p99_us: f64,
+    p999_us: f64,
+    max_us: f64,
+    save_ms: Option<u128>,
+}
+
+/// A row of the comparison of the evaluated configurations.
+#[derive(Serialize)]
+struct EvaluationRow<'a> {
+    config: &'a str,
+    applied: usize,
+    rejected: usize,
+    rejected_amount: Decimal,
+    locked_accounts: usize,
+    clients: usize,
+    total: Decimal,
+}
+
+/// A row of the final accounts of the evaluated configurations.
+#[derive(Serialize)]
+struct EvaluatedAccountRow<'a> {
+    config: &'a str,
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// A row of the discrepancies found by a reconciliation.
+#[derive(Serialize)]
+struct DiscrepancyRow {
+    client: Option<u16>,
+    check: &'static str,
+    expected: Decimal,
+    actual: Decimal,
+}
+
+/// A row of the manifest of a split, the total being the sum of the control
+/// totals of its clients when given.
+#[derive(Serialize, Deserialize)]
+struct ShardRow {
+    shard: usize,
+    file: String,
+    rows: u64,
+    clients: usize,
+    #[serde(default)]
+    total: Option<Decimal>,
+}
+
+/// The compression of the transactions input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression from the extension of the input file.
+    fn detect(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    /// Wrap the input in the matching decompressor.
+    fn decoder<'a>(self, input: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::None => Box::new(input),
+            Self::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Self::Zstd => Box::new(zstd::Decoder::new(input)?),
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("Unknown compression: {s}")),
+        }
+    }
+}
+
+/// An input record along with the transaction parsed from it. JSON lines are
+/// kept as single-field records and Parquet rows as empty ones, so that
+/// they're reported the same way.
+type Row = (StringRecord, Result<Transaction, Box<dyn Error>>);
+
+/// The records of an input, failing on I/O or CSV framing errors.
+type Rows<'a> = Box<dyn Iterator<Item = Result<Row, Box<dyn Error>>> + 'a>;
+
+/// The address the REST API is served on by default.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+/// The bounds of the delay before restarting a failed worker, see `supervise`.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// How long a worker has to be up for its failure not to count as a crash
+/// loop.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
+
+/// Code of the records which can't be parsed, following the
+/// `TransactionError` codes.
+const PARSE_ERROR_CODE: &str = "PE100";
+
+/// A row of the rejected transactions report.
+#[derive(Serialize)]
+struct RejectRow {
+    /// The line of the transaction in the input.
+    row: u64,
+    #[serde(rename = "type")]
+    kind: TransactionKind,
+    client: u16,
+    tx: u32,
+    code: &'static str,
+    reason: String,
+    reference: Option<String>,
+    memo: Option<String>,
+}
+
+impl RejectRow {
+    fn new(record: &StringRecord, tx: &Transaction) -> Self {
+        Self {
+            row: record.position().map_or(0, csv::Position::line),
+            kind: tx.kind,
+            client: tx.client_id,
+            tx: tx.id,
+            code: "",
+            reason: String::new(),
+            reference: tx.reference.clone(),
+            memo: tx.memo.clone(),
+        }
+    }
+}
+
+/// Where the records which failed to be parsed or applied are reported.
+struct Failures {
+    quarantine: Option<Quarantine>,
+    rejects: Option<csv::Writer<File>>,
+    /// The line and the error of the rows skipped for being invalid, if
+    /// they're skipped rather than failing the run.
+    skipped: Option<Vec<(u64, String)>>,
+}
+
+impl Failures {
+    /// Quarantine or skip a record which failed to be parsed, failing the run
+    /// if it's neither.
+    fn invalid(
+        &mut self,
+        record: &StringRecord,
+        error: Box<dyn Error>,
+    ) -> Result<(), Box<dyn Error>> {
+        match (&mut self.quarantine, &mut self.skipped) {
+            (Some(quarantine), _) => quarantine.write(record, PARSE_ERROR_CODE, &error),
+            (None, Some(skipped)) => {
+                let line = record.position().map_or(0, Position::line);
+                skipped.push((line, error.to_string()));
+                Ok(())
+            }
+            (None, None) => Err(error),
+        }
+    }
+
+    /// Skip a row which couldn't even be read as a record, e.g. because of
+    /// invalid UTF-8, failing the run on I/O errors or if rows aren't skipped.
+    fn unreadable(&mut self, error: Box<dyn Error>) -> Result<(), Box<dyn Error>> {
+        let position = match error.downcast_ref::<csv::Error>() {
+            Some(csv_error) if !csv_error.is_io_error() => csv_error.position(),
+            _ => return Err(error),
+        };
+        match &mut self.skipped {
+            Some(skipped) => {
+                skipped.push((position.map_or(0, Position::line), error.to_string()));
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+
+    /// Report the skipped rows along with their error, then print their
+    /// count.
+    fn report_skipped(&self, diagnostics: &mut Diagnostics) -> Result<(), Box<dyn Error>> {
+        let Some(skipped) = &self.skipped else {
+            return Ok(());
+        };
+        for (line, error) in skipped {
+            diagnostics.report(Some(*line), None, Some(PARSE_ERROR_CODE), error)?;
+        }
+        if !skipped.is_empty() {
+            eprintln!("Skipped {} invalid row(s)", skipped.len());
+        }
+        Ok(())
+    }
+
+    /// Report a transaction rejected by the engine, failed balance assertions
+    /// are checks rather than poisoned records and aren't quarantined.
+    fn rejected(
+        &mut self,
+        record: &StringRecord,
+        mut row: RejectRow,
+        error: &TransactionError,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(writer) = &mut self.rejects {
+            row.code = error.code();
+            row.reason = error.to_string();
+            writer.serialize(row)?;
+        }
+        match (&mut self.quarantine, error) {
+            (_, TransactionError::AssertionFailed { .. }) | (None, _) => Ok(()),
+            (Some(quarantine), _) => quarantine.write(record, error.code(), error),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(quarantine) = &mut self.quarantine {
+            quarantine.writer.flush()?;
+        }
+        if let Some(writer) = &mut self.rejects {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Process transaction files into client account balances.
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Log the transactions and the pipeline to the standard error, as text
+    /// or json, available with the `tracing` feature.
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+    /// The most verbose level logged: error, warn, info, debug or trace,
+    /// defaults to info.
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+    /// Arguments of the default `process` subcommand.
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process the transactions and print the accounts, this is the default.
+    ///
+    /// Requeuing a quarantine file processes it again, its `error` column is
+    /// ignored.
+    #[command(visible_alias = "requeue")]
+    Process(Box<ProcessArgs>),
+    /// Check that every transaction is well-formed, without processing them.
+    Validate(ValidateArgs),
+    /// Write the reports and the accounts of a saved engine state.
+    Report(ReportArgs),
+    /// Verify the invariants of every account of a saved engine state, then
+    /// print the discrepancies.
+    Reconcile(ReconcileArgs),
+    /// Serve the REST API of the engine until the process is stopped.
+    Serve(ServeArgs),
+    /// Partition the transactions into shard files, e.g. to process them in
+    /// parallel.
+    Split(SplitArgs),
+    /// Merge the account reports of the shards of a split into one, sorted
+    /// by client.
+    MergeReports(MergeReportsArgs),
+    /// Process the transactions with both a previous build and this one, then
+    /// print the differences of their accounts.
+    Canary(CanaryArgs),
+    /// Compare two accounts outputs or saved engine states, then print the
+    /// differences of their accounts.
+    Diff(DiffArgs),
+    /// Drive a synthetic load through the engine, periodically checking its
+    /// invariants and printing the memory and the latencies.
+    Soak(SoakArgs),
+    /// Process the transactions with the default configuration and every
+    /// given one concurrently, then print how they compare.
+    Evaluate(EvaluateArgs),
+    /// Lock the accounts of every client of the batch at once in a saved
+    /// engine state, then print the audit record of the batch.
+    Lock(AdminArgs),
+    /// Unlock the accounts of every client of the batch at once, see `lock`.
+    Unlock(AdminArgs),
+    /// Credit or debit the available funds of every client of the batch by
+    /// its `amount` at once, see `lock`.
+    Adjust(AdminArgs),
+    /// Describe the supported transaction types, flags and outputs.
+    Describe {
+        /// Print the description as JSON, the only supported format.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// The transactions input.
+#[derive(Args)]
+struct InputArgs {
+    /// The input file, `-` or none for the standard input.
+    file: Option<String>,
+    /// The format of the input and of the accounts output: csv, json or
+    /// parquet.
+    #[arg(long, default_value = "csv")]
+    format: Format,
+    /// The compression of the input, none, gzip or zstd, detected from its
+    /// extension if missing.
+    #[arg(long)]
+    compression: Option<Compression>,
+    /// Only read the rows matching the expression, e.g.
+    /// `client == 42 || type == 'chargeback'`.
+    #[arg(long)]
+    filter: Option<Filter>,
+}
+
+/// Where the engine state comes from.
+#[derive(Args)]
+struct StateArgs {
+    /// The engine state file, restored if it exists and saved at the end.
+    /// Only `process` and `serve` start afresh when it doesn't exist.
+    #[arg(long)]
+    state: Option<String>,
+    /// Seed the accounts from a CSV file, unless resuming a saved state.
+    #[arg(long)]
+    opening_balances: Option<String>,
+    /// The account tags sidecar file, with `client` and `tag` columns.
+    #[arg(long)]
+    tags: Option<String>,
+}
+
+/// The behavioral policies of the engine, see `EngineConfig`.
+#[derive(Args)]
+struct ConfigArgs {
+    /// Read the policies from a TOML file, the flags take precedence.
+    #[arg(long)]
+    config: Option<String>,
+    /// What to reject on locked accounts: reject-all, reject-withdrawals or
+    /// allow.
+    #[arg(long)]
+    locked_accounts: Option<LockedAccountPolicy>,
+    /// How disputes on withdrawals are handled: reverse or deny.
+    #[arg(long)]
+    withdrawal_disputes: Option<WithdrawalDisputePolicy>,
+    /// Whether resolved transactions can be disputed again: allow, deny or
+    /// allow-N to allow N new disputes of each transaction after the first.
+    #[arg(long)]
+    redisputes: Option<RedisputePolicy>,
+    /// What to do with reused transaction ids: reject or ignore.
+    #[arg(long)]
+    duplicates: Option<DuplicatePolicy>,
+    /// What to do with amounts stating more than four decimal places: round
+    /// (the default), reject or keep.
+    #[arg(long)]
+    excess_precision: Option<ExcessPrecisionPolicy>,
+    /// Reject deposits, withdrawals and transfers moving more than this.
+    #[arg(long)]
+    max_amount: Option<Decimal>,
+    /// What to do with records older than the last activity of their account:
+    /// allow, warn or reject.
+    #[arg(long)]
+    out_of_order: Option<OutOfOrderPolicy>,
+    /// Let withdrawals take the available funds negative down to minus this,
+    /// unless the opening balances state a `credit_limit` for the account.
+    #[arg(long)]
+    credit_limit: Option<Decimal>,
+    /// Charge a fee for a transaction type, e.g. `withdrawal=0.5+1%`, can be
+    /// repeated.
+    #[arg(long, value_name = "TYPE=FEE", value_parser = parse_fee)]
+    fee: Vec<(TransactionKind, Fee)>,
+    /// Let disputes hold spent funds, leaving a deficit on chargebacks.
+    #[arg(long)]
+    allow_negative: bool,
+    /// Quarantine the clients whose transactions panic instead of aborting.
+    #[arg(long)]
+    contain_panics: bool,
+    /// Check the invariants of every account after each transaction, slow
+    /// and only in debug builds.
+    #[arg(long)]
+    check_invariants: bool,
+    /// Don't notify the event listeners, e.g. when rebuilding a store.
+    #[arg(long)]
+    backfill: bool,
+}
+
+/// Where the chargebacks and the account locks are posted.
+#[derive(Args)]
+struct WebhookArgs {
+    /// Post the chargebacks and the account locks as JSON events to a plain
+    /// HTTP URL.
+    #[arg(long)]
+    webhook: Option<String>,
+    /// How many times a failed post is retried.
+    #[arg(long, default_value_t = webhook::DEFAULT_RETRIES, requires = "webhook")]
+    webhook_retries: u32,
+    /// The number of events waiting to be posted, further events are dropped.
+    #[arg(long, default_value_t = webhook::DEFAULT_QUEUE, requires = "webhook")]
+    webhook_queue: usize,
+}
+
+/// The reports of the engine state.
+#[derive(Args)]
+struct ReportsArgs {
+    /// Reconcile the closing totals against a CSV file with `client` and
+    /// `total` columns.
+    #[arg(long)]
+    control_totals: Option<String>,
+    /// Fail on control total mismatches instead of only reporting them.
+    #[arg(long)]
+    strict: bool,
+    /// Write the open disputes to a CSV file.
+    #[arg(long)]
+    disputes: Option<String>,
+    /// Write the outstanding negative balances to a CSV file.
+    #[arg(long)]
+    deficits: Option<String>,
+    /// Write the totals of the accounts and of the transactions to a CSV file.
+    #[arg(long)]
+    summary: Option<String>,
+    /// Write the fees charged to each client to a CSV file.
+    #[arg(long)]
+    fees: Option<String>,
+    /// Write the debit and credit balances of the ledger to a CSV file,
+    /// failing if they don't match the accounts.
+    #[arg(long)]
+    trial_balance: Option<String>,
+    /// Write the available funds eligible for escheatment to a CSV file.
+    #[arg(long)]
+    aged_funds: Option<String>,
+    /// How many years without activity make funds eligible for escheatment.
+    #[arg(long, default_value_t = 5)]
+    aged_years: u32,
+    /// The Unix time the age of the funds is computed at, defaults to now.
+    #[arg(long)]
+    as_of: Option<i64>,
+}
+
+/// Where the accounts and the diagnostics are written.
+#[derive(Args)]
+struct OutputArgs {
+    /// Write the accounts to a file instead of the standard output.
+    #[arg(long, short)]
+    output: Option<String>,
+    /// Write the diagnostics to a CSV file instead of the standard error.
+    #[arg(long)]
+    errors: Option<String>,
+    /// Only print the accounts having this tag.
+    #[arg(long)]
+    only_tag: Option<String>,
+    /// Print the number and the sum of the account totals per balance bucket
+    /// instead of the accounts, refusing any per-client output.
+    #[arg(long)]
+    aggregate: bool,
+    /// The comma-separated bounds of the balance buckets.
+    #[arg(long, value_delimiter = ',', requires = "aggregate")]
+    buckets: Vec<Decimal>,
+    /// Suppress the buckets counting less accounts than this.
+    #[arg(long, default_value_t = aggregation::DEFAULT_MIN_COUNT, requires = "aggregate")]
+    min_count: usize,
+    /// Add the timestamp of the latest transaction of each account as a
+    /// `last_activity` column.
+    #[arg(long, conflicts_with = "aggregate")]
+    last_activity: bool,
+    /// The comma-separated columns of the accounts, among the ones of the
+    /// spec, `disputes`, `last_tx`, `last_activity` and `currency`.
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["aggregate", "last_activity"])]
+    fields: Vec<AccountField>,
+    /// The currency of the accounts, stated by the `currency` column.
+    #[arg(long, conflicts_with = "aggregate")]
+    currency: Option<Currency>,
+    /// Render the amounts along with the currency with the conventions of
+    /// this locale: en, de or fr.
+    #[arg(long, requires = "currency")]
+    locale: Option<Locale>,
+    /// The decimal places of the amounts, more precise ones being rounded
+    /// half to even.
+    #[arg(long, default_value_t = SPEC_DECIMALS, conflicts_with = "aggregate")]
+    decimals: u32,
+}
+
+impl OutputArgs {
+    /// Get the format of the accounts output.
+    fn report_format(&self) -> ReportFormat {
+        let mut fields = if self.fields.is_empty() {
+            AccountField::SPEC.to_vec()
+        } else {
+            self.fields.clone()
+        };
+        if self.last_activity {
+            fields.push(AccountField::LastActivity);
+        }
+
+        ReportFormat {
+            fields,
+            decimals: self.decimals,
+            currency: self.currency.clone(),
+            locale: self.locale,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    state: StateArgs,
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(flatten)]
+    webhook: WebhookArgs,
+    #[command(flatten)]
+    reports: ReportsArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+    /// Write the records failing to be parsed or applied to a CSV file.
+    #[arg(long)]
+    quarantine: Option<String>,
+    /// Write the transactions rejected by the engine to a CSV file.
+    #[arg(long)]
+    rejects: Option<String>,
+    /// Write the balances of the accounts before and after each transaction
+    /// altering them to a file.
+    #[arg(long)]
+    audit_log: Option<String>,
+    /// Skip the rows failing to be parsed instead of aborting the run, then
+    /// print them along with their line.
+    #[arg(long, conflicts_with = "quarantine")]
+    skip_invalid: bool,
+    /// The id of the run, recorded in the state to make re-runs a no-op.
+    #[arg(long, requires = "state")]
+    run_id: Option<String>,
+    /// Only report failed balance assertions instead of failing the run.
+    #[arg(long)]
+    warn_assertions: bool,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Write the diagnostics to a CSV file instead of the standard error.
+    #[arg(long)]
+    errors: Option<String>,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    #[command(flatten)]
+    state: StateArgs,
+    #[command(flatten)]
+    reports: ReportsArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+    /// The format of the accounts output: csv, json or parquet.
+    #[arg(long, default_value = "csv")]
+    format: Format,
+}
+
+#[derive(Args)]
+struct ReconcileArgs {
+    #[command(flatten)]
+    state: StateArgs,
+    /// Write the discrepancies to a file instead of the standard output.
+    #[arg(long, short)]
+    output: Option<String>,
+}
+
+/// An administrative batch, applied atomically.
+#[derive(Args)]
+struct AdminArgs {
+    /// The CSV file of the batch, with a `client` column, plus an `amount`
+    /// one for adjustments.
+    file: String,
+    #[command(flatten)]
+    state: StateArgs,
+    /// Append the audit record of the batch to a JSON lines file.
+    #[arg(long)]
+    audit_log: Option<String>,
+}
+
+#[derive(Args)]
+struct SplitArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// What to split the transactions by, only client is supported.
+    #[arg(long, default_value = "client")]
+    by: SplitKey,
+    /// The number of shard files.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    shards: u16,
+    /// The prefix of the shard files, e.g. `shard` for `shard-00.csv`,
+    /// defaults to the input path without its extensions.
+    #[arg(long)]
+    prefix: Option<String>,
+    /// Write the expected total of each shard to the manifest, summing the
+    /// totals of its clients from a CSV file with `client` and `total`
+    /// columns.
+    #[arg(long)]
+    control_totals: Option<String>,
+}
+
+#[derive(Args)]
+struct MergeReportsArgs {
+    /// The CSV account reports of the shards.
+    #[arg(required = true)]
+    files: Vec<String>,
+    /// Check the reports against the manifest of the split, one report per
+    /// shard holding only clients routed to it and matching its total if any.
+    #[arg(long)]
+    manifest: Option<String>,
+    /// Fail unless the merged totals match a CSV file with `client` and
+    /// `total` columns.
+    #[arg(long)]
+    control_totals: Option<String>,
+    /// Write the accounts to a file instead of the standard output.
+    #[arg(long, short)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct CanaryArgs {
+    /// The input file, read by both builds.
+    file: String,
+    /// The executable of the previous build, run with the input file and the
+    /// trailing arguments.
+    #[arg(long, conflicts_with = "baseline_accounts")]
+    baseline: Option<String>,
+    /// The accounts already output by the previous build, instead of running
+    /// it.
+    #[arg(long)]
+    baseline_accounts: Option<String>,
+    /// Resume a saved state with both builds, the previous one resuming a
+    /// copy so that the state is left untouched.
+    #[arg(long)]
+    state: Option<String>,
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Write the differences to a file instead of the standard output.
+    #[arg(long, short)]
+    output: Option<String>,
+    /// The arguments of the previous build, e.g. its policy flags.
+    #[arg(last = true)]
+    baseline_args: Vec<String>,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// The expected accounts, e.g. a golden output: an accounts CSV file, or
+    /// a saved engine state if its extension is `.json`.
+    baseline: String,
+    /// The accounts to check, in the same formats.
+    candidate: String,
+    /// Write the differences to a file instead of the standard output.
+    #[arg(long, short)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct SoakArgs {
+    /// The transactions per second, e.g. `50k/s`.
+    #[arg(long, default_value = "10k/s", value_parser = soak::parse_rate)]
+    rate: u64,
+    /// How long to run, e.g. `2h` or `1h30m`.
+    #[arg(long, value_parser = soak::parse_duration)]
+    duration: Duration,
+    /// How often the invariants are checked and the statistics printed.
+    #[arg(long, default_value = "10s", value_parser = soak::parse_duration)]
+    interval: Duration,
+    /// The number of clients of the generated transactions.
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u16).range(1..))]
+    clients: u16,
+    /// The seed of the generated transactions.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Save the engine state to this file on every interval.
+    #[arg(long)]
+    state: Option<String>,
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Write the statistics to a file instead of the standard output.
+    #[arg(long, short)]
+    output: Option<String>,
+}
+
+#[derive(Args)]
+struct EvaluateArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// A configuration file to evaluate, named after its file stem. Can be
+    /// repeated.
+    #[arg(long = "config", required = true)]
+    configs: Vec<String>,
+    /// Seed the accounts of every engine from a CSV file.
+    #[arg(long)]
+    opening_balances: Option<String>,
+    /// Write the comparison to a file instead of the standard output.
+    #[arg(long, short)]
+    output: Option<String>,
+    /// Write the final accounts of every configuration to a CSV file.
+    #[arg(long)]
+    balances: Option<String>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    state: StateArgs,
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(flatten)]
+    webhook: WebhookArgs,
+    /// The address to serve the REST API on.
+    #[arg(long, default_value = DEFAULT_ADDRESS)]
+    listen: String,
+    /// Serve from a worker process, restarted from the saved state whenever
+    /// it fails.
+    #[arg(long, requires = "state", conflicts_with = "opening_balances")]
+    supervised: bool,
+}
+
+impl ConfigArgs {
+    /// Get the configuration of the file, if any, overridden by the flags.
+    fn config(&self) -> Result<EngineConfig, Box<dyn Error>> {
+        let mut config: EngineConfig = match &self.config {
+            Some(path) => toml::from_str(&fs::read_to_string(path)?)
+                .map_err(|error| format!("Invalid config {path}: {error}"))?,
+            None => EngineConfig::default(),
+        };
+
+        if let Some(policy) = self.locked_accounts {
+            config.locked_accounts = policy;
+        }
+        if let Some(policy) = self.withdrawal_disputes {
+            config.withdrawal_disputes = policy;
+        }
+        if let Some(policy) = self.redisputes {
+            config.redisputes = policy;
+        }
+        if let Some(policy) = self.duplicates {
+            config.duplicate_transactions = policy;
+        }
+        if let Some(policy) = self.excess_precision {
+            config.excess_precision = policy;
+        }
+        if let Some(max) = self.max_amount {
+            config.max_amount = Some(max);
+        }
+        if let Some(policy) = self.out_of_order {
+            config.out_of_order = policy;
+        }
+        if let Some(limit) = self.credit_limit {
+            config.credit_limit = limit;
+        }
+        config.fees.extend(self.fee.iter().copied());
+        config.allow_negative_balances |= self.allow_negative;
+        config.contain_panics |= self.contain_panics;
+        config.check_invariants |= self.check_invariants;
+        config.backfill |= self.backfill;
+
+        Ok(config)
+    }
+}
+
+impl StateArgs {
+    /// Create a payments engine, resuming the saved state or seeding the
+    /// opening balances if any, then attach the tags of the sidecar file.
+    fn engine(&self, config: EngineConfig) -> Result<PaymentsEngine, Box<dyn Error>> {
+        let state = self
+            .state
+            .as_deref()
+            .filter(|path| Path::new(path).exists());
+        let mut engine = match (state, &self.opening_balances) {
+            (Some(_), Some(_)) => {
+                return Err("--opening-balances can't be used when resuming a saved state".into())
+            }
+            (Some(path), None) => PaymentsEngine::load(path)?,
+            (None, Some(path)) => {
+                let accounts = csv_reader(path)?
+                    .deserialize()
+                    .collect::<Result<Vec<Account>, _>>()?;
+                PaymentsEngine::with_accounts(accounts)
+            }
+            (None, None) => PaymentsEngine::new(),
+        };
+        engine.set_config(config);
+
+        if let Some(path) = &self.tags {
+            for row in csv_reader(path)?.deserialize() {
+                let TagRow { client, tag } = row?;
+                engine.tag_account(client, tag);
+            }
+        }
+
+        Ok(engine)
+    }
+
+    /// Create a payments engine as `engine` does, failing if the state
+    /// wasn't saved yet rather than starting afresh, for the subcommands
+    /// working on an existing state.
+    fn saved_engine(&self, config: EngineConfig) -> Result<PaymentsEngine, Box<dyn Error>> {
+        match &self.state {
+            Some(path) if !Path::new(path).exists() => {
+                Err(format!("No saved state at {path}").into())
+            }
+            _ => self.engine(config),
+        }
+    }
+}
+
+impl WebhookArgs {
+    /// Attach the webhook to the engine if any, returning the thread posting
+    /// the events.
+    fn attach(
+        &self,
+        engine: &mut PaymentsEngine,
+    ) -> Result<Option<JoinHandle<DeliveryStats>>, Box<dyn Error>> {
+        let Some(url) = &self.webhook else {
+            return Ok(None);
+        };
+        let (webhook, worker) = Webhook::spawn(url, self.webhook_queue, self.webhook_retries)?;
+        engine.add_listener(webhook);
+        Ok(Some(worker))
+    }
+}
+
+impl InputArgs {
+    /// Open the input, reading from the standard input if no file is
+    /// provided, and decompress it.
+    fn open(&self) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        let (input, detected): (Box<dyn Read>, _) = match self.file.as_deref() {
+            None | Some("-") => (Box::new(io::stdin().lock()), Compression::None),
+            Some(path) => (Box::new(File::open(path)?), Compression::detect(path)),
+        };
+        Ok(self.compression.unwrap_or(detected).decoder(input)?)
+    }
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Get the CSV reader
-    let file_path = get_first_arg()?;
-    let file = File::open(&file_path)?;
-    let mut reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .has_headers(true)
-        .comment(Some(b'#'))
-        .from_reader(&file);
+    let cli = Cli::parse();
+    if cli.log_format.is_some() || cli.log_level.is_some() {
+        init_logging(cli.log_format.unwrap_or_default(), cli.log_level.as_deref())?;
+    }
 
-    // Create a payments engine
-    let mut engine = PaymentsEngine::new();
+    match cli
+        .command
+        .unwrap_or_else(|| Command::Process(Box::new(cli.process)))
+    {
+        Command::Process(args) => process(&args),
+        Command::Validate(args) => validate(&args),
+        Command::Report(args) => report(&args),
+        Command::Serve(args) if args.supervised => supervise(),
+        Command::Serve(args) => {
+            let mut engine = args.state.engine(args.config.config()?)?;
+            args.webhook.attach(&mut engine)?;
+            serve(engine, args.state.state.as_deref(), &args.listen)
+        }
+        Command::Split(args) => split(&args),
+        Command::MergeReports(args) => merge_reports(&args),
+        Command::Canary(args) => canary(&args),
+        Command::Diff(args) => diff(&args),
+        Command::Reconcile(args) => reconcile(&args),
+        Command::Soak(args) => soak(&args),
+        Command::Evaluate(args) => evaluate(&args),
+        Command::Lock(args) => administer(&args, "lock", |row| {
+            Some(AdminAction::Lock { client: row.client })
+        }),
+        Command::Unlock(args) => administer(&args, "unlock", |row| {
+            Some(AdminAction::Unlock { client: row.client })
+        }),
+        Command::Adjust(args) => administer(&args, "adjust", |row| {
+            let amount = row.amount?;
+            Some(AdminAction::Adjust { client: row.client, amount })
+        }),
+        // JSON is the only format of the description
+        Command::Describe { json: _ } => {
+            println!("{}", serde_json::to_string_pretty(&describe())?);
+            Ok(())
+        }
+    }
+}
+
+/// Log the events of the engine and of the pipeline to the standard error.
+#[cfg(feature = "tracing")]
+fn init_logging(format: LogFormat, level: Option<&str>) -> Result<(), Box<dyn Error>> {
+    use tracing_subscriber::filter::LevelFilter;
+
+    let level: LevelFilter = match level {
+        Some(level) => level
+            .parse()
+            .map_err(|_| format!("Unknown log level: {level}"))?,
+        None => LevelFilter::INFO,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr);
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+    }
+    .map_err(|error| error as Box<dyn Error>)
+}
+
+#[cfg(not(feature = "tracing"))]
+fn init_logging(_format: LogFormat, _level: Option<&str>) -> Result<(), Box<dyn Error>> {
+    Err("logging requires the tracing feature".into())
+}
+
+/// Process the transactions, then write the reports and the accounts.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(input = args.input.file.as_deref().unwrap_or("-")))
+)]
+fn process(args: &ProcessArgs) -> Result<(), Box<dyn Error>> {
+    if args.output.aggregate {
+        let per_client =
+            args.quarantine.is_some() || args.rejects.is_some() || args.output.errors.is_some();
+        check_aggregate(&args.reports, per_client)?;
+    }
+
+    let mut engine = args.state.engine(args.config.config()?)?;
+    let webhook = args.webhook.attach(&mut engine)?;
+    let mut diagnostics = Diagnostics::create(args.output.errors.as_deref())?;
+
+    // Decompress the input before hashing it, so that the digest only depends
+    // on the contents
+    let mut input = DigestReader::new(args.input.open()?);
+
+    // Runs already recorded in the state are only verified against their
+    // inputs, their output is regenerated without processing them again
+    if let Some(run) = &args.run_id {
+        if let Some(digest) = engine.run_digest(run) {
+            io::copy(&mut input, &mut io::sink())?;
+            if input.digest != digest {
+                return Err(
+                    format!("Run {run} was already processed with different inputs").into(),
+                );
+            }
+
+            eprintln!("Run {run} was already processed, regenerating its output");
+            return write_accounts(&engine, &args.output, args.input.format);
+        }
+    }
+
+    // Keep track of the records failing to be parsed or applied
+    let (headers, rows) = read_rows(&mut input, &args.input)?;
+    let mut failures = Failures {
+        quarantine: match (&args.quarantine, headers) {
+            (Some(path), Some(headers)) => Some(Quarantine::create(path, &headers)?),
+            (Some(_), None) => return Err("--quarantine requires CSV input".into()),
+            (None, _) => None,
+        },
+        rejects: match &args.rejects {
+            Some(path) => Some(csv::Writer::from_path(path)?),
+            None => None,
+        },
+        skipped: args.skip_invalid.then(Vec::new),
+    };
+
+    // Parse each line and perform the transaction, reporting failed balance
+    // assertions and, if asked to, the records applied out of order
+    let warnings =
+        (engine.config().out_of_order == OutOfOrderPolicy::Warn).then(|| engine.subscribe());
+    let mut audit_log = match &args.audit_log {
+        Some(path) => Some((
+            engine.audit(),
+            AuditLogFile::create(path, args.input.format)?,
+        )),
+        None => None,
+    };
+    let mut failed_assertions = 0;
+    let mut group = Vec::new();
+    for row in rows {
+        let (record, transaction) = match row {
+            Ok(row) => row,
+            Err(error) => {
+                failures.unreadable(error)?;
+                continue;
+            }
+        };
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(error) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    line = record.position().map_or(0, Position::line),
+                    code = PARSE_ERROR_CODE,
+                    %error,
+                    "Invalid row"
+                );
+                failures.invalid(&record, error)?;
+                continue;
+            }
+        };
+        let client_id = transaction.client_id;
+
+        // Legs of a composite transaction are consecutive rows sharing the
+        // group id, execute them once the group is over
+        if group
+            .last()
+            .is_some_and(|(leg, _): &(Transaction, _)| leg.group != transaction.group)
+        {
+            execute_group(&mut engine, mem::take(&mut group), &mut failures)?;
+            report_out_of_order(warnings.as_ref(), None, &mut diagnostics)?;
+            if let Some((entries, file)) = &mut audit_log {
+                file.write(entries)?;
+            }
+        }
+        if transaction.group.is_some() {
+            group.push((transaction, record));
+            continue;
+        }
+
+        let row = RejectRow::new(&record, &transaction);
+        let line = record.position().map(Position::line);
+        if let Err(error) = engine.execute(transaction) {
+            if let TransactionError::AssertionFailed { .. } = error {
+                diagnostics.report(line, Some(client_id), Some(error.code()), &error)?;
+                failed_assertions += 1;
+            }
+            failures.rejected(&record, row, &error)?;
+        }
+        report_out_of_order(warnings.as_ref(), line, &mut diagnostics)?;
+        if let Some((entries, file)) = &mut audit_log {
+            file.write(entries)?;
+        }
+    }
+
+    // Execute the trailing group, if any
+    if !group.is_empty() {
+        execute_group(&mut engine, group, &mut failures)?;
+        report_out_of_order(warnings.as_ref(), None, &mut diagnostics)?;
+    }
+    if let Some((entries, file)) = &mut audit_log {
+        file.write(entries)?;
+        file.flush()?;
+    }
+    failures.flush()?;
+    failures.report_skipped(&mut diagnostics)?;
+    #[cfg(feature = "tracing")]
+    tracing::info!(accounts = engine.accounts().len(), "Processed the input");
+
+    // Report the clients quarantined under containment mode
+    for client in engine.quarantined_clients() {
+        diagnostics.report(None, Some(client), None, &"quarantined after a panic")?;
+    }
+
+    // Fail the run on mismatches, unless asked to only warn
+    if failed_assertions > 0 && !args.warn_assertions {
+        return Err(format!("{failed_assertions} balance assertion(s) failed").into());
+    }
+
+    write_reports(&engine, &args.reports, &mut diagnostics)?;
+    diagnostics.flush()?;
+
+    // Record the run along with its inputs, then save the state for the next
+    // run
+    if let Some(run) = &args.run_id {
+        engine.record_run(run.as_str(), input.digest);
+    }
+    if let Some(path) = &args.state.state {
+        engine.save(path)?;
+    }
+
+    write_accounts(&engine, &args.output, args.input.format)?;
+
+    // Wait for the pending notifications, which stop with the engine
+    drop(engine);
+    if let Some(worker) = webhook {
+        let stats = worker.join().map_err(|_| "The webhook thread panicked")?;
+        if stats.failed > 0 || stats.dropped > 0 {
+            eprintln!(
+                "Webhook notifications: {} delivered, {} failed, {} dropped",
+                stats.delivered, stats.failed, stats.dropped
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parse every transaction of the input, reporting the malformed ones along
+/// with their line.
+fn validate(args: &ValidateArgs) -> Result<(), Box<dyn Error>> {
+    let (_, rows) = read_rows(args.input.open()?, &args.input)?;
+    let mut diagnostics = Diagnostics::create(args.errors.as_deref())?;
+
+    let (mut checked, mut invalid) = (0, 0);
+    for row in rows {
+        let (record, transaction) = row?;
+        let line = record.position().map_or(0, Position::line);
+        checked += 1;
+
+        match transaction {
+            Ok(transaction) => match client::validate(&transaction) {
+                Ok(()) => continue,
+                Err(error) => {
+                    let client = Some(transaction.client_id);
+                    diagnostics.report(Some(line), client, Some(error.code()), &error)?;
+                }
+            },
+            Err(error) => diagnostics.report(Some(line), None, Some(PARSE_ERROR_CODE), &error)?,
+        }
+        invalid += 1;
+    }
+    diagnostics.flush()?;
 
-    // Parse each line and perform the transaction
-    for result in reader.deserialize() {
-        let transaction: Transaction = result?;
-        engine.execute(transaction);
+    if invalid > 0 {
+        return Err(format!("{invalid} of {checked} transaction(s) are invalid").into());
     }
+    eprintln!("{checked} transaction(s) are valid");
+    Ok(())
+}
 
-    // Get the CSV writer
-    let mut writer = csv::Writer::from_writer(io::stdout());
+/// Write the reports and the accounts of a saved state.
+fn report(args: &ReportArgs) -> Result<(), Box<dyn Error>> {
+    if args.state.state.is_none() {
+        return Err("report requires --state".into());
+    }
+    if args.output.aggregate {
+        check_aggregate(&args.reports, args.output.errors.is_some())?;
+    }
 
-    // Print each customer's account data
-    for account in engine.accounts.values() {
+    let engine = args.state.saved_engine(EngineConfig::default())?;
+    let mut diagnostics = Diagnostics::create(args.output.errors.as_deref())?;
+    write_reports(&engine, &args.reports, &mut diagnostics)?;
+    diagnostics.flush()?;
+    write_accounts(&engine, &args.output, args.format)
+}
+
+/// Check the accounts of a saved state against their movements, failing on
+/// any discrepancy.
+fn reconcile(args: &ReconcileArgs) -> Result<(), Box<dyn Error>> {
+    if args.state.state.is_none() {
+        return Err("reconcile requires --state".into());
+    }
+
+    let engine = args.state.saved_engine(EngineConfig::default())?;
+    let discrepancies = reconciliation::reconcile(&engine);
+
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    for discrepancy in &discrepancies {
+        writer.serialize(DiscrepancyRow {
+            client: discrepancy.client,
+            check: discrepancy.check,
+            expected: discrepancy.expected,
+            actual: discrepancy.actual,
+        })?;
+    }
+    writer.flush()?;
+
+    if !discrepancies.is_empty() {
+        return Err(format!("{} discrepancy(ies) found", discrepancies.len()).into());
+    }
+    Ok(())
+}
+
+/// Partition the transactions into shard files by client, keeping their order
+/// within each shard, and write a manifest of the shards.
+fn split(args: &SplitArgs) -> Result<(), Box<dyn Error>> {
+    let SplitKey::Client = args.by;
+    let prefix = match (&args.prefix, args.input.file.as_deref()) {
+        (Some(prefix), _) => prefix.clone(),
+        (None, Some(path)) if path != "-" => {
+            let path = Path::new(path);
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(".");
+            let stem = name.split('.').next().unwrap_or_default();
+            path.with_file_name(stem).to_string_lossy().into_owned()
+        }
+        _ => return Err("split requires --prefix when reading the standard input".into()),
+    };
+
+    let (headers, rows) = read_rows(args.input.open()?, &args.input)?;
+    let Some(headers) = headers else {
+        return Err("split requires CSV input".into());
+    };
+
+    // Sum the control totals per shard, aggregate ones belong to none
+    let shards = usize::from(args.shards);
+    let totals = match &args.control_totals {
+        Some(path) => {
+            let mut totals = vec![Decimal::ZERO; shards];
+            for control in csv_reader(path)?.deserialize() {
+                let control: control_totals::ControlTotal = control?;
+                if let Some(client) = control.client {
+                    let total = &mut totals[sharded_engine::shard_of(client, shards)];
+                    *total = total.saturating_add(control.total);
+                }
+            }
+            Some(totals)
+        }
+        None => None,
+    };
+
+    // Create the shard files, numbered with a fixed width so that they sort
+    let width = (shards - 1).to_string().len();
+    let files: Vec<_> = (0..shards)
+        .map(|shard| format!("{prefix}-{shard:0width$}.csv"))
+        .collect();
+    let mut writers = Vec::with_capacity(shards);
+    for file in &files {
+        let mut writer = csv::WriterBuilder::new().flexible(true).from_path(file)?;
+        writer.write_record(&headers)?;
+        writers.push(writer);
+    }
+
+    // Route the records as they are, refusing the transactions spanning
+    // shards, i.e. transfers to or groups with clients of other shards
+    let mut counts = vec![0; shards];
+    let mut clients = vec![HashSet::new(); shards];
+    let mut groups = HashMap::new();
+    for row in rows {
+        let (record, transaction) = row?;
+        let transaction = transaction?;
+        let client = transaction.client_id;
+        let shard = sharded_engine::shard_of(client, shards);
+        let recipient = transaction
+            .to_client
+            .map(|to_client| sharded_engine::shard_of(to_client, shards));
+        let group = transaction
+            .group
+            .map(|group| *groups.entry(group).or_insert(shard));
+        if let Some(other) = recipient.or(group).filter(|&other| other != shard) {
+            let line = record.position().map_or(0, csv::Position::line);
+            return Err(format!(
+                "Line {line}: transaction {} spans shards {shard} and {other}",
+                transaction.id
+            )
+            .into());
+        }
+        writers[shard].write_record(&record)?;
+        counts[shard] += 1;
+        clients[shard].insert(client);
+    }
+
+    let mut manifest = csv::Writer::from_path(format!("{prefix}-manifest.csv"))?;
+    for (shard, mut writer) in writers.into_iter().enumerate() {
+        writer.flush()?;
+        manifest.serialize(ShardRow {
+            shard,
+            file: files[shard].clone(),
+            rows: counts[shard],
+            clients: clients[shard].len(),
+            total: totals.as_ref().map(|totals| totals[shard]),
+        })?;
+    }
+    manifest.flush()?;
+
+    let total: u64 = counts.iter().sum();
+    eprintln!("Split {total} transaction(s) into {shards} shard(s)");
+    Ok(())
+}
+
+/// Merge the account reports of the shards, making sure that every client is
+/// in a single one, then check the merged accounts against the manifest and
+/// the control totals if any.
+fn merge_reports(args: &MergeReportsArgs) -> Result<(), Box<dyn Error>> {
+    let mut accounts = BTreeMap::new();
+    let mut reports = Vec::with_capacity(args.files.len());
+    for (index, path) in args.files.iter().enumerate() {
+        let mut ids = Vec::new();
+        let mut total = Decimal::ZERO;
+        for account in csv_reader(path)?.deserialize() {
+            let account: Account = account?;
+            ids.push(account.id);
+            total = total.saturating_add(account.total);
+            match accounts.entry(account.id) {
+                Entry::Vacant(entry) => {
+                    entry.insert((index, account));
+                }
+                Entry::Occupied(entry) => {
+                    let other = &args.files[entry.get().0];
+                    return Err(
+                        format!("Client {} is in both {other} and {path}", account.id).into(),
+                    );
+                }
+            }
+        }
+        reports.push((ids, total));
+    }
+
+    if let Some(manifest) = &args.manifest {
+        let shards = csv_reader(manifest)?
+            .deserialize()
+            .collect::<Result<Vec<ShardRow>, _>>()?;
+        check_shards(&args.files, &reports, &shards)?;
+    }
+
+    let accounts: Vec<_> = accounts.into_values().map(|(_, account)| account).collect();
+    if let Some(path) = &args.control_totals {
+        let totals = csv_reader(path)?
+            .deserialize()
+            .collect::<Result<Vec<_>, _>>()?;
+        let engine = PaymentsEngine::with_accounts(accounts.iter().cloned());
+        let mismatches = control_totals::check(&engine, totals);
+
+        let mut diagnostics = Diagnostics::Stderr;
+        for mismatch in &mismatches {
+            let message = format!(
+                "Control total mismatch: expected {}, found {} (delta {})",
+                mismatch.expected, mismatch.actual, mismatch.delta
+            );
+            diagnostics.report(None, mismatch.client, None, &message)?;
+        }
+        if !mismatches.is_empty() {
+            return Err(format!("{} control total(s) mismatched", mismatches.len()).into());
+        }
+    }
+
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    for account in &accounts {
         writer.serialize(account)?;
     }
+    writer.flush()?;
 
-    // Flush CSV buffer to stdout
+    eprintln!(
+        "Merged {} account(s) from {} report(s)",
+        accounts.len(),
+        reports.len()
+    );
+    Ok(())
+}
+
+/// Check that there's a report per shard of the manifest, each holding only
+/// clients routed to its shard, no more than the shard has, and summing up to
+/// the total of the shard if any.
+fn check_shards(
+    files: &[String],
+    reports: &[(Vec<u16>, Decimal)],
+    shards: &[ShardRow],
+) -> Result<(), Box<dyn Error>> {
+    if reports.len() != shards.len() {
+        return Err(format!(
+            "The manifest lists {} shard(s), found {} report(s)",
+            shards.len(),
+            reports.len()
+        )
+        .into());
+    }
+
+    // Empty reports can't be told apart, they're left to the count check
+    let mut seen = vec![None; shards.len()];
+    for (path, (ids, total)) in files.iter().zip(reports) {
+        let Some(&first) = ids.first() else { continue };
+        let shard = sharded_engine::shard_of(first, shards.len());
+        if let Some(id) = ids
+            .iter()
+            .find(|&&id| sharded_engine::shard_of(id, shards.len()) != shard)
+        {
+            return Err(
+                format!("Clients {first} and {id} of {path} are in different shards").into(),
+            );
+        }
+        if let Some(other) = seen[shard].replace(path) {
+            return Err(format!("Both {other} and {path} are reports of shard {shard}").into());
+        }
+
+        let row = shards.iter().find(|row| row.shard == shard);
+        let Some(row) = row else {
+            return Err(format!("Shard {shard} of {path} is missing from the manifest").into());
+        };
+        if ids.len() > row.clients {
+            return Err(format!(
+                "{path} has {} account(s), shard {shard} only has {} client(s)",
+                ids.len(),
+                row.clients
+            )
+            .into());
+        }
+        if let Some(expected) = row.total.filter(|expected| expected != total) {
+            return Err(format!(
+                "{path} totals {total}, the control totals of shard {shard} sum up to {expected}"
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Process the input with the previous build and with this one, resuming the
+/// same state if any, and print the columns of the accounts that differ.
+fn canary(args: &CanaryArgs) -> Result<(), Box<dyn Error>> {
+    let baseline: Vec<Account> = match (&args.baseline, &args.baseline_accounts) {
+        (Some(executable), _) => {
+            let mut command = process::Command::new(executable);
+            command.arg(&args.file).args(&args.baseline_args);
+
+            // Keep the state untouched, as the previous build saves it back
+            let copy = args.state.as_ref().map(|path| format!("{path}.canary"));
+            if let (Some(path), Some(copy)) = (&args.state, &copy) {
+                fs::copy(path, copy)?;
+                command.arg("--state").arg(copy);
+            }
+            let output = command.output();
+            if let Some(copy) = &copy {
+                fs::remove_file(copy)?;
+            }
+
+            let output = output?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("The previous build failed: {}", stderr.trim_end()).into());
+            }
+            csv_reader_builder()
+                .from_reader(output.stdout.as_slice())
+                .deserialize()
+                .collect::<Result<_, _>>()?
+        }
+        (None, Some(path)) => csv_reader(path)?.deserialize().collect::<Result<_, _>>()?,
+        (None, None) => return Err("canary requires --baseline or --baseline-accounts".into()),
+    };
+
+    let mut engine = match &args.state {
+        Some(path) => PaymentsEngine::load(path)?,
+        None => PaymentsEngine::new(),
+    };
+    engine.set_config(args.config.config()?);
+
+    // Apply the transactions the same way as the process subcommand, without
+    // reporting the rejected ones
+    let input = InputArgs {
+        file: Some(args.file.clone()),
+        format: Format::Csv,
+        compression: None,
+        filter: None,
+    };
+    let (_, rows) = read_rows(input.open()?, &input)?;
+    let mut failures = Failures { quarantine: None, rejects: None, skipped: None };
+    let mut group = Vec::new();
+    for row in rows {
+        let (record, transaction) = row?;
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(error) => {
+                failures.invalid(&record, error)?;
+                continue;
+            }
+        };
+        if group
+            .last()
+            .is_some_and(|(leg, _): &(Transaction, _)| leg.group != transaction.group)
+        {
+            execute_group(&mut engine, mem::take(&mut group), &mut failures)?;
+        }
+        if transaction.group.is_some() {
+            group.push((transaction, record));
+        } else {
+            let _ = engine.execute(transaction);
+        }
+    }
+    if !group.is_empty() {
+        execute_group(&mut engine, group, &mut failures)?;
+    }
+
+    let differences = canary::compare(&baseline, engine.accounts_sorted());
+    let clients = write_differences(&differences, args.output.as_deref())?;
+    if clients > 0 {
+        return Err(format!("{clients} client(s) differ between the builds").into());
+    }
+    eprintln!("No differences across {} account(s)", baseline.len());
+    Ok(())
+}
+
+/// Compare the accounts of the two files and print the columns that differ,
+/// failing if any does.
+fn diff(args: &DiffArgs) -> Result<(), Box<dyn Error>> {
+    let baseline = read_accounts(&args.baseline)?;
+    let candidate = read_accounts(&args.candidate)?;
+
+    let differences = canary::compare(&baseline, &candidate);
+    let clients = write_differences(&differences, args.output.as_deref())?;
+    if clients > 0 {
+        return Err(format!("{clients} client(s) differ between the accounts").into());
+    }
+    eprintln!("No differences across {} account(s)", baseline.len());
+    Ok(())
+}
+
+/// Read the accounts of an accounts CSV file, or of a saved engine state if
+/// its extension is `.json`, sorted by client.
+fn read_accounts(path: &str) -> Result<Vec<Account>, Box<dyn Error>> {
+    if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+        let engine = PaymentsEngine::load(path)?;
+        return Ok(engine.accounts_sorted().cloned().collect());
+    }
+    let mut accounts: Vec<Account> = csv_reader(path)?.deserialize().collect::<Result<_, _>>()?;
+    accounts.sort_unstable_by_key(|account| account.id);
+    Ok(accounts)
+}
+
+/// Write the differences to the file or the standard output, getting the
+/// number of differing clients.
+fn write_differences(
+    differences: &[canary::Difference],
+    output: Option<&str>,
+) -> Result<usize, Box<dyn Error>> {
+    let output: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    for difference in differences {
+        writer.serialize(DifferenceRow {
+            client: difference.client,
+            column: difference.column,
+            baseline: difference.baseline.as_deref(),
+            candidate: difference.candidate.as_deref(),
+        })?;
+    }
+    writer.flush()?;
+
+    let clients: HashSet<_> = differences.iter().map(|d| d.client).collect();
+    Ok(clients.len())
+}
+
+/// Drive generated transactions through the CSV parsing and the engine at the
+/// given rate, checking the invariants and printing the statistics on every
+/// interval, and failing on the first violation.
+fn soak(args: &SoakArgs) -> Result<(), Box<dyn Error>> {
+    let config = args.config.config()?;
+    let allow_negative = config.allow_negative_balances;
+    let mut engine = PaymentsEngine::new();
+    engine.set_config(config);
+
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    let input = InputArgs {
+        file: None,
+        format: Format::Csv,
+        compression: None,
+        filter: None,
+    };
+
+    // Pace the load in batches of a hundredth of a second
+    let mut generator = Generator::new(args.seed, args.clients);
+    let batch = usize::try_from(args.rate / 100)
+        .unwrap_or(usize::MAX)
+        .max(1);
+    let (mut latencies, mut overall) = (Histogram::default(), Histogram::default());
+    let (mut sent, mut exhausted) = (0, false);
+    let initial_memory = soak::resident_memory();
+    let started = Instant::now();
+    let mut next_check = args.interval;
+
+    while !exhausted && started.elapsed() < args.duration {
+        // Serialize the batch, then read it back as an input file would be
+        let mut batch_writer = csv::Writer::from_writer(Vec::new());
+        for tx in generator.by_ref().take(batch) {
+            batch_writer.serialize(tx)?;
+        }
+        let data = batch_writer.into_inner()?;
+        let (_, rows) = read_rows(data.as_slice(), &input)?;
+
+        let mut executed = 0;
+        for row in rows {
+            let tx = row?.1?;
+            let before = Instant::now();
+            let _ = engine.execute(tx);
+            latencies.record(before.elapsed());
+            executed += 1;
+        }
+        sent += executed;
+        exhausted = executed < batch as u64;
+
+        let target = Duration::from_secs_f64(sent as f64 / args.rate as f64);
+        if let Some(ahead) = target.checked_sub(started.elapsed()) {
+            thread::sleep(ahead);
+        }
+
+        let elapsed = started.elapsed();
+        if elapsed < next_check && !exhausted && elapsed < args.duration {
+            continue;
+        }
+        next_check += args.interval;
+
+        let violations = reconciliation::check_invariants(&engine, allow_negative);
+        if !violations.is_empty() {
+            let mut diagnostics = Diagnostics::Stderr;
+            for violation in &violations {
+                diagnostics.report(None, None, None, violation)?;
+            }
+            return Err(format!(
+                "{} invariant violation(s) after {sent} transaction(s)",
+                violations.len()
+            )
+            .into());
+        }
+
+        let save_ms = match &args.state {
+            Some(path) => {
+                let before = Instant::now();
+                engine.save(path)?;
+                Some(before.elapsed().as_millis())
+            }
+            None => None,
+        };
+
+        let micros = |latency: Duration| (latency.as_secs_f64() * 1e6 * 1e3).round() / 1e3;
+        writer.serialize(SoakRow {
+            elapsed: elapsed.as_secs(),
+            transactions: sent,
+            rate: (sent as f64 / elapsed.as_secs_f64()) as u64,
+            resident_memory: soak::resident_memory(),
+            p50_us: micros(latencies.percentile(50.0)),
+            p99_us: micros(latencies.percentile(99.0)),
+            p999_us: micros(latencies.percentile(99.9)),
+            max_us: micros(latencies.max()),
+            save_ms,
+        })?;
+        writer.flush()?;
+        overall.merge(&latencies);
+        latencies = Histogram::default();
+    }
+
+    if exhausted {
+        eprintln!("Stopped early, every transaction id was used");
+    }
+    let growth = match (initial_memory, soak::resident_memory()) {
+        (Some(initial), Some(last)) => format!("{} KiB", (last as i64 - initial as i64) / 1024),
+        _ => "unknown".to_string(),
+    };
+    eprintln!(
+        "Soaked {sent} transaction(s) in {:.1}s, memory growth {growth}, p50 {:?}, p99 {:?}, \
+         max {:?}",
+        started.elapsed().as_secs_f64(),
+        overall.percentile(50.0),
+        overall.percentile(99.0),
+        overall.max()
+    );
+    Ok(())
+}
+
+/// Process the transactions under every configuration, then write how the
+/// rejections, the locked accounts and the balances compare.
+fn evaluate(args: &EvaluateArgs) -> Result<(), Box<dyn Error>> {
+    let mut configs = vec![("default".to_string(), EngineConfig::default())];
+    for path in &args.configs {
+        let name = Path::new(path)
+            .file_stem()
+            .map_or_else(|| path.clone(), |stem| stem.to_string_lossy().into_owned());
+        if configs.iter().any(|(other, _)| *other == name) {
+            return Err(format!("Configuration {name} is evaluated twice").into());
+        }
+        let config = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|error| format!("Invalid config {path}: {error}"))?;
+        configs.push((name, config));
+    }
+    let opening_balances: Vec<Account> = match &args.opening_balances {
+        Some(path) => csv_reader(path)?.deserialize().collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+
+    // Every engine executes the same transactions, read beforehand
+    let (_, rows) = read_rows(args.input.open()?, &args.input)?;
+    let mut txs = Vec::new();
+    for row in rows {
+        let (record, transaction) = row?;
+        let line = record.position().map_or(0, Position::line);
+        txs.push(transaction.map_err(|error| format!("Line {line}: {error}"))?);
+    }
+    let evaluations = evaluation::evaluate(&txs, configs, || {
+        PaymentsEngine::with_accounts(opening_balances.iter().cloned())
+    });
+
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    for evaluation in &evaluations {
+        let totals = evaluation.accounts.iter().map(|account| account.total);
+        writer.serialize(EvaluationRow {
+            config: &evaluation.name,
+            applied: evaluation.applied,
+            rejected: evaluation.rejected,
+            rejected_amount: evaluation.rejected_amount,
+            locked_accounts: evaluation.locked_accounts,
+            clients: evaluation.accounts.len(),
+            total: totals.fold(Decimal::ZERO, Decimal::saturating_add),
+        })?;
+    }
     writer.flush()?;
+
+    if let Some(path) = &args.balances {
+        let mut writer = csv::Writer::from_path(path)?;
+        for evaluation in &evaluations {
+            for account in &evaluation.accounts {
+                writer.serialize(EvaluatedAccountRow {
+                    config: &evaluation.name,
+                    client: account.id,
+                    available: account.available,
+                    held: account.held,
+                    total: account.total,
+                    locked: account.locked,
+                })?;
+            }
+        }
+        writer.flush()?;
+    }
+
     Ok(())
 }
 
-/// Get the first command line argument
-fn get_first_arg() -> Result<String, Box<dyn Error>> {
-    env::args().nth(1)
-        .ok_or_else(|| From::from("No argument provided"))
+/// Apply the actions of the rows of the batch to a saved state, all of them
+/// or none, then print the audit record of the batch.
+fn administer(
+    args: &AdminArgs,
+    command: &str,
+    action: impl Fn(AdminRow) -> Option<AdminAction>,
+) -> Result<(), Box<dyn Error>> {
+    let Some(state) = &args.state.state else {
+        return Err(format!("{command} requires --state").into());
+    };
+
+    let mut reader = csv_reader(&args.file)?;
+    let headers = reader.headers()?.clone();
+    let (mut actions, mut lines) = (Vec::new(), Vec::new());
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, Position::line);
+        let row = record
+            .deserialize(Some(&headers))
+            .map_err(|error| format!("Line {line}: {error}"))?;
+        actions.push(action(row).ok_or_else(|| format!("Line {line}: missing amount"))?);
+        lines.push(line);
+    }
+
+    let mut engine = args.state.saved_engine(EngineConfig::default())?;
+    let batch = engine.administer(&actions).map_err(|error| {
+        let line = lines[error.index];
+        let code = error.error.code();
+        format!(
+            "Line {line}, client {}: {code} {}",
+            error.client, error.error
+        )
+    })?;
+
+    // Audit the batch before saving it, so that no applied batch goes
+    // unaudited
+    let record = serde_json::to_string(&batch)?;
+    if let Some(path) = &args.audit_log {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{record}")?;
+    }
+    engine.save(state)?;
+    println!("{record}");
+    Ok(())
+}
+
+/// Make sure that no per-client values are written under aggregate mode.
+fn check_aggregate(reports: &ReportsArgs, per_client: bool) -> Result<(), Box<dyn Error>> {
+    let per_client = per_client
+        || reports.disputes.is_some()
+        || reports.deficits.is_some()
+        || reports.fees.is_some()
+        || reports.aged_funds.is_some()
+        || reports.trial_balance.is_some();
+    if per_client {
+        return Err("--aggregate can't be combined with per-client reports".into());
+    }
+    Ok(())
+}
+
+/// Reconcile the control totals and export the requested reports.
+fn write_reports(
+    engine: &PaymentsEngine,
+    args: &ReportsArgs,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), Box<dyn Error>> {
+    // Reconcile against the control totals, failing under strict mode
+    if let Some(path) = &args.control_totals {
+        let totals = csv_reader(path)?
+            .deserialize()
+            .collect::<Result<Vec<_>, _>>()?;
+        let mismatches = control_totals::check(engine, totals);
+
+        for mismatch in &mismatches {
+            let scope = if mismatch.client.is_some() {
+                ""
+            } else {
+                " for all clients"
+            };
+            let message = format!(
+                "Control total mismatch{scope}: expected {}, found {} (delta {})",
+                mismatch.expected, mismatch.actual, mismatch.delta
+            );
+            diagnostics.report(None, mismatch.client, None, &message)?;
+        }
+
+        if !mismatches.is_empty() && args.strict {
+            return Err(format!("{} control total(s) mismatched", mismatches.len()).into());
+        }
+    }
+
+    // Export the open disputes along with their case reference
+    if let Some(path) = &args.disputes {
+        let mut writer = csv::Writer::from_path(path)?;
+        for tx in engine.disputes() {
+            writer.serialize(DisputeRow {
+                client: tx.client_id,
+                tx: tx.id,
+                amount: tx.amount,
+                reference: tx.dispute_reference.as_deref(),
+            })?;
+        }
+        writer.flush()?;
+    }
+
+    // Export the outstanding negative balances
+    if let Some(path) = &args.deficits {
+        let mut writer = csv::Writer::from_path(path)?;
+        for account in engine.negative_balances() {
+            writer.serialize(DeficitRow { client: account.id, deficit: account.deficit() })?;
+        }
+        writer.flush()?;
+    }
+
+    // Export the fees charged, separately from the balances they reduced
+    if let Some(path) = &args.fees {
+        let mut writer = csv::Writer::from_path(path)?;
+        for account in engine.accounts_sorted() {
+            if !account.fees.is_zero() {
+                writer.serialize(FeeRow { client: account.id, fees: account.fees })?;
+            }
+        }
+        writer.flush()?;
+    }
+
+    // Export the totals for a quick reconciliation
+    if let Some(path) = &args.summary {
+        let summary = summary::summarize(engine);
+        let mut rows = vec![
+            ("clients", summary.clients.to_string()),
+            ("locked_accounts", summary.locked_accounts.to_string()),
+            ("available", summary.available.to_string()),
+            ("held", summary.held.to_string()),
+            ("total", summary.total.to_string()),
+            ("fees", summary.fees.to_string()),
+        ];
+        for (metric, amount_metric, tally) in [
+            ("deposits", "deposits_amount", summary.deposits),
+            ("withdrawals", "withdrawals_amount", summary.withdrawals),
+            ("transfers", "transfers_amount", summary.transfers),
+            (
+                "open_disputes",
+                "open_disputes_amount",
+                summary.open_disputes,
+            ),
+            ("chargebacks", "chargebacks_amount", summary.chargebacks),
+        ] {
+            rows.push((metric, tally.count.to_string()));
+            rows.push((amount_metric, tally.amount.to_string()));
+        }
+
+        let mut writer = csv::Writer::from_path(path)?;
+        for (metric, value) in rows {
+            writer.serialize(SummaryRow { metric, value })?;
+        }
+        writer.flush()?;
+    }
+
+    // Export the ledger balances, proving the accounts
+    if let Some(path) = &args.trial_balance {
+        let trial_balance = engine.ledger().trial_balance(engine.accounts());
+        let mut writer = csv::Writer::from_path(path)?;
+        for row in &trial_balance.rows {
+            writer.serialize(TrialBalanceRow {
+                account: row.account.to_string(),
+                debit: row.debit,
+                credit: row.credit,
+            })?;
+        }
+        writer.serialize(TrialBalanceRow {
+            account: "total".to_string(),
+            debit: trial_balance.debits,
+            credit: trial_balance.credits,
+        })?;
+        writer.flush()?;
+
+        for client in &trial_balance.mismatches {
+            diagnostics.report(
+                None,
+                Some(*client),
+                None,
+                &"account doesn't match the ledger",
+            )?;
+        }
+        if !trial_balance.is_balanced() {
+            return Err("The trial balance doesn't balance".into());
+        }
+    }
+
+    // Export the funds untouched for too long, per jurisdiction
+    if let Some(path) = &args.aged_funds {
+        let as_of = match args.as_of {
+            Some(as_of) => as_of,
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_secs()
+                .try_into()?,
+        };
+
+        let mut writer = csv::Writer::from_path(path)?;
+        for aged in escheatment::aged_balances(engine, as_of, args.aged_years) {
+            writer.serialize(AgedRow {
+                jurisdiction: aged.jurisdiction.as_deref(),
+                client: aged.client,
+                last_activity: aged.last_activity,
+                amount: aged.amount,
+            })?;
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Get the records of the input in the given format, along with the headers
+/// of CSV inputs, skipping the ones not matching the filter if any.
+fn read_rows<'a>(
+    input: impl Read + 'a,
+    args: &InputArgs,
+) -> Result<(Option<StringRecord>, Rows<'a>), Box<dyn Error>> {
+    let filter = args.filter.clone();
+    let rows: Rows<'a> = match args.format {
+        Format::Csv => {
+            let mut reader = csv_reader_builder().from_reader(input);
+            let headers = reader.headers()?.clone();
+
+            // Evaluate the filter on the raw fields, so that skipped rows
+            // aren't even parsed
+            let positions: Vec<_> = Column::ALL
+                .iter()
+                .map(|column| headers.iter().position(|header| header == column.name()))
+                .collect();
+            let records = reader.into_records().filter(move |record| {
+                let (Some(filter), Ok(record)) = (&filter, record) else {
+                    return true;
+                };
+                filter.matches(&|column| positions[column as usize].and_then(|i| record.get(i)))
+            });
+
+            let rows = records.map({
+                let headers = headers.clone();
+                move |record| {
+                    let record = record?;
+                    let transaction = record.deserialize(Some(&headers)).map_err(Into::into);
+                    Ok((record, transaction))
+                }
+            });
+            return Ok((Some(headers), Box::new(rows)));
+        }
+        Format::Json => {
+            let lines = BufReader::new(input).lines().zip(1..);
+            let rows = lines
+                .filter(|(line, _)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+                .map(|(line, number)| {
+                    let line = line?;
+                    let transaction = serde_json::from_str(&line)
+                        .map_err(|error| format!("Line {number}: {error}").into());
+                    let mut record = StringRecord::from(vec![line]);
+                    let mut position = Position::new();
+                    position.set_line(number);
+                    record.set_position(Some(position));
+                    Ok((record, transaction))
+                });
+            Box::new(rows)
+        }
+        Format::Parquet => parquet_rows(input)?,
+    };
+
+    // Other formats are filtered once parsed, malformed rows are kept to be
+    // reported
+    match filter {
+        Some(filter) => Ok((
+            None,
+            Box::new(rows.filter(move |row| match row {
+                Ok((_, Ok(transaction))) => filter.matches_transaction(transaction),
+                _ => true,
+            })),
+        )),
+        None => Ok((None, rows)),
+    }
+}
+
+/// Get the rows of a Parquet input, which is read in memory as the format
+/// requires random access. Rows are numbered from one.
+#[cfg(feature = "parquet")]
+fn parquet_rows<'a>(mut input: impl Read) -> Result<Rows<'a>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+
+    let transactions = payments::parquet_io::read_transactions(bytes::Bytes::from(bytes))?;
+    let rows = transactions.zip(1..).map(|(transaction, number)| {
+        let mut record = StringRecord::new();
+        let mut position = Position::new();
+        position.set_line(number);
+        record.set_position(Some(position));
+        Ok((record, transaction.map_err(Into::into)))
+    });
+    Ok(Box::new(rows))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_rows<'a>(_input: impl Read) -> Result<Rows<'a>, Box<dyn Error>> {
+    Err("--format parquet requires the parquet feature".into())
+}
+
+/// Print each customer's account data in ascending id order, only the ones
+/// having the tag if any.
+fn write_accounts(
+    engine: &PaymentsEngine,
+    args: &OutputArgs,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let accounts: Box<dyn Iterator<Item = &Account>> = match &args.only_tag {
+        Some(tag) => Box::new(engine.tagged_accounts(tag)),
+        None => Box::new(engine.accounts_sorted()),
+    };
+    let output: Box<dyn Write + Send> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    if args.aggregate {
+        return write_aggregates(output, accounts, args, format);
+    }
+
+    let report_format = args.report_format();
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(output);
+            for report in engine.reports(accounts, &report_format) {
+                writer.serialize(report)?;
+            }
+
+            // Flush CSV buffer to the output
+            writer.flush()?;
+        }
+        Format::Json => {
+            let mut writer = io::BufWriter::new(output);
+            for report in engine.reports(accounts, &report_format) {
+                serde_json::to_writer(&mut writer, &report)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        Format::Parquet if report_format != ReportFormat::default() => {
+            return Err(
+                "--last-activity, --fields, --currency, --locale and --decimals \
+                        require CSV or JSON output"
+                    .into(),
+            )
+        }
+        Format::Parquet => write_parquet(output, accounts)?,
+    }
+
+    Ok(())
+}
+
+/// Print the balance buckets of the accounts, small buckets being suppressed.
+fn write_aggregates<'a>(
+    output: impl Write,
+    accounts: impl Iterator<Item = &'a Account>,
+    args: &OutputArgs,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    if !args.buckets.is_sorted_by(|a, b| a < b) {
+        return Err("--buckets must be in ascending order".into());
+    }
+    let bounds = if args.buckets.is_empty() {
+        aggregation::default_bounds()
+    } else {
+        args.buckets.clone()
+    };
+    let buckets = aggregation::buckets(accounts, &bounds, args.min_count);
+
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(output);
+            for bucket in &buckets {
+                writer.serialize(bucket)?;
+            }
+            writer.flush()?;
+        }
+        Format::Json => {
+            let mut writer = io::BufWriter::new(output);
+            for bucket in &buckets {
+                serde_json::to_writer(&mut writer, bucket)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        Format::Parquet => return Err("--aggregate requires CSV or JSON output".into()),
+    }
+
+    Ok(())
+}
+
+/// Write the accounts to the output as a Parquet file.
+#[cfg(feature = "parquet")]
+fn write_parquet<'a>(
+    output: impl Write + Send,
+    accounts: impl Iterator<Item = &'a Account>,
+) -> Result<(), Box<dyn Error>> {
+    payments::parquet_io::write_accounts(output, accounts)?.flush()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet<'a>(
+    _output: impl Write + Send,
+    _accounts: impl Iterator<Item = &'a Account>,
+) -> Result<(), Box<dyn Error>> {
+    Err("--format parquet requires the parquet feature".into())
+}
+
+/// A reader computing the FNV-1a digest of the bytes read through it, used to
+/// tell whether a run is re-processed with the same inputs.
+struct DigestReader<R> {
+    inner: R,
+    digest: u64,
+}
+
+impl<R: Read> DigestReader<R> {
+    const fn new(inner: R) -> Self {
+        Self { inner, digest: 0xcbf2_9ce4_8422_2325 }
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for byte in &buf[..read] {
+            self.digest = (self.digest ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        Ok(read)
+    }
+}
+
+/// Serve the REST API of the engine until the process is stopped, saving the
+/// state after every change if any.
+#[cfg(feature = "server")]
+fn serve(engine: PaymentsEngine, state: Option<&str>, addr: &str) -> Result<(), Box<dyn Error>> {
+    use payments::async_engine::AsyncPaymentsEngine;
+
+    // Exit on panics, e.g. of the engine task, rather than serving a state
+    // which is no longer persisted
+    if state.is_some() {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            hook(info);
+            process::exit(101);
+        }));
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let engine = match state {
+            Some(path) => AsyncPaymentsEngine::spawn_persistent(engine, path),
+            None => AsyncPaymentsEngine::spawn(engine),
+        };
+        eprintln!("Serving on {addr}");
+        payments::server::serve(addr, engine).await
+    })?;
+    Ok(())
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_engine: PaymentsEngine, _state: Option<&str>, _addr: &str) -> Result<(), Box<dyn Error>> {
+    Err("serve requires the server feature".into())
+}
+
+/// Run the server in a worker process with the same arguments, restarting it
+/// with an exponential backoff whenever it fails. The worker resumes from the
+/// state saved before its last acknowledged change.
+fn supervise() -> Result<(), Box<dyn Error>> {
+    if !cfg!(feature = "server") {
+        return Err("serve requires the server feature".into());
+    }
+
+    let exe = env::current_exe()?;
+    let args: Vec<_> = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--supervised")
+        .collect();
+
+    let mut delay = MIN_RESTART_DELAY;
+    loop {
+        let started = Instant::now();
+        let status = process::Command::new(&exe).args(&args).status()?;
+        if status.success() {
+            return Ok(());
+        }
+
+        // Workers which were up for a while are restarted right away
+        if started.elapsed() >= HEALTHY_UPTIME {
+            delay = MIN_RESTART_DELAY;
+        }
+        eprintln!("Worker {status}, restarting in {}s", delay.as_secs());
+        thread::sleep(delay);
+        delay = (delay * 2).min(MAX_RESTART_DELAY);
+    }
+}
+
+/// Describe the supported transaction types, configuration and output columns,
+/// so that wrapper tooling can introspect them.
+fn describe() -> serde_json::Value {
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "subcommands": [
+            "process", "requeue", "validate", "report", "reconcile", "serve", "split",
+            "merge-reports", "canary", "diff", "soak", "evaluate", "lock", "unlock", "adjust", "describe"
+        ],
+        "transaction_kinds": TransactionKind::ALL,
+        "input_columns": [
+            "type", "client", "tx", "amount", "to_client", "group", "reference", "timestamp",
+            "memo"
+        ],
+        "config": {
+            "--locked-accounts": ["reject-all", "reject-withdrawals", "allow"],
+            "--withdrawal-disputes": ["reverse", "deny"],
+            "--excess-precision": ["keep", "round", "reject"],
+            "--duplicates": ["reject", "ignore"],
+            "--redisputes": ["allow", "deny", "allow-N"],
+            "--out-of-order": ["allow", "warn", "reject"],
+            "--format": ["csv", "json", "parquet"],
+            "--compression": ["none", "gzip", "zstd"],
+            "--allow-negative": "flag",
+            "--contain-panics": "flag",
+            "--check-invariants": "flag",
+            "--backfill": "flag",
+            "--max-amount": "decimal",
+            "--credit-limit": "decimal",
+            "--fee": "type=fee",
+            "--aggregate": "flag",
+            "--buckets": "decimals",
+            "--min-count": "integer",
+            "--last-activity": "flag",
+            "--fields": [
+                "id", "available", "held", "total", "locked", "disputes", "last_tx",
+                "last_activity", "currency"
+            ],
+            "--currency": "code",
+            "--locale": ["en", "de", "fr"],
+            "--decimals": "integer",
+            "--config": "toml",
+            "--warn-assertions": "flag",
+            "--skip-invalid": "flag",
+            "--filter": "expression",
+            "--supervised": "flag",
+            "--strict": "flag",
+            "--log-format": ["text", "json"],
+            "--log-level": ["error", "warn", "info", "debug", "trace"],
+            "--webhook": "url",
+            "--webhook-retries": "integer",
+            "--webhook-queue": "integer",
+        },
+        "outputs": {
+            "accounts": ["id", "available", "held", "total", "locked"],
+            "--output": "<accounts>",
+            "--last-activity": ["id", "available", "held", "total", "locked", "last_activity"],
+            "--fields": "<fields>",
+            "--audit-log": [
+                "client", "tx", "kind", "available_before", "held_before", "total_before",
+                "available_after", "held_after", "total_after", "reference", "memo"
+            ],
+            "--errors": ["line", "client", "code", "message"],
+            "--aggregate": ["lower", "upper", "accounts", "total"],
+            "--disputes": ["client", "tx", "amount", "reference"],
+            "--deficits": ["client", "deficit"],
+            "--fees": ["client", "fees"],
+            "--aged-funds": ["jurisdiction", "client", "last_activity", "amount"],
+            "--summary": ["metric", "value"],
+            "--trial-balance": ["account", "debit", "credit"],
+            "split": ["<input columns>"],
+            "split manifest": ["shard", "file", "rows", "clients", "total"],
+            "merge-reports": "<accounts>",
+            "canary, diff": ["client", "column", "baseline", "candidate"],
+            "reconcile": ["client", "check", "expected", "actual"],
+            "soak": [
+                "elapsed", "transactions", "rate", "resident_memory", "p50_us", "p99_us",
+                "p999_us", "max_us", "save_ms"
+            ],
+            "evaluate": [
+                "config", "applied", "rejected", "rejected_amount", "locked_accounts", "clients",
+                "total"
+            ],
+            "evaluate --balances": ["config", "client", "available", "held", "total", "locked"],
+            "lock, unlock, adjust": ["clients", "locks", "unlocks", "adjustments", "adjusted"],
+            "--rejects": ["row", "type", "client", "tx", "code", "reason", "reference", "memo"],
+            "--quarantine": ["<input columns>", "error"],
+        },
+    })
+}
+
+/// Report the records applied out of order since the last call, draining the
+/// other events.
+fn report_out_of_order(
+    events: Option<&Receiver<EngineEvent>>,
+    line: Option<u64>,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), Box<dyn Error>> {
+    for event in events.into_iter().flat_map(Receiver::try_iter) {
+        if let EngineEvent::OutOfOrder { client, timestamp, last_activity, .. } = event {
+            let warning = TransactionError::OutOfOrder { timestamp, last_activity };
+            diagnostics.report(line, Some(client), Some(warning.code()), &warning)?;
+        }
+    }
+    Ok(())
+}
+
+/// Execute the legs of a composite transaction, reporting all of them if the
+/// group is rejected.
+fn execute_group(
+    engine: &mut PaymentsEngine,
+    group: Vec<(Transaction, StringRecord)>,
+    failures: &mut Failures,
+) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<_> = group
+        .iter()
+        .map(|(leg, record)| RejectRow::new(record, leg))
+        .collect();
+    let (legs, records): (Vec<_>, Vec<_>) = group.into_iter().unzip();
+
+    if let Err(error) = engine.execute_group(legs) {
+        for (record, row) in records.iter().zip(rows) {
+            failures.rejected(record, row, &error)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a `--fee` flag, the transaction type and the fee being separated by
+/// an equal sign.
+fn parse_fee(s: &str) -> Result<(TransactionKind, Fee), String> {
+    let (kind, fee) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected TYPE=FEE: {s}"))?;
+    Ok((kind.parse()?, fee.parse()?))
+}
+
+/// Get a CSV reader for the file, trimming fields and skipping comments.
+fn csv_reader(path: &str) -> Result<Reader<File>, Box<dyn Error>> {
+    Ok(csv_reader_builder().from_reader(File::open(path)?))
+}
+
+/// Get the CSV reader settings shared by every input.
+fn csv_reader_builder() -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder
+        .trim(csv::Trim::All)
+        .has_headers(true)
+        .flexible(true)
+        .comment(Some(b'#'));
+    builder
 }
@@ -0,0 +1,301 @@
+//! Human-facing rendering of amounts, along with their currency, e.g. for
+//! messages and statements. Machine-readable outputs keep plain decimals.
+
+use std::{fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+
+/// The currencies having a symbol, along with their minor units.
+const CURRENCIES: [(&str, &str, u32); 5] = [
+    ("USD", "$", 2),
+    ("EUR", "€", 2),
+    ("GBP", "£", 2),
+    ("JPY", "¥", 0),
+    ("INR", "₹", 2),
+];
+
+/// An ISO 4217 currency code, e.g. `USD`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Currency {
+    code: String,
+}
+
+impl Currency {
+    #[must_use]
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Get the symbol of the currency, if it's a known one.
+    #[must_use]
+    pub fn symbol(&self) -> Option<&'static str> {
+        self.known().map(|(_, symbol, _)| symbol)
+    }
+
+    /// Get the decimal places amounts are displayed with at least, two for
+    /// the unknown currencies.
+    #[must_use]
+    pub fn minor_units(&self) -> u32 {
+        self.known().map_or(2, |(_, _, units)| units)
+    }
+
+    fn known(&self) -> Option<(&'static str, &'static str, u32)> {
+        CURRENCIES
+            .into_iter()
+            .find(|(code, _, _)| *code == self.code)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 3 && s.bytes().all(|b| b.is_ascii_alphabetic()) {
+            Ok(Self { code: s.to_ascii_uppercase() })
+        } else {
+            Err(format!("Unknown currency: {s}"))
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.code)
+    }
+}
+
+/// The conventions amounts are displayed with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    /// `$1,234.50`, the currency before the amount.
+    #[default]
+    En,
+    /// `1.234,50 €`, the currency after the amount.
+    De,
+    /// `1 234,50 €`, grouping with narrow no-break spaces.
+    Fr,
+}
+
+impl Locale {
+    const fn separators(self) -> (char, char) {
+        match self {
+            Self::En => ('.', ','),
+            Self::De => (',', '.'),
+            Self::Fr => (',', '\u{202f}'),
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Self::En),
+            "de" => Ok(Self::De),
+            "fr" => Ok(Self::Fr),
+            _ => Err(format!("Unknown locale: {s}")),
+        }
+    }
+}
+
+/// An amount in a currency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    pub amount: Decimal,
+    pub currency: Currency,
+}
+
+impl Money {
+    #[must_use]
+    pub const fn new(amount: Decimal, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    /// Render the amount with the symbol of the currency, or its code if it
+    /// has none. Amounts keep their significant digits, and are padded to
+    /// the minor units of the currency.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::money::{Locale, Money};
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let money = Money::new(dec!(-1234.5), "EUR".parse().unwrap());
+    /// assert_eq!(money.format(Locale::En), "-€1,234.50");
+    /// assert_eq!(money.format(Locale::De), "-1.234,50 €");
+    ///
+    /// let money = Money::new(dec!(0.0001), "CHF".parse().unwrap());
+    /// assert_eq!(money.format(Locale::En), "CHF 0.0001");
+    /// ```
+    #[must_use]
+    pub fn format(&self, locale: Locale) -> String {
+        let (decimal, grouping) = locale.separators();
+        // Pad the digits themselves, the largest amounts can't be rescaled
+        let digits = self.amount.abs().normalize().to_string();
+        let (integer, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+        let units = self.currency.minor_units() as usize;
+        let fraction = format!("{fraction:0<units$}");
+
+        let mut number = String::new();
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                number.push(grouping);
+            }
+            number.push(digit);
+        }
+        if !fraction.is_empty() {
+            number.push(decimal);
+            number.push_str(&fraction);
+        }
+
+        let sign = if self.amount.is_sign_negative() && !self.amount.is_zero() {
+            "-"
+        } else {
+            ""
+        };
+        match (locale, self.currency.symbol()) {
+            (Locale::En, Some(symbol)) => format!("{sign}{symbol}{number}"),
+            (Locale::En, None) => format!("{sign}{} {number}", self.currency),
+            (_, Some(symbol)) => format!("{sign}{number} {symbol}"),
+            (_, None) => format!("{sign}{number} {}", self.currency),
+        }
+    }
+
+    /// Parse an amount rendered with the conventions of the locale, stating
+    /// its currency by symbol or by code, before or after the amount.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the currency is missing or the amount is invalid.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::money::{Locale, Money};
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let money = Money::parse("1.234,5 EUR", Locale::De).unwrap();
+    /// assert_eq!((money.amount, money.currency.code()), (dec!(1234.5), "EUR"));
+    /// assert!(Money::parse("1,234.50", Locale::En).is_err());
+    /// ```
+    pub fn parse(s: &str, locale: Locale) -> Result<Self, String> {
+        let (negative, rest) = match s.trim().strip_prefix('-') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, s.trim()),
+        };
+        let (currency, number) =
+            split_currency(rest).ok_or_else(|| format!("Missing currency: {s}"))?;
+
+        let (decimal, grouping) = locale.separators();
+        let number: String = number
+            .chars()
+            .filter(|&c| c != grouping && !c.is_whitespace())
+            .map(|c| if c == decimal { '.' } else { c })
+            .collect();
+        let amount: Decimal = match number.parse() {
+            Ok(amount) if !number.starts_with(['-', '+']) => amount,
+            _ => return Err(format!("Invalid amount: {s}")),
+        };
+
+        let amount = if negative { -amount } else { amount };
+        Ok(Self { amount, currency })
+    }
+}
+
+/// Display the amount with the conventions of `Locale::En`.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(Locale::En))
+    }
+}
+
+/// Split the currency, by symbol or by code, from either end of the amount.
+fn split_currency(s: &str) -> Option<(Currency, &str)> {
+    for (code, symbol, _) in CURRENCIES {
+        let number = s.strip_prefix(symbol).or_else(|| s.strip_suffix(symbol));
+        if let Some(number) = number {
+            return Some((Currency { code: code.to_string() }, number));
+        }
+    }
+
+    let affixes = [
+        s.get(..3).zip(s.get(3..)),
+        s.len()
+            .checked_sub(3)
+            .and_then(|i| s.get(i..).zip(s.get(..i))),
+    ];
+    affixes.into_iter().flatten().find_map(|(code, number)| {
+        let currency = code.parse().ok()?;
+        Some((currency, number))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_money() {
+        let currency = |code: &str| code.parse::<Currency>().unwrap();
+        let cases = [
+            (dec!(1234567.8900), "USD", Locale::En, "$1,234,567.89"),
+            (dec!(12), "JPY", Locale::En, "¥12"),
+            (dec!(-0.5), "GBP", Locale::Fr, "-0,50 £"),
+            (dec!(1234), "EUR", Locale::Fr, "1\u{202f}234,00 €"),
+            (dec!(999.1234), "sek", Locale::De, "999,1234 SEK"),
+            (dec!(-0), "USD", Locale::En, "$0.00"),
+        ];
+        for (amount, code, locale, formatted) in cases {
+            let money = Money::new(amount, currency(code));
+            assert_eq!(money.format(locale), formatted);
+
+            // Formatted amounts parse back, only the scale may differ
+            let parsed = Money::parse(formatted, locale).unwrap();
+            assert_eq!(parsed, Money { amount: parsed.amount, ..money });
+            assert_eq!(parsed.amount.normalize(), amount.normalize());
+        }
+
+        assert_eq!(
+            Money::parse("USD -3", Locale::En),
+            Err("Invalid amount: USD -3".into())
+        );
+        assert_eq!(
+            Money::parse("- 3 USD", Locale::En).unwrap().amount,
+            dec!(-3)
+        );
+        assert_eq!(
+            Money::parse("3", Locale::En),
+            Err("Missing currency: 3".into())
+        );
+        assert_eq!("US".parse::<Currency>(), Err("Unknown currency: US".into()));
+    }
+
+    #[test]
+    fn test_money_limits() {
+        let usd = || "USD".parse::<Currency>().unwrap();
+
+        // The extreme amounts are grouped in full and parse back
+        let formatted = "-$79,228,162,514,264,337,593,543,950,335.00";
+        assert_eq!(Money::new(Decimal::MIN, usd()).to_string(), formatted);
+        let parsed = Money::parse(formatted, Locale::En).unwrap();
+        assert_eq!(parsed.amount, Decimal::MIN);
+
+        // Amounts out of range are rejected instead of overflowing
+        assert_eq!(
+            Money::parse("$79,228,162,514,264,337,593,543,950,336", Locale::En),
+            Err("Invalid amount: $79,228,162,514,264,337,593,543,950,336".into())
+        );
+
+        // Amounts are never rounded to the minor units, only padded
+        let yen = Money::new(dec!(0.5), "JPY".parse().unwrap());
+        assert_eq!(yen.to_string(), "¥0.5");
+        let money = Money::new(dec!(0.0000000000000000000000000001), usd());
+        assert_eq!(money.format(Locale::De), "0,0000000000000000000000000001 $");
+
+        // Excess digits are rounded when parsing
+        let parsed = Money::parse("$0.00000000000000000000000000015", Locale::En).unwrap();
+        assert_eq!(parsed.amount, dec!(0.0000000000000000000000000002));
+    }
+}
@@ -0,0 +1,227 @@
+//! Parquet support for large transaction dumps, enabled by the `parquet`
+//! feature.
+//!
+//! Transaction columns are named as in the CSV format, and any column type
+//! Arrow can cast to a string is accepted, e.g. integer ids and amounts stored
+//! as strings or decimals. Account amounts are written as strings, padded to
+//! `SPEC_DECIMALS` decimal places, so that no precision is lost.
+
+use std::{io::Write, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray, Array, ArrayRef, BooleanArray, RecordBatch, RecordBatchReader, StringArray,
+    UInt16Array,
+};
+use arrow_schema::{DataType, Field, Schema};
+use csv::StringRecord;
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    errors::ParquetError,
+    file::reader::ChunkReader,
+};
+
+use crate::{
+    account::{pad_decimals, Account},
+    transaction::Transaction,
+};
+
+/// Read the transactions of a Parquet file, in order.
+///
+/// # Errors
+///
+/// Returns an error if the file isn't valid Parquet, the iterator fails on
+/// rows which aren't valid transactions.
+pub fn read_transactions<R: ChunkReader + 'static>(
+    reader: R,
+) -> Result<impl Iterator<Item = Result<Transaction, ParquetError>>, ParquetError> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(reader)?.build()?;
+    let headers: StringRecord = reader
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name())
+        .collect();
+
+    let mut row = 0;
+    Ok(reader.flat_map(move |batch| {
+        let transactions = batch
+            .map_err(ParquetError::from)
+            .and_then(|batch| batch_transactions(&batch, &headers, row));
+        let transactions = match transactions {
+            Ok(transactions) => transactions,
+            Err(error) => vec![Err(error)],
+        };
+        row += transactions.len();
+        transactions
+    }))
+}
+
+/// Parse the rows of a record batch the same way as CSV records, the row is
+/// the index of the first one in the file.
+fn batch_transactions(
+    batch: &RecordBatch,
+    headers: &StringRecord,
+    row: usize,
+) -> Result<Vec<Result<Transaction, ParquetError>>, ParquetError> {
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| arrow_cast::cast(column, &DataType::Utf8))
+        .collect::<Result<Vec<_>, _>>()?;
+    let columns: Vec<&StringArray> = columns.iter().map(|column| column.as_string()).collect();
+
+    Ok((0..batch.num_rows())
+        .map(|i| {
+            let record: StringRecord = columns
+                .iter()
+                .map(|column| {
+                    if column.is_valid(i) {
+                        column.value(i)
+                    } else {
+                        ""
+                    }
+                })
+                .collect();
+            record
+                .deserialize(Some(headers))
+                .map_err(|error| ParquetError::General(format!("row {}: {error}", row + i)))
+        })
+        .collect())
+}
+
+/// Write the accounts to a Parquet file with the columns of the CSV output,
+/// getting the writer back.
+///
+/// # Errors
+///
+/// Returns the error of the underlying writer.
+///
+/// # Example
+/// ```
+/// use payments::account::Account;
+/// use payments::parquet_io;
+///
+/// let file = parquet_io::write_accounts(Vec::new(), &[Account::new(1)]).unwrap();
+/// assert!(file.starts_with(b"PAR1"));
+/// ```
+pub fn write_accounts<'a, W: Write + Send>(
+    writer: W,
+    accounts: impl IntoIterator<Item = &'a Account>,
+) -> Result<W, ParquetError> {
+    let accounts: Vec<_> = accounts.into_iter().collect();
+    let amounts = |amount: fn(&Account) -> _| -> ArrayRef {
+        Arc::new(
+            accounts
+                .iter()
+                .map(|account| Some(pad_decimals(amount(account)).to_string()))
+                .collect::<StringArray>(),
+        )
+    };
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt16, false),
+        Field::new("available", DataType::Utf8, false),
+        Field::new("held", DataType::Utf8, false),
+        Field::new("total", DataType::Utf8, false),
+        Field::new("locked", DataType::Boolean, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(
+                accounts
+                    .iter()
+                    .map(|account| account.id)
+                    .collect::<UInt16Array>(),
+            ),
+            amounts(|account| account.available),
+            amounts(|account| account.held),
+            amounts(|account| account.total),
+            Arc::new(
+                accounts
+                    .iter()
+                    .map(|account| Some(account.locked))
+                    .collect::<BooleanArray>(),
+            ),
+        ],
+    )?;
+
+    let mut writer = ArrowWriter::try_new(writer, schema, None)?;
+    writer.write(&batch)?;
+    writer.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Decimal128Array, UInt32Array};
+    use bytes::Bytes;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction_kind::TransactionKind;
+
+    #[test]
+    fn test_read_transactions() {
+        // Write a dump with integer ids and decimal amounts
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::UInt16, false),
+            Field::new("tx", DataType::UInt32, false),
+            Field::new("amount", DataType::Decimal128(20, 4), true),
+        ]));
+        let amounts = Decimal128Array::from(vec![Some(15_000), None, Some(1)])
+            .with_precision_and_scale(20, 4)
+            .unwrap();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["deposit", "dispute", "deposit"])),
+                Arc::new(UInt16Array::from(vec![1, 1, 2])),
+                Arc::new(UInt32Array::from(vec![1, 1, 2])),
+                Arc::new(amounts),
+            ],
+        )
+        .unwrap();
+        let mut writer = ArrowWriter::try_new(Vec::new(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        let file = Bytes::from(writer.into_inner().unwrap());
+
+        // Rows are read back as transactions, nulls are missing values
+        let txs: Vec<_> = read_transactions(file)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            txs,
+            vec![
+                Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1.5000))),
+                Transaction::new(TransactionKind::Dispute, 1, 1, None),
+                Transaction::new(TransactionKind::Deposit, 2, 2, Some(dec!(0.0001))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_accounts() {
+        let mut account = Account::new(1);
+        account.available = dec!(1.000000000000000001);
+        account.total = account.available;
+        let file = Bytes::from(write_accounts(Vec::new(), [&account]).unwrap());
+
+        // Amounts are padded, but never rounded
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let available = batch
+            .column_by_name("available")
+            .unwrap()
+            .as_string::<i32>();
+        let held = batch.column_by_name("held").unwrap().as_string::<i32>();
+        assert_eq!(available.value(0), "1.000000000000000001");
+        assert_eq!(held.value(0), "0.0000");
+    }
+}
@@ -1,24 +1,238 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    iter, mem,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    slice,
+    sync::mpsc,
+    thread,
+};
 
 use rust_decimal::Decimal;
 
-use crate::{account::Account, transaction::Transaction, transaction_kind::TransactionKind};
+use crate::{
+    account::Account,
+    account_report::{AccountReport, ReportFormat},
+    admin::{AdminAction, AdminBatch, AdminError},
+    audit_log::{AuditEntry, AuditLog},
+    dispute_state::{DisputeRecord, DisputeState},
+    engine_config::{DuplicatePolicy, EngineConfig, OutOfOrderPolicy, WithdrawalDisputePolicy},
+    engine_event::{EngineEvent, EventListener},
+    ids::{ClientId, TxId},
+    ledger::{JournalEntry, Ledger},
+    reconciliation,
+    reject_handler::{IgnoreRejects, RejectHandler},
+    snapshot::{Snapshot, SNAPSHOT_VERSION},
+    transaction::Transaction,
+    transaction_error::TransactionError,
+    transaction_kind::TransactionKind,
+};
 
 /// A payment processing engine capable of executing deposits and withdraws as
-/// well as handling disputes.
-pub struct PaymentsEngine {
-    pub accounts: HashMap<u16, Account>,
-    history: HashMap<u32, Transaction>,
+/// well as handling disputes. The accounts and the history are only altered by
+/// the engine, and queried with `account`, `transaction` and the iterators.
+pub struct PaymentsEngine<C = u16, T = u32> {
+    pub(crate) accounts: HashMap<C, Account<C>>,
+    history: HashMap<T, Transaction<C, T>>,
+    ledger: Ledger<C>,
+    config: EngineConfig,
+    quarantined: HashSet<C>,
+    deleted: HashSet<C>,
+    tags: HashMap<C, BTreeSet<String>>,
+    last_txs: HashMap<C, T>,
+    runs: HashMap<String, u64>,
+    reject_handler: Box<dyn RejectHandler<C, T>>,
+    listeners: Vec<Box<dyn EventListener<C, T>>>,
+    audit_log: Option<Box<dyn AuditLog<C, T>>>,
 }
 
 impl PaymentsEngine {
+    /// Create an engine identifying the clients and the transactions as the
+    /// CSV format does, see `ids` for wider ids.
     #[must_use]
     pub fn new() -> Self {
-        Self { accounts: HashMap::new(), history: HashMap::new() }
+        Self::default()
+    }
+
+    /// Create an engine starting from the given accounts instead of empty
+    /// ones, e.g. to seed opening balances. Their funds are journaled against
+    /// the suspense account of the ledger. Engines with other ids are seeded
+    /// by collecting their accounts.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::payments_engine::PaymentsEngine;
+    ///
+    /// let engine = PaymentsEngine::with_accounts([Account::new(1)]);
+    /// assert!(engine.account(1).is_some());
+    /// ```
+    #[must_use]
+    pub fn with_accounts(accounts: impl IntoIterator<Item = Account>) -> Self {
+        accounts.into_iter().collect()
+    }
+
+    /// Restore an engine saved by `PaymentsEngine::save`. The configuration,
+    /// reject handler, listeners and audit log are not part of the saved state and are
+    /// reset to their defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or isn't a valid snapshot.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::payments_engine::PaymentsEngine;
+    ///
+    /// let path = std::env::temp_dir().join("payments-doctest-state.json");
+    /// PaymentsEngine::with_accounts([Account::new(1)]).save(&path).unwrap();
+    ///
+    /// let engine = PaymentsEngine::load(&path).unwrap();
+    /// assert!(engine.account(1).is_some());
+    /// ```
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<C: ClientId, T: TxId> PaymentsEngine<C, T> {
+    /// Save the accounts and the transaction history to a file, so that the
+    /// processing can be resumed by a later run with `PaymentsEngine::load`.
+    /// The state is written next to the file then renamed over it, so that a
+    /// crash never leaves a partially written state behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            accounts: self.accounts.values().map(Into::into).collect(),
+            history: self.history.values().map(Into::into).collect(),
+            quarantined: self.quarantined.iter().copied().collect(),
+            deleted: self.deleted.iter().copied().collect(),
+            tags: self.tags.clone().into_iter().collect(),
+            last_txs: self.last_txs.clone().into_iter().collect(),
+            runs: self.runs.clone().into_iter().collect(),
+            ledger: self.ledger.balances().collect(),
+        };
+
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.flush()?;
+        fs::rename(partial, path)
+    }
+
+    /// Restore an engine saved by `PaymentsEngine::save` from a reader, which
+    /// unlike `load` is available whatever the ids.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state can't be read or isn't a valid snapshot.
+    pub fn from_reader(reader: impl Read) -> io::Result<Self> {
+        let snapshot: Snapshot<C, T> = serde_json::from_reader(reader)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported snapshot version {}", snapshot.version),
+            ));
+        }
+
+        let mut engine: Self = snapshot.accounts.into_iter().map(Account::from).collect();
+        engine.history.extend(
+            snapshot
+                .history
+                .into_iter()
+                .map(Transaction::from)
+                .map(|tx| (tx.id, tx)),
+        );
+        engine.quarantined.extend(snapshot.quarantined);
+        engine.deleted.extend(snapshot.deleted);
+        engine.tags.extend(snapshot.tags);
+        engine.last_txs.extend(snapshot.last_txs);
+        engine.runs.extend(snapshot.runs);
+
+        // States saved before the ledger only journal the opening balances
+        if !snapshot.ledger.is_empty() {
+            engine.ledger = Ledger::from_balances(snapshot.ledger);
+        }
+        Ok(engine)
+    }
+
+    /// Get the double-entry ledger the balances of the accounts are derived
+    /// from.
+    #[must_use]
+    pub const fn ledger(&self) -> &Ledger<C> {
+        &self.ledger
+    }
+
+    /// Get the behavioral policies of the engine.
+    #[must_use]
+    pub const fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Set the behavioral policies of the engine.
+    pub fn set_config(&mut self, config: EngineConfig) {
+        self.config = config;
+    }
+
+    /// Set the strategy used for transactions the engine rejects, by default
+    /// they are silently dropped.
+    pub fn set_reject_handler(&mut self, handler: impl RejectHandler<C, T> + 'static) {
+        self.reject_handler = Box::new(handler);
+    }
+
+    /// Register a listener notified of every event emitted by the engine.
+    pub fn add_listener(&mut self, listener: impl EventListener<C, T> + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    /// Set the audit log recording every balance mutation, replacing the
+    /// previous one if any.
+    pub fn set_audit_log(&mut self, audit_log: impl AuditLog<C, T> + 'static) {
+        self.audit_log = Some(Box::new(audit_log));
+    }
+
+    /// Receive the entries of the audit log, see `set_audit_log`.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction::Transaction;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// let entries = engine.audit();
+    /// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)))).unwrap();
+    ///
+    /// let entry = entries.recv().unwrap();
+    /// assert_eq!((entry.total_before, entry.total_after), (dec!(0), dec!(1)));
+    /// ```
+    pub fn audit(&mut self) -> mpsc::Receiver<AuditEntry<C, T>> {
+        let (sender, receiver) = mpsc::channel();
+        self.set_audit_log(move |entry: &AuditEntry<C, T>| {
+            let _ = sender.send(entry.clone());
+        });
+        receiver
     }
 
     /// Execute the transaction, this will alter the corresponding account
-    /// accordingly.
+    /// accordingly. Invalid transactions are handed to the reject handler.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason for which the transaction was rejected, in which
+    /// case no account was altered.
     ///
     /// # Example
     /// ```
@@ -30,252 +244,3235 @@ impl PaymentsEngine {
     /// let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
     /// let mut engine = PaymentsEngine::new();
     ///
-    /// engine.execute(tx);
-    /// assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(1));
+    /// engine.execute(tx).unwrap();
+    /// assert_eq!(engine.account(1).unwrap().available, dec!(1));
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "info",
+            skip_all,
+            fields(tx = %tx.id, client = %tx.client_id, kind = tx.kind.as_str())
+        )
+    )]
+    pub fn execute(&mut self, tx: Transaction<C, T>) -> Result<(), TransactionError> {
+        let clients: Vec<_> = iter::once(tx.client_id).chain(tx.to_client).collect();
+        let id = tx.id;
+        self.contain(&clients, id, |engine| engine.execute_unguarded(tx))
+    }
+
+    /// Execute the transaction, see `execute`, letting panics unwind.
+    fn execute_unguarded(&mut self, tx: Transaction<C, T>) -> Result<(), TransactionError> {
+        // Claims on a leg of a composite transaction claim the whole group
+        if let Some(group) = self.claimed_group(&tx) {
+            return self.execute_group_claim(tx, group);
+        }
+
+        // Duplicates are either skipped or rejected when applied
+        if self.config.duplicate_transactions == DuplicatePolicy::Ignore && self.is_duplicate(&tx) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(outcome = "ignored", "Skipped a duplicate transaction");
+            return Ok(());
+        }
+
+        // Rejected transactions may still have opened an empty account
+        let states = self.account_states(slice::from_ref(&tx));
+        let result = self.try_apply_audited(&tx);
+        self.emit_created(&states);
+        match result {
+            Ok(applied) => {
+                let id = tx.id;
+                self.record(&tx, &applied);
+                self.commit(tx, &applied);
+                self.emit_locked(&states, id);
+                #[cfg(feature = "tracing")]
+                tracing::debug!(outcome = "applied", "Applied the transaction");
+                Ok(())
+            }
+            Err(error) => {
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    outcome = "rejected",
+                    code = error.code(),
+                    reason = %error,
+                    "Rejected the transaction"
+                );
+                self.reject(&tx, &error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Subscribe to the events of the engine, see `add_listener`. The
+    /// listener is kept even once the receiver is dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::engine_event::EngineEvent;
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction::Transaction;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// let events = engine.subscribe();
+    /// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)))).unwrap();
+    ///
+    /// assert_eq!(events.recv().unwrap(), EngineEvent::AccountCreated { client: 1 });
+    /// ```
+    pub fn subscribe(&mut self) -> mpsc::Receiver<EngineEvent<C, T>> {
+        let (sender, receiver) = mpsc::channel();
+        self.add_listener(move |event: &EngineEvent<C, T>| {
+            let _ = sender.send(event.clone());
+        });
+        receiver
+    }
+
+    /// Execute the legs of a composite transaction (e.g. a purchase made of
+    /// principal, fee and tax) atomically: either all of them are applied or
+    /// none is. Legs can only be deposits or withdrawals, and are recorded
+    /// under the group of the first leg so that the whole group can be later
+    /// disputed as a unit.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason for which the first invalid leg was rejected, in
+    /// which case no account was altered.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use payments::transaction::Transaction;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+    /// engine.execute(deposit_tx).unwrap();
+    ///
+    /// // The fee leg can't be covered, so the principal isn't withdrawn either
+    /// let principal_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(1)));
+    /// let fee_tx = Transaction::new(TransactionKind::Withdrawal, 1, 3, Some(dec!(0.1)));
+    /// assert!(engine.execute_group(vec![principal_tx, fee_tx]).is_err());
+    /// assert_eq!(engine.account(1).unwrap().available, dec!(1));
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "info", skip_all, fields(legs = legs.len()))
+    )]
+    pub fn execute_group(&mut self, legs: Vec<Transaction<C, T>>) -> Result<(), TransactionError> {
+        let clients: Vec<_> = legs.iter().map(|leg| leg.client_id).collect();
+        let id = legs.first().map_or_else(T::default, |leg| leg.id);
+        self.contain(&clients, id, |engine| engine.execute_group_unguarded(legs))
+    }
+
+    /// Execute the legs of a composite transaction, see `execute_group`,
+    /// letting panics unwind.
+    fn execute_group_unguarded(
+        &mut self,
+        legs: Vec<Transaction<C, T>>,
+    ) -> Result<(), TransactionError> {
+        let group = legs.first().and_then(|leg| leg.group.or(Some(leg.id)));
+        let ignore_duplicates = self.config.duplicate_transactions == DuplicatePolicy::Ignore;
+        let legs = legs
+            .into_iter()
+            .filter(|leg| !(ignore_duplicates && self.is_duplicate(leg)))
+            .map(|leg| Transaction { group, ..leg })
+            .collect::<Vec<_>>();
+
+        // Only deposits and withdrawals can be part of a group
+        let allowed = |leg: &Transaction<C, T>| {
+            matches!(
+                leg.kind,
+                TransactionKind::Deposit | TransactionKind::Withdrawal
+            )
+        };
+        if let Some(leg) = legs.iter().find(|leg| !allowed(leg)) {
+            let error = TransactionError::InvalidGroup;
+            self.reject(leg, &error);
+            return Err(error);
+        }
+
+        // Legs are only recorded once all of them are applied, so ids
+        // repeated within the group must be checked upfront
+        let repeated = legs
+            .iter()
+            .enumerate()
+            .find(|(i, leg)| legs[..*i].iter().any(|other| other.id == leg.id));
+        if let Some((_, leg)) = repeated {
+            let error = TransactionError::DuplicateTransaction;
+            self.reject(leg, &error);
+            return Err(error);
+        }
+
+        self.execute_atomically(legs)
+    }
+
+    /// Evaluate the transaction against the current state without applying
+    /// it, e.g. to pre-validate a withdrawal before submitting it. Nothing is
+    /// emitted nor handed to the reject handler.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason for which the transaction would be rejected.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use payments::transaction::Transaction;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+    ///
+    /// // The resulting balances are returned, the account is still missing
+    /// let accounts = engine.simulate(&tx).unwrap();
+    /// assert_eq!(accounts[0].available, dec!(1));
+    /// assert!(engine.account(1).is_none());
     /// ```
+    pub fn simulate(
+        &mut self,
+        tx: &Transaction<C, T>,
+    ) -> Result<Vec<Account<C>>, TransactionError> {
+        let clients: Vec<_> = iter::once(tx.client_id).chain(tx.to_client).collect();
+        self.contain(&clients, tx.id, |engine| engine.simulate_unguarded(tx))
+    }
+
+    /// Evaluate the transaction, see `simulate`, letting panics unwind.
+    fn simulate_unguarded(
+        &mut self,
+        tx: &Transaction<C, T>,
+    ) -> Result<Vec<Account<C>>, TransactionError> {
+        let legs = match self.claimed_group(tx) {
+            Some(group) => self.group_claim_legs(tx, group),
+            None => vec![tx.clone()],
+        };
+        let checkpoint = self.checkpoint(&legs);
+
+        // Duplicates are skipped as if applied under the ignore policy
+        let ignored =
+            self.config.duplicate_transactions == DuplicatePolicy::Ignore && self.is_duplicate(tx);
+        let result = if ignored {
+            Ok(())
+        } else {
+            legs.iter()
+                .try_for_each(|leg| self.try_apply(leg).map(drop))
+        };
+
+        let accounts = checkpoint
+            .accounts
+            .iter()
+            .filter_map(|(id, _)| self.accounts.get(id).cloned())
+            .collect();
+        self.rollback(checkpoint);
+        result.map(|()| accounts)
+    }
+
+    /// Execute the transactions over the given number of threads, getting
+    /// the same results, accounts, events, rejections and audit entries as
+    /// when executing them one after the other. Consecutive legs sharing a
+    /// group are executed as a composite transaction, each getting its result.
+    ///
+    /// Transactions are stamped with their position in the sequence. Those
+    /// touching a single shard of clients, routed as by `ClientId::shard`, are
+    /// executed in parallel by an engine per shard, in order within each one.
+    /// Cross-shard ones, e.g. transfers, composite transactions or claims on
+    /// the transactions of another shard, are executed once every earlier
+    /// transaction is, the more of them the less parallelism. The events,
+    /// rejections and audit entries of the shards are then committed in
+    /// sequence order.
     ///
     /// # Panics
     ///
-    /// See assumptions made in the `README.md` file.
-    pub fn execute(&mut self, tx: Transaction) {
+    /// Resumes the panic of a thread, without containment mode.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction::Transaction;
+    /// use payments::transaction_error::TransactionError;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let txs = [
+    ///     Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2))),
+    ///     Transaction::new(TransactionKind::Deposit, 2, 2, Some(dec!(1))),
+    ///     Transaction::new(TransactionKind::Transfer, 1, 3, Some(dec!(1))).with_recipient(2),
+    ///     Transaction::new(TransactionKind::Withdrawal, 2, 4, Some(dec!(3))),
+    /// ];
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// let results = engine.execute_parallel(&txs, 2);
+    /// assert_eq!(results[3], Err(TransactionError::InsufficientFunds));
+    /// assert_eq!(engine.account(2).unwrap().total, dec!(2));
+    /// ```
+    pub fn execute_parallel(
+        &mut self,
+        txs: &[Transaction<C, T>],
+        threads: usize,
+    ) -> Vec<Result<(), TransactionError>> {
+        let shards = threads.max(1);
+        let mut results = vec![Ok(()); txs.len()];
+
+        // The shard owning the history entry of each transaction id submitted
+        // so far, none if it may be in several
+        let mut owners: HashMap<T, Option<usize>> = HashMap::new();
+        let mut segment = Vec::new();
+        let mut end = 0;
+        for legs in txs.chunk_by(|a, b| a.group.is_some() && a.group == b.group) {
+            let seq = end;
+            end += legs.len();
+            let local = match legs {
+                [tx] if tx.group.is_none() => self.local_shard(tx, shards, &owners),
+                _ => None,
+            };
+            for leg in legs.iter().filter(|leg| is_transfer(leg.kind)) {
+                let shard = leg.client_id.shard(shards);
+                let owner = match self.owner(leg.id, shards, &owners) {
+                    _ if leg.group.is_some() => None,
+                    None => Some(shard),
+                    Some(owner) => owner.filter(|owner| *owner == shard),
+                };
+                owners.insert(leg.id, owner);
+            }
+
+            if let Some(shard) = local {
+                segment.push((seq, shard));
+                continue;
+            }
+            self.execute_segment(txs, mem::take(&mut segment), shards, &mut results);
+            let result = match legs {
+                [tx] if tx.group.is_none() => self.execute(tx.clone()),
+                _ => self.execute_group(legs.to_vec()),
+            };
+            results[seq..end].fill(result);
+        }
+        self.execute_segment(txs, segment, shards, &mut results);
+
+        results
+    }
+
+    /// Get the shard of the transaction if it only touches the accounts and
+    /// the history entries of its client's shard.
+    fn local_shard(
+        &self,
+        tx: &Transaction<C, T>,
+        shards: usize,
+        owners: &HashMap<T, Option<usize>>,
+    ) -> Option<usize> {
+        let shard = tx.client_id.shard(shards);
+        let local = match tx.kind {
+            TransactionKind::Transfer => false,
+            TransactionKind::AssertBalance => true,
+            _ => self
+                .owner(tx.id, shards, owners)
+                .is_none_or(|owner| owner == Some(shard)),
+        };
+        local.then_some(shard)
+    }
+
+    /// Get the shard owning the history entry of the transaction id, if any,
+    /// see `execute_parallel`.
+    fn owner(
+        &self,
+        id: T,
+        shards: usize,
+        owners: &HashMap<T, Option<usize>>,
+    ) -> Option<Option<usize>> {
+        owners.get(&id).copied().or_else(|| {
+            let entry = self.history.get(&id)?;
+            Some(entry.group.is_none().then(|| entry.client_id.shard(shards)))
+        })
+    }
+
+    /// Execute the single-shard transactions of the segment in parallel, on
+    /// engines owning the accounts and history entries they may touch, then
+    /// take them back and commit the outcomes in sequence order.
+    fn execute_segment(
+        &mut self,
+        txs: &[Transaction<C, T>],
+        segment: Vec<(usize, usize)>,
+        shards: usize,
+        results: &mut [Result<(), TransactionError>],
+    ) {
+        let mut sequences = vec![Vec::new(); shards];
+        for (seq, shard) in segment {
+            sequences[shard].push(seq);
+        }
+
+        let mut workers = Vec::new();
+        for sequence in sequences.into_iter().filter(|s| !s.is_empty()) {
+            let mut engine = Self { config: self.config.clone(), ..Self::default() };
+            for tx in sequence.iter().map(|&seq| &txs[seq]) {
+                let client = tx.client_id;
+                if let Some(account) = self.accounts.remove(&client) {
+                    engine.accounts.insert(client, account);
+                }
+                if self.quarantined.contains(&client) {
+                    engine.quarantined.insert(client);
+                }
+                if self.deleted.contains(&client) {
+                    engine.deleted.insert(client);
+                }
+                // Assertions don't refer to the history
+                if tx.kind != TransactionKind::AssertBalance {
+                    if let Some(entry) = self.history.remove(&tx.id) {
+                        engine.history.insert(tx.id, entry);
+                    }
+                }
+            }
+            let events = (!self.listeners.is_empty()).then(|| engine.subscribe());
+            let entries = self.audit_log.is_some().then(|| engine.audit());
+            workers.push((engine, sequence, events, entries));
+        }
+
+        let shards: Vec<(Self, Vec<Stamped<C, T>>)> = thread::scope(|scope| {
+            let workers: Vec<_> = workers
+                .into_iter()
+                .map(|(mut engine, sequence, events, entries)| {
+                    scope.spawn(move || {
+                        let stamped = sequence
+                            .into_iter()
+                            .map(|seq| Stamped {
+                                seq,
+                                result: engine.execute(txs[seq].clone()),
+                                events: events.iter().flat_map(|e| e.try_iter()).collect(),
+                                entries: entries.iter().flat_map(|e| e.try_iter()).collect(),
+                            })
+                            .collect();
+                        (engine, stamped)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|error| panic::resume_unwind(error))
+                })
+                .collect()
+        });
+
+        let mut outcomes = Vec::new();
+        for (engine, stamped) in shards {
+            self.merge_disjoint(engine);
+            outcomes.extend(stamped);
+        }
+        outcomes.sort_unstable_by_key(|stamped| stamped.seq);
+        for stamped in outcomes {
+            if let Some(audit_log) = &mut self.audit_log {
+                for entry in &stamped.entries {
+                    audit_log.record(entry);
+                }
+            }
+            for event in &stamped.events {
+                self.emit(event);
+            }
+            if let Err(error) = &stamped.result {
+                if !self.config.backfill {
+                    self.reject_handler.reject(&txs[stamped.seq], error);
+                }
+            }
+            results[stamped.seq] = stamped.result;
+        }
+    }
+
+    /// Get the group of the transaction claimed by a dispute, resolve or
+    /// chargeback, if any.
+    fn claimed_group(&self, tx: &Transaction<C, T>) -> Option<T> {
         match tx.kind {
-            TransactionKind::Deposit | TransactionKind::Withdrawal => {
-                // Find the account, insert if missing
-                let account = self
-                    .accounts
-                    .entry(tx.client_id)
-                    .or_insert_with(|| Account::new(tx.client_id));
+            TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback => {
+                self.history
+                    .get(&tx.id)
+                    .and_then(|disputed_tx| disputed_tx.group)
+            }
+            _ => None,
+        }
+    }
 
-                // Perform the transaction
-                if handle_transfer(&tx.kind, account, tx.amount.unwrap()) {
-                    // Transaction succeded, add it to the history
-                    self.history.insert(tx.id, tx);
+    /// Execute a claim on every recorded leg of the group atomically.
+    fn execute_group_claim(
+        &mut self,
+        tx: Transaction<C, T>,
+        group: T,
+    ) -> Result<(), TransactionError> {
+        let legs = self.group_claim_legs(&tx, group);
+        self.execute_atomically(legs)
+    }
+
+    /// Get the claims of every recorded leg of the group, the requested leg
+    /// comes first so that its owner gets checked.
+    fn group_claim_legs(&self, tx: &Transaction<C, T>, group: T) -> Vec<Transaction<C, T>> {
+        let mut legs = vec![tx.clone()];
+        legs.extend(
+            self.history
+                .values()
+                .filter(|leg| leg.group == Some(group) && leg.id != tx.id)
+                .map(|leg| Transaction { client_id: leg.client_id, id: leg.id, ..tx.clone() }),
+        );
+        legs
+    }
+
+    /// Apply all the transactions or, if any of them fails, none of them.
+    fn execute_atomically(&mut self, txs: Vec<Transaction<C, T>>) -> Result<(), TransactionError> {
+        let states = self.account_states(&txs);
+        let checkpoint = self.checkpoint(&txs);
+
+        let mut amounts = Vec::with_capacity(txs.len());
+        for tx in &txs {
+            match self.try_apply_audited(tx) {
+                Ok(applied) => amounts.push(applied),
+                Err(error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(
+                        tx = %tx.id,
+                        client = %tx.client_id,
+                        kind = tx.kind.as_str(),
+                        outcome = "rejected",
+                        code = error.code(),
+                        reason = %error,
+                        "Rejected the legs"
+                    );
+                    self.rollback(checkpoint);
+                    self.reject(tx, &error);
+                    return Err(error);
                 }
             }
-            _ => {
-                let disputed_tx = self.history.get_mut(&tx.id);
+        }
+
+        let id = txs.first().map_or_else(T::default, |tx| tx.id);
+        self.emit_created(&states);
+        for (tx, applied) in txs.into_iter().zip(amounts) {
+            self.record(&tx, &applied);
+            self.commit(tx, &applied);
+        }
+        self.emit_locked(&states, id);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(outcome = "applied", "Applied the legs");
+
+        Ok(())
+    }
+
+    /// Keep a copy of everything the transactions could alter: the accounts of
+    /// their clients and recipients, and the history entries they refer to.
+    fn checkpoint(&self, txs: &[Transaction<C, T>]) -> Checkpoint<C, T> {
+        let mut accounts = txs
+            .iter()
+            .flat_map(|tx| iter::once(tx.client_id).chain(tx.to_client))
+            .map(|id| (id, self.accounts.get(&id).cloned()))
+            .collect::<Vec<_>>();
+        let mut history = txs
+            .iter()
+            .map(|tx| (tx.id, self.history.get(&tx.id).cloned()))
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(|(id, _)| *id);
+        accounts.dedup_by_key(|(id, _)| *id);
+        history.dedup_by_key(|(id, _)| *id);
+
+        Checkpoint { accounts, history }
+    }
+
+    /// Get whether the accounts of the clients and recipients of the
+    /// transactions are locked, `None` for the missing ones, to tell the
+    /// lifecycle events to emit once they're applied.
+    fn account_states(&self, txs: &[Transaction<C, T>]) -> Vec<(C, Option<bool>)> {
+        let mut states = txs
+            .iter()
+            .flat_map(|tx| iter::once(tx.client_id).chain(tx.to_client))
+            .map(|id| (id, self.accounts.get(&id).map(|account| account.locked)))
+            .collect::<Vec<_>>();
+        states.sort_by_key(|(id, _)| *id);
+        states.dedup_by_key(|(id, _)| *id);
+        states
+    }
+
+    /// Notify about the accounts which were missing before `account_states`.
+    fn emit_created(&mut self, states: &[(C, Option<bool>)]) {
+        for (id, state) in states {
+            if state.is_none() && self.accounts.contains_key(id) {
+                self.emit(&EngineEvent::AccountCreated { client: *id });
+            }
+        }
+    }
+
+    /// Notify about the accounts locked since `account_states`, by the
+    /// transaction.
+    fn emit_locked(&mut self, states: &[(C, Option<bool>)], tx: T) {
+        for (id, state) in states {
+            let Some(account) = self.accounts.get(id) else {
+                continue;
+            };
+            if account.locked && *state != Some(true) {
+                let event = EngineEvent::AccountLocked {
+                    client: *id,
+                    tx,
+                    account_version: account.version,
+                };
+                self.emit(&event);
+            }
+        }
+    }
+
+    /// Restore the state saved by `checkpoint`, removing the accounts which
+    /// were missing.
+    fn rollback(&mut self, checkpoint: Checkpoint<C, T>) {
+        for (id, account) in checkpoint.accounts {
+            match account {
+                Some(account) => self.accounts.insert(id, account),
+                None => self.accounts.remove(&id),
+            };
+        }
+        for (id, disputed_tx) in checkpoint.history {
+            if let Some(disputed_tx) = disputed_tx {
+                self.history.insert(id, disputed_tx);
+            }
+        }
+    }
+
+    /// Notify about an applied transaction and record it if needed.
+    fn commit(&mut self, tx: Transaction<C, T>, applied: &Applied<C, T>) {
+        let amount = applied.amount;
+        if self.config.out_of_order == OutOfOrderPolicy::Warn {
+            if let Some((timestamp, last_activity)) = self.out_of_order(&tx) {
+                self.emit(&EngineEvent::OutOfOrder {
+                    client: tx.client_id,
+                    tx: tx.id,
+                    timestamp,
+                    last_activity,
+                });
+            }
+        }
+
+        // Assertions don't count as activity, as they don't alter the account
+        if tx.kind != TransactionKind::AssertBalance {
+            let recipient = tx
+                .to_client
+                .filter(|_| tx.kind == TransactionKind::Transfer);
+            for id in iter::once(tx.client_id).chain(recipient) {
+                self.last_txs.insert(id, tx.id);
+                let account = self.accounts.get_mut(&id);
+                if let Some((account, timestamp)) = account.zip(tx.timestamp) {
+                    account.last_activity = account.last_activity.max(Some(timestamp));
+                }
+            }
+        }
+
+        let version = self.accounts.get(&tx.client_id).map_or(0, |a| a.version);
+        self.emit(&applied_event(&tx, amount, version));
+        if !applied.fee.is_zero() {
+            self.emit(&EngineEvent::FeeCharged {
+                client: tx.client_id,
+                tx: tx.id,
+                kind: tx.kind,
+                amount: applied.fee,
+                account_version: version,
+            });
+        }
+
+        // The deposit, withdrawal or transfer succeded, add it to the history
+        // along with the amount actually moved
+        if is_transfer(tx.kind) {
+            self.history
+                .insert(tx.id, Transaction { amount: Some(amount), ..tx });
+        }
+    }
+
+    /// Get the timestamp of the transaction along with the last activity of
+    /// its account, if the transaction is older.
+    fn out_of_order(&self, tx: &Transaction<C, T>) -> Option<(i64, i64)> {
+        let timestamp = tx
+            .timestamp
+            .filter(|_| tx.kind != TransactionKind::AssertBalance)?;
+        let last_activity = self.accounts.get(&tx.client_id)?.last_activity?;
+        (timestamp < last_activity).then_some((timestamp, last_activity))
+    }
+
+    /// Check whether the transaction is a deposit, withdrawal or transfer
+    /// reusing a recorded id.
+    fn is_duplicate(&self, tx: &Transaction<C, T>) -> bool {
+        is_transfer(tx.kind) && self.history.contains_key(&tx.id)
+    }
+
+    /// Notify about a rejected transaction, unless backfilling.
+    fn reject(&mut self, tx: &Transaction<C, T>, error: &TransactionError) {
+        if self.config.backfill {
+            return;
+        }
+
+        self.emit(&EngineEvent::TransactionRejected {
+            client: tx.client_id,
+            tx: tx.id,
+            kind: tx.kind,
+            reason: error.clone(),
+        });
+        self.reject_handler.reject(tx, error);
+    }
+
+    /// Combine the state of an engine which processed another partition of
+    /// the transactions into this one, e.g. to reduce files processed in
+    /// parallel. The balances, fees and versions of the clients found in both
+    /// engines are summed, their tags united, and their last transaction is
+    /// the one of this engine. The summed version counts the changes made by
+    /// either engine, and an account locked in either one stays locked, as a
+    /// chargeback in any partition freezes it. A transaction id found in
+    /// both histories is a conflict, resolved by the duplicate transactions
+    /// policy: the merge is rejected, leaving both engines untouched, or the
+    /// entry of this engine is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns the lowest conflicting transaction id under the reject policy.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction::Transaction;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)))).unwrap();
+    /// let mut other = PaymentsEngine::new();
+    /// other.execute(Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(3)))).unwrap();
+    ///
+    /// engine.merge(other).unwrap();
+    /// assert_eq!(engine.account(1).unwrap().total, dec!(5));
+    /// ```
+    pub fn merge(&mut self, mut other: Self) -> Result<(), MergeConflict<T>> {
+        let conflicts: Vec<T> = other
+            .history
+            .keys()
+            .filter(|id| self.history.contains_key(id))
+            .copied()
+            .collect();
+        if self.config.duplicate_transactions == DuplicatePolicy::Reject {
+            if let Some(&tx) = conflicts.iter().min() {
+                return Err(MergeConflict { tx });
+            }
+        }
+        for id in conflicts {
+            other.history.remove(&id);
+        }
+
+        for (client, account) in mem::take(&mut other.accounts) {
+            let merged = match self.accounts.entry(client) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(account);
+                    continue;
+                }
+            };
+            merged.available = merged.available.saturating_add(account.available);
+            merged.held = merged.held.saturating_add(account.held);
+            merged.total = merged.total.saturating_add(account.total);
+            merged.fees = merged.fees.saturating_add(account.fees);
+            merged.locked |= account.locked;
+            merged.version = merged.version.saturating_add(account.version);
+            merged.last_activity = merged.last_activity.max(account.last_activity);
+            merged.credit_limit = merged.credit_limit.or(account.credit_limit);
+        }
+        for (client, tags) in mem::take(&mut other.tags) {
+            self.tags.entry(client).or_default().extend(tags);
+        }
+        for (client, tx) in mem::take(&mut other.last_txs) {
+            self.last_txs.entry(client).or_insert(tx);
+        }
+        for (id, digest) in mem::take(&mut other.runs) {
+            self.runs.entry(id).or_insert(digest);
+        }
+
+        self.merge_disjoint(other);
+        Ok(())
+    }
+
+    /// Move the accounts and history of another engine, handling a disjoint
+    /// set of clients, into this one.
+    pub(crate) fn merge_disjoint(&mut self, other: Self) {
+        self.accounts.extend(other.accounts);
+        self.history.extend(other.history);
+        self.ledger.merge(other.ledger);
+        self.quarantined.extend(other.quarantined);
+        self.deleted.extend(other.deleted);
+        self.tags.extend(other.tags);
+        self.last_txs.extend(other.last_txs);
+        self.runs.extend(other.runs);
+    }
+
+    /// Record a completed processing run along with the digest of its inputs.
+    /// Runs are kept in the saved state, so that pipelines can detect when the
+    /// same run is submitted twice.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// engine.record_run("2024-01-01", 42);
+    /// assert_eq!(engine.run_digest("2024-01-01"), Some(42));
+    /// ```
+    pub fn record_run(&mut self, id: impl Into<String>, digest: u64) {
+        self.runs.insert(id.into(), digest);
+    }
+
+    /// Get the input digest of a recorded run, see
+    /// `PaymentsEngine::record_run`.
+    #[must_use]
+    pub fn run_digest(&self, id: &str) -> Option<u64> {
+        self.runs.get(id).copied()
+    }
+
+    /// Apply a batch of administrative actions or, if any of them fails, none
+    /// of them, getting the audit record of the batch. Unlike transactions,
+    /// actions apply to locked accounts too and the batch is notified as a
+    /// whole, the audit log isn't fed.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first failed action, e.g. an adjustment debiting more than
+    /// the available funds or any action on a deleted account.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::admin::AdminAction;
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::with_accounts([Account::new(1)]);
+    /// let actions = [
+    ///     AdminAction::Lock { client: 1 },
+    ///     AdminAction::Adjust { client: 1, amount: dec!(2) },
+    /// ];
+    ///
+    /// let batch = engine.administer(&actions).unwrap();
+    /// assert_eq!(batch.adjusted, dec!(2));
+    /// assert!(engine.account(1).unwrap().locked);
+    /// ```
+    pub fn administer(
+        &mut self,
+        actions: &[AdminAction<C>],
+    ) -> Result<AdminBatch<C>, AdminError<C>> {
+        let mut accounts: Vec<_> = actions
+            .iter()
+            .map(|action| {
+                (
+                    action.client(),
+                    self.accounts.get(&action.client()).cloned(),
+                )
+            })
+            .collect();
+        accounts.sort_by_key(|(id, _)| *id);
+        accounts.dedup_by_key(|(id, _)| *id);
+
+        let mut batch = AdminBatch::default();
+        for (index, action) in actions.iter().enumerate() {
+            if let Err(error) = self.apply_admin(*action, &mut batch) {
+                self.rollback(Checkpoint { accounts, history: Vec::new() });
+                return Err(AdminError { index, client: action.client(), error });
+            }
+        }
+        batch.clients = accounts.iter().map(|(id, _)| *id).collect();
+
+        self.ledger
+            .post(&JournalEntry::<C, T>::adjustments(actions));
+        for (id, account) in &accounts {
+            if account.is_none() && self.accounts.contains_key(id) {
+                self.emit(&EngineEvent::AccountCreated { client: *id });
+            }
+        }
+        self.emit(&EngineEvent::AdminBatchApplied(batch.clone()));
+        if self.config.check_invariants {
+            self.debug_assert_invariants();
+        }
+
+        Ok(batch)
+    }
+
+    /// Apply an administrative action, see `administer`, counting it in the
+    /// batch.
+    fn apply_admin(
+        &mut self,
+        action: AdminAction<C>,
+        batch: &mut AdminBatch<C>,
+    ) -> Result<(), TransactionError> {
+        let client = action.client();
+        if self.quarantined.contains(&client) {
+            return Err(TransactionError::ClientQuarantined);
+        }
+        if self.deleted.contains(&client) {
+            return Err(TransactionError::AccountDeleted);
+        }
+
+        match action {
+            AdminAction::Lock { .. } => {
+                let account = self
+                    .accounts
+                    .entry(client)
+                    .or_insert_with(|| Account::open(client));
+                if !account.locked {
+                    account.locked = true;
+                    account.version += 1;
+                }
+                batch.locks += 1;
+            }
+            AdminAction::Unlock { .. } => {
+                if let Some(account) = self.accounts.get_mut(&client).filter(|a| a.locked) {
+                    account.locked = false;
+                    account.version += 1;
+                }
+                batch.unlocks += 1;
+            }
+            AdminAction::Adjust { amount, .. } => {
+                let account = self
+                    .accounts
+                    .entry(client)
+                    .or_insert_with(|| Account::open(client));
+                if amount.is_sign_negative() {
+                    account.withdraw(-amount, Decimal::ZERO)?;
+                } else {
+                    account.deposit(amount)?;
+                }
+                batch.adjustments += 1;
+                batch.adjusted = batch.adjusted.saturating_add(amount);
+            }
+        }
+        Ok(())
+    }
+
+    /// Soft-delete an account, e.g. when offboarding its client: it's left
+    /// out of the reports and its transactions are rejected, while its history
+    /// is kept. Returns whether an active account was deleted.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::payments_engine::PaymentsEngine;
+    ///
+    /// let mut engine = PaymentsEngine::with_accounts([Account::new(1)]);
+    /// assert!(engine.delete_account(1));
+    /// assert_eq!(engine.accounts_sorted().count(), 0);
+    ///
+    /// assert!(engine.restore_account(1));
+    /// assert_eq!(engine.accounts_sorted().count(), 1);
+    /// ```
+    pub fn delete_account(&mut self, id: C) -> bool {
+        let deleted = self.accounts.contains_key(&id) && self.deleted.insert(id);
+        if deleted {
+            self.emit(&EngineEvent::AccountDeleted { client: id });
+        }
+        deleted
+    }
+
+    /// Restore a soft-deleted account, see `PaymentsEngine::delete_account`.
+    /// Returns whether the account was deleted.
+    pub fn restore_account(&mut self, id: C) -> bool {
+        let restored = self.deleted.remove(&id);
+        if restored {
+            self.emit(&EngineEvent::AccountRestored { client: id });
+        }
+        restored
+    }
+
+    /// Attach a tag to an account (e.g. "vip" or "under-investigation"), the
+    /// account doesn't need to exist yet. Returns whether the tag was added.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::payments_engine::PaymentsEngine;
+    ///
+    /// let mut engine = PaymentsEngine::with_accounts([Account::new(1), Account::new(2)]);
+    /// engine.tag_account(2, "vip");
+    ///
+    /// let vips: Vec<_> = engine.tagged_accounts("vip").map(|account| account.id).collect();
+    /// assert_eq!(vips, vec![2]);
+    /// ```
+    pub fn tag_account(&mut self, id: C, tag: impl Into<String>) -> bool {
+        self.tags.entry(id).or_default().insert(tag.into())
+    }
+
+    /// Remove a tag from an account. Returns whether the account had the tag.
+    pub fn untag_account(&mut self, id: C, tag: &str) -> bool {
+        let removed = self.tags.get_mut(&id).is_some_and(|tags| tags.remove(tag));
+        if self.tags.get(&id).is_some_and(BTreeSet::is_empty) {
+            self.tags.remove(&id);
+        }
+        removed
+    }
+
+    /// Iterate over the tags of an account, in alphabetical order.
+    pub fn account_tags(&self, id: C) -> impl Iterator<Item = &str> {
+        self.tags.get(&id).into_iter().flatten().map(String::as_str)
+    }
+
+    /// Iterate over the active accounts having the tag, in ascending client id
+    /// order.
+    pub fn tagged_accounts<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Account<C>> {
+        self.accounts_sorted()
+            .filter(move |account| self.tags.get(&account.id).is_some_and(|t| t.contains(tag)))
+    }
+
+    /// Iterate over the clients whose account is soft-deleted.
+    pub fn deleted_accounts(&self) -> impl Iterator<Item = C> + '_ {
+        self.deleted.iter().copied()
+    }
+
+    /// Get the account of the client, even if soft-deleted.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::payments_engine::PaymentsEngine;
+    ///
+    /// let engine = PaymentsEngine::with_accounts([Account::new(1)]);
+    /// assert_eq!(engine.account(1), Some(&Account::new(1)));
+    /// assert!(engine.account(2).is_none());
+    /// ```
+    #[must_use]
+    pub fn account(&self, id: C) -> Option<&Account<C>> {
+        self.accounts.get(&id)
+    }
+
+    /// Iterate over every account, including the soft-deleted ones, in no
+    /// particular order.
+    pub fn accounts(&self) -> impl ExactSizeIterator<Item = &Account<C>> {
+        self.accounts.values()
+    }
+
+    /// Iterate over the active locked accounts, in ascending client id order.
+    pub fn locked_accounts(&self) -> impl Iterator<Item = &Account<C>> {
+        self.accounts_sorted().filter(|account| account.locked)
+    }
+
+    /// Iterate over the active accounts in ascending client id order, unlike
+    /// `accounts` which has no stable order and includes soft-deleted
+    /// accounts.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::account::Account;
+    /// use payments::payments_engine::PaymentsEngine;
+    ///
+    /// let engine = PaymentsEngine::with_accounts([Account::new(2), Account::new(1)]);
+    /// let ids: Vec<_> = engine.accounts_sorted().map(|account| account.id).collect();
+    /// assert_eq!(ids, vec![1, 2]);
+    /// ```
+    pub fn accounts_sorted(&self) -> impl Iterator<Item = &Account<C>> {
+        let mut accounts: Vec<_> = self.active_accounts().collect();
+        accounts.sort_unstable_by_key(|account| account.id);
+        accounts.into_iter()
+    }
+
+    /// Iterate over the active accounts with an outstanding deficit, see
+    /// `Account::deficit`.
+    pub fn negative_balances(&self) -> impl Iterator<Item = &Account<C>> {
+        self.active_accounts()
+            .filter(|account| account.deficit() > Decimal::ZERO)
+    }
+
+    /// Iterate over the accounts which are not soft-deleted.
+    fn active_accounts(&self) -> impl Iterator<Item = &Account<C>> {
+        self.accounts
+            .values()
+            .filter(|account| !self.deleted.contains(&account.id))
+    }
+
+    /// Iterate over the deposits, withdrawals and transfers applied so far,
+    /// along with their dispute state, in no particular order.
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction<C, T>> {
+        self.history.values()
+    }
+
+    /// Get a deposit, withdrawal or transfer applied so far, along with its
+    /// dispute state.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::dispute_state::DisputeState;
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction::Transaction;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)))).unwrap();
+    /// engine.execute(Transaction::new(TransactionKind::Dispute, 1, 1, None)).unwrap();
+    ///
+    /// assert_eq!(engine.transaction(1).unwrap().dispute_state, DisputeState::Open);
+    /// assert_eq!(engine.transactions_for_client(2).count(), 0);
+    /// ```
+    #[must_use]
+    pub fn transaction(&self, id: T) -> Option<&Transaction<C, T>> {
+        self.history.get(&id)
+    }
+
+    /// Iterate over the transactions applied so far on behalf of the client,
+    /// i.e. the ones it can claim, in no particular order. Transfers it
+    /// received belong to their sender.
+    pub fn transactions_for_client(&self, id: C) -> impl Iterator<Item = &Transaction<C, T>> {
+        self.history.values().filter(move |tx| tx.client_id == id)
+    }
+
+    /// Get the id of the latest transaction applied to the account, either on
+    /// behalf of its client or as the recipient of a transfer. Claims refer to
+    /// the transaction they claim, and balance assertions are not counted.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::payments_engine::PaymentsEngine;
+    /// use payments::transaction::Transaction;
+    /// use payments::transaction_kind::TransactionKind;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut engine = PaymentsEngine::new();
+    /// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)))).unwrap();
+    /// let transfer = Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(1)));
+    /// engine.execute(transfer.with_recipient(2)).unwrap();
+    ///
+    /// assert_eq!(engine.last_transaction(2), Some(2));
+    /// assert_eq!(engine.last_transaction(3), None);
+    /// ```
+    #[must_use]
+    pub fn last_transaction(&self, id: C) -> Option<T> {
+        self.last_txs.get(&id).copied()
+    }
+
+    /// Report the accounts in the given format, along with their open
+    /// disputes and last transaction, see `account_report`.
+    pub fn reports<'a>(
+        &'a self,
+        accounts: impl IntoIterator<Item = &'a Account<C>> + 'a,
+        format: &'a ReportFormat,
+    ) -> impl Iterator<Item = AccountReport<'a, C, T>> + 'a {
+        let mut disputes: HashMap<C, usize> = HashMap::new();
+        for tx in self.disputes() {
+            *disputes.entry(tx.client_id).or_default() += 1;
+        }
+
+        accounts.into_iter().map(move |account| AccountReport {
+            disputes: disputes.get(&account.id).copied().unwrap_or_default(),
+            last_tx: self.last_transaction(account.id),
+            ..AccountReport::new(account, format)
+        })
+    }
+
+    /// Iterate over the transactions currently under dispute.
+    pub fn disputes(&self) -> impl Iterator<Item = &Transaction<C, T>> {
+        self.history
+            .values()
+            .filter(|tx| tx.dispute_state == DisputeState::Open)
+    }
+
+    /// Notify every listener of the event, unless backfilling.
+    fn emit(&mut self, event: &EngineEvent<C, T>) {
+        if self.config.backfill {
+            return;
+        }
+
+        for listener in &mut self.listeners {
+            listener.on_event(event);
+        }
+    }
+
+    /// Check the invariants of every account, see
+    /// `reconciliation::check_invariants`, as done after each transaction in
+    /// invariant checking mode. Release builds skip the check.
+    ///
+    /// # Panics
+    ///
+    /// Panics listing the violations, if any, in debug builds.
+    pub fn debug_assert_invariants(&self) {
+        if cfg!(debug_assertions) {
+            let violations =
+                reconciliation::check_invariants(self, self.config.allow_negative_balances);
+            debug_assert!(
+                violations.is_empty(),
+                "Invariants violated: {}",
+                violations.join("; ")
+            );
+        }
+    }
+
+    /// Iterate over the clients quarantined after a panic, their transactions
+    /// are all rejected.
+    pub fn quarantined_clients(&self) -> impl Iterator<Item = C> + '_ {
+        self.quarantined.iter().copied()
+    }
+
+    /// Run a transaction, recovering from panics under containment mode: the
+    /// clients of the transaction are quarantined, as their state can't be
+    /// trusted anymore, instead of aborting the whole run. The accounts of
+    /// the other clients were not touched.
+    fn contain<R>(
+        &mut self,
+        clients: &[C],
+        tx: T,
+        run: impl FnOnce(&mut Self) -> Result<R, TransactionError>,
+    ) -> Result<R, TransactionError> {
+        let run = |engine: &mut Self| {
+            let result = run(engine);
+            if engine.config.check_invariants {
+                engine.debug_assert_invariants();
+            }
+            result
+        };
+        if !self.config.contain_panics {
+            return run(self);
+        }
+
+        panic::catch_unwind(AssertUnwindSafe(|| run(self))).unwrap_or_else(|_| {
+            for &client in clients {
+                if self.quarantined.insert(client) {
+                    self.emit(&EngineEvent::ClientQuarantined { client, tx });
+                }
+            }
+            Err(TransactionError::ClientQuarantined)
+        })
+    }
+
+    /// Apply the transaction unless its client is quarantined or deleted,
+    /// getting the amount it moved along with the fee charged for it.
+    fn try_apply(
+        &mut self,
+        tx: &Transaction<C, T>,
+    ) -> Result<(Decimal, Decimal), TransactionError> {
+        if self.quarantined.contains(&tx.client_id) {
+            return Err(TransactionError::ClientQuarantined);
+        }
+        if self.deleted.contains(&tx.client_id) {
+            return Err(TransactionError::AccountDeleted);
+        }
+
+        // Assertions don't alter the account, so they're never charged
+        let fee = self
+            .config
+            .fees
+            .get(&tx.kind)
+            .copied()
+            .filter(|_| tx.kind != TransactionKind::AssertBalance);
+        let Some(fee) = fee else {
+            return self.apply(tx).map(|amount| (amount, Decimal::ZERO));
+        };
+
+        // The transaction is reverted if the client can't afford the fee
+        let checkpoint = self.checkpoint(slice::from_ref(tx));
+        let amount = self.apply(tx)?;
+        let fee = fee.amount(amount);
+        let credit_limit = match tx.kind {
+            TransactionKind::Chargeback => Decimal::MAX,
+            _ => self.credit_limit(tx.client_id),
+        };
+        let charged = match self.accounts.get_mut(&tx.client_id) {
+            Some(account) if !fee.is_zero() => account.charge_fee(fee, credit_limit),
+            _ => Ok(()),
+        };
+        if let Err(error) = charged {
+            self.rollback(checkpoint);
+            return Err(error);
+        }
+
+        Ok((amount, fee))
+    }
+
+    /// Apply the transaction, see `try_apply`, getting the changes of the
+    /// accounts it altered.
+    fn try_apply_audited(
+        &mut self,
+        tx: &Transaction<C, T>,
+    ) -> Result<Applied<C, T>, TransactionError> {
+        let recipient = tx
+            .to_client
+            .filter(|_| tx.kind == TransactionKind::Transfer);
+        let before: Vec<_> = iter::once(tx.client_id)
+            .chain(recipient)
+            .map(|id| self.accounts.get(&id).cloned().unwrap_or(Account::open(id)))
+            .collect();
+        let (amount, fee) = self.try_apply(tx)?;
+        let changes = before
+            .iter()
+            .filter_map(|before| {
+                let after = self.accounts.get(&before.id)?;
+                AuditEntry::new(tx.id, tx.kind, before, after).map(|entry| entry.with_metadata(tx))
+            })
+            .collect();
+        Ok(Applied { amount, fee, changes })
+    }
+
+    /// Journal the changes of the accounts made by an applied transaction,
+    /// then hand them to the audit log if any, even when backfilling.
+    fn record(&mut self, tx: &Transaction<C, T>, applied: &Applied<C, T>) {
+        let entry = JournalEntry::new(Some(tx.id), tx.kind, &applied.changes, applied.fee);
+        self.ledger.post(&entry);
+        if let Some(audit_log) = &mut self.audit_log {
+            for entry in &applied.changes {
+                audit_log.record(entry);
+            }
+        }
+    }
+
+    /// Get how far withdrawals can take the available funds of the client
+    /// below zero: the limit of its account if any, or the one of the engine.
+    fn credit_limit(&self, client: C) -> Decimal {
+        self.accounts
+            .get(&client)
+            .and_then(|account| account.credit_limit)
+            .unwrap_or(self.config.credit_limit)
+    }
+
+    /// Validate a deposit, withdrawal or transfer before moving any funds,
+    /// getting its normalized amount.
+    fn transfer_amount(&self, tx: &Transaction<C, T>) -> Result<Decimal, TransactionError> {
+        if self.is_duplicate(tx) {
+            return Err(TransactionError::DuplicateTransaction);
+        }
+        let amount = tx.amount.ok_or(TransactionError::MissingAmount)?;
+        let amount = self.config.excess_precision.normalize(amount)?;
+
+        // Negative amounts would move the funds the other way around
+        match self.config.max_amount {
+            _ if amount <= Decimal::ZERO => Err(TransactionError::NonPositiveAmount),
+            Some(max) if amount > max => Err(TransactionError::AmountTooLarge),
+            _ => Ok(amount),
+        }
+    }
+
+    /// Apply the transaction to the corresponding account, returning the
+    /// amount of funds it moved.
+    fn apply(&mut self, tx: &Transaction<C, T>) -> Result<Decimal, TransactionError> {
+        // Locked accounts reject transactions according to the policy
+        let locked = self.accounts.get(&tx.client_id).is_some_and(|a| a.locked);
+        if locked && self.config.locked_accounts.rejects(tx.kind) {
+            return Err(TransactionError::AccountLocked);
+        }
+
+        if self.config.out_of_order == OutOfOrderPolicy::Reject {
+            if let Some((timestamp, last_activity)) = self.out_of_order(tx) {
+                return Err(TransactionError::OutOfOrder { timestamp, last_activity });
+            }
+        }
+
+        match tx.kind {
+            TransactionKind::Deposit | TransactionKind::Withdrawal => {
+                let amount = self.transfer_amount(tx)?;
+                let credit_limit = self.credit_limit(tx.client_id);
+
+                // Find the account, insert if missing
+                let account = self
+                    .accounts
+                    .entry(tx.client_id)
+                    .or_insert_with(|| Account::open(tx.client_id));
+
+                // Perform the transaction
+                handle_transfer(tx.kind, account, amount, credit_limit)?;
+                Ok(amount)
+            }
+            TransactionKind::Transfer => {
+                let amount = self.transfer_amount(tx)?;
+
+                // The recipient must be another client, able to receive funds
+                let recipient = tx
+                    .to_client
+                    .filter(|id| *id != tx.client_id)
+                    .ok_or(TransactionError::InvalidRecipient)?;
+                if self.quarantined.contains(&recipient) {
+                    return Err(TransactionError::ClientQuarantined);
+                }
+                if self.deleted.contains(&recipient) {
+                    return Err(TransactionError::AccountDeleted);
+                }
+                let recipient_locked = self.accounts.get(&recipient).is_some_and(|a| a.locked);
+                if recipient_locked
+                    && self
+                        .config
+                        .locked_accounts
+                        .rejects(TransactionKind::Deposit)
+                {
+                    return Err(TransactionError::AccountLocked);
+                }
+
+                // Credit a copy of the recipient first, so that nothing is
+                // altered if the funds are insufficient or would overflow
+                let mut credited = self
+                    .accounts
+                    .get(&recipient)
+                    .cloned()
+                    .unwrap_or_else(|| Account::open(recipient));
+                credited.deposit(amount)?;
+                let credit_limit = self.credit_limit(tx.client_id);
+                self.accounts
+                    .entry(tx.client_id)
+                    .or_insert_with(|| Account::open(tx.client_id))
+                    .withdraw(amount, credit_limit)?;
+                self.accounts.insert(recipient, credited);
+                Ok(amount)
+            }
+            TransactionKind::AssertBalance => {
+                // Missing accounts are considered empty
+                let expected = tx.amount.ok_or(TransactionError::MissingAmount)?;
+                let actual = self
+                    .accounts
+                    .get(&tx.client_id)
+                    .map_or(Decimal::ZERO, |a| a.total);
+                if actual != expected {
+                    return Err(TransactionError::AssertionFailed { expected, actual });
+                }
+
+                Ok(actual)
+            }
+            _ => {
+                // If the disputed tx doesn't exist reject this tx, transfers
+                // between clients can't be disputed and neither can
+                // withdrawals under the legacy behavior
+                let deny_withdrawals =
+                    self.config.withdrawal_disputes == WithdrawalDisputePolicy::Deny;
+                let disputed_tx = self
+                    .history
+                    .get_mut(&tx.id)
+                    .filter(|disputed_tx| match disputed_tx.kind {
+                        TransactionKind::Transfer => false,
+                        TransactionKind::Withdrawal => !deny_withdrawals,
+                        _ => true,
+                    })
+                    .ok_or(TransactionError::UnknownTransaction)?;
+
+                // Only the owner of the disputed tx can claim it
+                if disputed_tx.client_id != tx.client_id {
+                    return Err(TransactionError::ClientMismatch);
+                }
+
+                // Check the claim is allowed in the current dispute state
+                let disputes = u32::try_from(disputed_tx.disputes.len()).unwrap_or(u32::MAX);
+                let state =
+                    disputed_tx
+                        .dispute_state
+                        .claim(tx.kind, self.config.redisputes, disputes)?;
+
+                // Deposits and withdrawals in the history always created their
+                // account, unless the saved state was tampered with
+                let account = self
+                    .accounts
+                    .get_mut(&tx.client_id)
+                    .ok_or(TransactionError::UnknownTransaction)?;
+                let amount = disputed_tx.amount.ok_or(TransactionError::MissingAmount)?;
+                let allow_negative = self.config.allow_negative_balances;
+                handle_claim(tx.kind, disputed_tx.kind, account, amount, allow_negative)?;
+
+                // Claim succeded, move to the new state and keep track of the
+                // disputes and the latest case reference
+                if state == DisputeState::Open {
+                    let dispute = DisputeRecord::open(tx.timestamp, tx.reference.clone());
+                    disputed_tx.disputes.push(dispute);
+                } else if let Some(dispute) = disputed_tx.disputes.last_mut() {
+                    dispute.close(state, tx.timestamp, tx.reference.as_ref());
+                }
+                disputed_tx.dispute_state = state;
+                if tx.reference.is_some() {
+                    disputed_tx.dispute_reference.clone_from(&tx.reference);
+                }
+                Ok(amount)
+            }
+        }
+    }
+}
+
+/// Seed the engine with the accounts, see `PaymentsEngine::with_accounts`.
+impl<C: ClientId, T: TxId> FromIterator<Account<C>> for PaymentsEngine<C, T> {
+    fn from_iter<I: IntoIterator<Item = Account<C>>>(accounts: I) -> Self {
+        let mut engine = Self::default();
+        for account in accounts {
+            engine.ledger.post(&JournalEntry::<C, T>::opening(&account));
+            engine.accounts.insert(account.id, account);
+        }
+        engine
+    }
+}
+
+impl<C: ClientId, T: TxId> Default for PaymentsEngine<C, T> {
+    fn default() -> Self {
+        Self {
+            accounts: HashMap::new(),
+            history: HashMap::new(),
+            ledger: Ledger::default(),
+            config: EngineConfig::default(),
+            quarantined: HashSet::new(),
+            deleted: HashSet::new(),
+            tags: HashMap::new(),
+            last_txs: HashMap::new(),
+            runs: HashMap::new(),
+            reject_handler: Box::new(IgnoreRejects),
+            listeners: Vec::new(),
+            audit_log: None,
+        }
+    }
+}
+
+/// A transaction id found in the histories of both merged engines, see
+/// `PaymentsEngine::merge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeConflict<T = u32> {
+    pub tx: T,
+}
+
+impl<T: TxId> fmt::Display for MergeConflict<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transaction {} is in both engines", self.tx)
+    }
+}
+
+impl<T: TxId> Error for MergeConflict<T> {}
+
+/// The accounts and history entries which could be altered by a set of
+/// transactions, as they were before applying them.
+struct Checkpoint<C, T> {
+    accounts: Vec<(C, Option<Account<C>>)>,
+    history: Vec<(T, Option<Transaction<C, T>>)>,
+}
+
+/// The outcome of a transaction executed by a shard, along with what it
+/// notified, to be committed in sequence order.
+struct Stamped<C, T> {
+    seq: usize,
+    result: Result<(), TransactionError>,
+    events: Vec<EngineEvent<C, T>>,
+    entries: Vec<AuditEntry<C, T>>,
+}
+
+/// A transaction applied but not committed yet: the amount of funds it
+/// moved, the fee charged for it and the changes of the accounts it altered.
+struct Applied<C, T> {
+    amount: Decimal,
+    fee: Decimal,
+    changes: Vec<AuditEntry<C, T>>,
+}
+
+/// Build the event describing a successfully applied transaction.
+const fn applied_event<C: ClientId, T: TxId>(
+    tx: &Transaction<C, T>,
+    amount: Decimal,
+    account_version: u64,
+) -> EngineEvent<C, T> {
+    let (client, tx_id) = (tx.client_id, tx.id);
+    match tx.kind {
+        TransactionKind::Deposit => {
+            EngineEvent::DepositApplied { client, tx: tx_id, amount, account_version }
+        }
+        TransactionKind::Withdrawal => {
+            EngineEvent::WithdrawalApplied { client, tx: tx_id, amount, account_version }
+        }
+        TransactionKind::Transfer => EngineEvent::TransferApplied {
+            client,
+            // Transfers are only applied with a recipient
+            to_client: match tx.to_client {
+                Some(to_client) => to_client,
+                None => client,
+            },
+            tx: tx_id,
+            amount,
+            account_version,
+        },
+        TransactionKind::Dispute => {
+            EngineEvent::DisputeOpened { client, tx: tx_id, amount, account_version }
+        }
+        TransactionKind::Resolve => {
+            EngineEvent::DisputeResolved { client, tx: tx_id, amount, account_version }
+        }
+        TransactionKind::Chargeback => {
+            EngineEvent::ChargebackApplied { client, tx: tx_id, amount, account_version }
+        }
+        TransactionKind::AssertBalance => {
+            EngineEvent::BalanceAsserted { client, total: amount, account_version }
+        }
+    }
+}
+
+/// Check whether the transaction is a deposit, withdrawal or transfer, i.e.
+/// it moves funds and is recorded in the history.
+const fn is_transfer(kind: TransactionKind) -> bool {
+    matches!(
+        kind,
+        TransactionKind::Deposit | TransactionKind::Withdrawal | TransactionKind::Transfer
+    )
+}
+
+/// Perform the actual transfer, that is: a deposit or a withdrawal.
+fn handle_transfer<C: ClientId>(
+    kind: TransactionKind,
+    account: &mut Account<C>,
+    amount: Decimal,
+    credit_limit: Decimal,
+) -> Result<(), TransactionError> {
+    if kind == TransactionKind::Deposit {
+        account.deposit(amount)
+    } else {
+        account.withdraw(amount, credit_limit)
+    }
+}
+
+/// Perform the actual claim, that is: a dispute, a resolve or a chargeback,
+/// according to the kind of the disputed transaction.
+fn handle_claim<C: ClientId>(
+    kind: TransactionKind,
+    disputed_kind: TransactionKind,
+    client: &mut Account<C>,
+    amount: Decimal,
+    allow_negative: bool,
+) -> Result<(), TransactionError> {
+    if disputed_kind == TransactionKind::Withdrawal {
+        return match kind {
+            TransactionKind::Dispute => client.dispute_withdrawal(amount),
+            TransactionKind::Resolve => client.resolve_withdrawal(amount),
+            _ => client.chargeback_withdrawal(amount),
+        };
+    }
+
+    match kind {
+        TransactionKind::Dispute if allow_negative => client.force_dispute(amount),
+        TransactionKind::Dispute => client.dispute(amount),
+        TransactionKind::Resolve => client.resolve(amount),
+        _ => client.chargeback(amount),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        engine_config::{ExcessPrecisionPolicy, Fee, LockedAccountPolicy},
+        ledger::LedgerAccount,
+        reject_handler::CollectRejects,
+        soak::Generator,
+    };
+
+    #[test]
+    fn test_deposit() {
+        // Create transaction
+        let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        let mut expected = Account::new(1);
+
+        // Deposit on both sides
+        engine.execute(tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_withdraw() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(1)));
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        let mut expected = Account::new(1);
+
+        // Deposit on both sides
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Withdraw on both sides
+        engine.execute(withdraw_tx).unwrap();
+        expected.withdraw(dec!(1), dec!(0)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_dispute() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        let mut expected = Account::new(1);
+
+        // Deposit on both sides
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Dispute on both sides
+        engine.execute(dispute_tx).unwrap();
+        expected.dispute(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_resolve() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        let resolve_tx = Transaction::new(TransactionKind::Resolve, 1, 1, None);
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        let mut expected = Account::new(1);
+
+        // Deposit on both sides
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Dispute on both sides
+        engine.execute(dispute_tx).unwrap();
+        expected.dispute(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Resolve on both sides
+        engine.execute(resolve_tx).unwrap();
+        expected.resolve(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_resolve_without_dispute() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let resolve_tx = Transaction::new(TransactionKind::Resolve, 1, 1, None);
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        let mut expected = Account::new(1);
+
+        // Deposit on both sides
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Resolve on both sides
+        assert_eq!(
+            engine.execute(resolve_tx),
+            Err(TransactionError::NotDisputed)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_chargeback() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        let mut expected = Account::new(1);
+
+        // Deposit on both sides
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Dispute on both sides
+        engine.execute(dispute_tx).unwrap();
+        expected.dispute(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Charge back on both sides
+        engine.execute(chargeback_tx).unwrap();
+        expected.chargeback(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_chargeback_without_dispute() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        let mut expected = Account::new(1);
+
+        // Deposit on both sides
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Resolve on both sides
+        assert_eq!(
+            engine.execute(chargeback_tx),
+            Err(TransactionError::NotDisputed)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_chargeback_closes_dispute() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+
+        // Open the dispute
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
+        let state = engine.history.get(&1).unwrap().dispute_state;
+        assert_eq!(state, DisputeState::Open);
+
+        // Close it for good
+        engine.execute(chargeback_tx).unwrap();
+        let state = engine.history.get(&1).unwrap().dispute_state;
+        assert_eq!(state, DisputeState::ChargedBack);
+    }
+
+    #[test]
+    fn test_reject_handler() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(2)));
+        let resolve_tx = Transaction::new(TransactionKind::Resolve, 1, 1, None);
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 3, None);
+
+        // Create test engine and collector
+        let mut engine = PaymentsEngine::new();
+        let collector = CollectRejects::default();
+        engine.set_reject_handler(collector.clone());
+
+        // Only the deposit is valid
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(withdraw_tx.clone()).unwrap_err();
+        engine.execute(resolve_tx.clone()).unwrap_err();
+        engine.execute(dispute_tx.clone()).unwrap_err();
+        assert_eq!(
+            collector.rejects(),
+            vec![
+                (withdraw_tx, TransactionError::InsufficientFunds),
+                (resolve_tx, TransactionError::NotDisputed),
+                (dispute_tx, TransactionError::UnknownTransaction),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dispute_twice() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+
+        // Create test engine and account
+        let mut engine = PaymentsEngine::new();
+        engine.set_reject_handler(|_: &Transaction, error: &TransactionError| {
+            assert_eq!(error, &TransactionError::AlreadyDisputed);
+        });
+        let mut expected = Account::new(1);
+
+        // Deposit and dispute on both sides
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(dispute_tx.clone()).unwrap();
+        expected.deposit(dec!(2)).unwrap();
+        expected.dispute(dec!(2)).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+
+        // Disputing again doesn't hold the funds twice
+        assert_eq!(
+            engine.execute(dispute_tx),
+            Err(TransactionError::AlreadyDisputed)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_events() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(2)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
+
+        // Create test engine and listener forwarding events to a channel
+        let mut engine = PaymentsEngine::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        engine.add_listener(move |event: &EngineEvent| sender.send(event.clone()).unwrap());
+
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(withdraw_tx).unwrap_err();
+        engine.execute(dispute_tx).unwrap();
+        engine.execute(chargeback_tx).unwrap();
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                EngineEvent::AccountCreated { client: 1 },
+                EngineEvent::DepositApplied {
+                    client: 1,
+                    tx: 1,
+                    amount: dec!(1),
+                    account_version: 1,
+                },
+                EngineEvent::TransactionRejected {
+                    client: 1,
+                    tx: 2,
+                    kind: TransactionKind::Withdrawal,
+                    reason: TransactionError::InsufficientFunds,
+                },
+                EngineEvent::DisputeOpened {
+                    client: 1,
+                    tx: 1,
+                    amount: dec!(1),
+                    account_version: 2,
+                },
+                EngineEvent::ChargebackApplied {
+                    client: 1,
+                    tx: 1,
+                    amount: dec!(1),
+                    account_version: 3,
+                },
+                EngineEvent::AccountLocked { client: 1, tx: 1, account_version: 3 },
+            ]
+        );
+
+        // Only the first transfer to a client opens its account
+        let events = engine.subscribe();
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 2, 3, Some(dec!(2)));
+        let transfer_tx = |id| Transaction::new(TransactionKind::Transfer, 2, id, Some(dec!(1)));
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(transfer_tx(4).with_recipient(3)).unwrap();
+        engine.execute(transfer_tx(5).with_recipient(3)).unwrap();
+        let created: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                EngineEvent::AccountCreated { client } => Some(client),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(created, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_max_amount() {
+        // Create transactions, the last one moves exactly the maximum
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10.0001)));
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(11))).with_recipient(2);
+        let max_deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 3, Some(dec!(10)));
+
+        // Create test engine
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig {
+            max_amount: Some(dec!(10)),
+            ..EngineConfig::default()
+        });
+
+        assert_eq!(
+            engine.execute(deposit_tx),
+            Err(TransactionError::AmountTooLarge)
+        );
+        assert_eq!(
+            engine.execute(transfer_tx),
+            Err(TransactionError::AmountTooLarge)
+        );
+        engine.execute(max_deposit_tx).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(10));
+    }
+
+    #[test]
+    fn test_non_positive_amount() {
+        // Create test engine, the recipient of the transfer having funds
+        let mut engine = PaymentsEngine::new();
+        engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                2,
+                1,
+                Some(dec!(100)),
+            ))
+            .unwrap();
+
+        // A negative transfer would take the funds of the recipient
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(-100))).with_recipient(2);
+        assert_eq!(
+            engine.execute(transfer_tx),
+            Err(TransactionError::NonPositiveAmount)
+        );
+        assert!(engine.account(1).is_none());
+        assert_eq!(engine.account(2).unwrap().total, dec!(100));
+
+        // Zero and negative amounts are rejected whatever the kind
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 2, 3, Some(dec!(0))),
+            Transaction::new(TransactionKind::Deposit, 2, 4, Some(dec!(-1))),
+            Transaction::new(TransactionKind::Withdrawal, 2, 5, Some(dec!(-1))),
+            Transaction::new(TransactionKind::Withdrawal, 2, 6, Some(dec!(0.0000))),
+            Transaction::new(TransactionKind::Transfer, 2, 7, Some(dec!(0))).with_recipient(1),
+        ];
+        for tx in txs {
+            assert_eq!(engine.execute(tx), Err(TransactionError::NonPositiveAmount));
+        }
+        assert_eq!(engine.account(2).unwrap().total, dec!(100));
+        assert!(engine.transaction(3).is_none());
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut engine = PaymentsEngine::new();
+        let fees = [
+            (
+                TransactionKind::Withdrawal,
+                Fee { flat: dec!(0.5), percentage: dec!(1) },
+            ),
+            (
+                TransactionKind::Chargeback,
+                Fee { flat: dec!(2), ..Fee::default() },
+            ),
+        ];
+        engine.set_config(EngineConfig {
+            fees: fees.into_iter().collect(),
+            ..EngineConfig::default()
+        });
+        let events = engine.subscribe();
+
+        // The fee is charged along with the withdrawal
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(5))),
+        ];
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!((account.total, account.fees), (dec!(4.45), dec!(0.55)));
+
+        // Withdrawals whose fee can't be afforded are rejected as a whole
+        let version = account.version;
+        assert_eq!(
+            engine.execute(Transaction::new(
+                TransactionKind::Withdrawal,
+                1,
+                3,
+                Some(dec!(4.2))
+            )),
+            Err(TransactionError::InsufficientFunds)
+        );
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!((account.total, account.version), (dec!(4.45), version));
+        assert!(!engine.history.contains_key(&3));
+
+        // The fee of a chargeback can leave a deficit
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 2, 4, Some(dec!(1))),
+            Transaction::new(TransactionKind::Dispute, 2, 4, None),
+            Transaction::new(TransactionKind::Chargeback, 2, 4, None),
+        ];
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        let account = engine.accounts.get(&2).unwrap();
+        assert_eq!((account.total, account.fees), (dec!(-2), dec!(2)));
+        assert!(account.locked);
+
+        let charged: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                EngineEvent::FeeCharged { client, tx, amount, .. } => Some((client, tx, amount)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(charged, vec![(1, 2, dec!(0.55)), (2, 4, dec!(2))]);
+
+        // The fees are credited to their own ledger account
+        assert_eq!(engine.ledger().balance(LedgerAccount::Fees), dec!(2.55));
+        assert!(engine
+            .ledger()
+            .trial_balance(engine.accounts.values())
+            .is_balanced());
+        assert!(crate::reconciliation::reconcile(&engine).is_empty());
+    }
+
+    #[test]
+    fn test_credit_limit() {
+        let mut strict = Account::new(2);
+        strict.credit_limit = Some(dec!(0));
+        let mut engine = PaymentsEngine::with_accounts([strict]);
+        engine.set_config(EngineConfig { credit_limit: dec!(5), ..EngineConfig::default() });
+
+        // Withdrawals can overdraw the account down to the limit
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2))),
+            Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(6))),
+        ];
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-4));
+        assert_eq!(
+            engine.execute(Transaction::new(
+                TransactionKind::Withdrawal,
+                1,
+                3,
+                Some(dec!(2))
+            )),
+            Err(TransactionError::InsufficientFunds)
+        );
 
-                // If the disputed tx doesn't exist ignore this tx
-                if disputed_tx.is_none() {
-                    return;
-                }
+        // The limit of the account takes precedence, for transfers too
+        engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                2,
+                4,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+        assert_eq!(
+            engine.execute(
+                Transaction::new(TransactionKind::Transfer, 2, 5, Some(dec!(2))).with_recipient(1)
+            ),
+            Err(TransactionError::InsufficientFunds)
+        );
+        engine
+            .execute(
+                Transaction::new(TransactionKind::Transfer, 1, 6, Some(dec!(1))).with_recipient(2),
+            )
+            .unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-5));
 
-                let disputed_tx = disputed_tx.unwrap();
+        // The limit is part of the saved state
+        let path = std::env::temp_dir().join("payments-test-credit-limit.json");
+        engine.save(&path).unwrap();
+        let engine = PaymentsEngine::load(&path).unwrap();
+        assert_eq!(engine.accounts.get(&2).unwrap().credit_limit, Some(dec!(0)));
+    }
 
-                // Set/check disputation flag for the disputed tx
-                if tx.kind == TransactionKind::Dispute {
-                    disputed_tx.disputed = true;
-                } else {
-                    // If the disputed tx was never disputed ignore this tx
-                    if !disputed_tx.disputed {
-                        return;
-                    }
+    #[test]
+    fn test_out_of_order() {
+        let deposit = |tx, timestamp| {
+            Transaction::new(TransactionKind::Deposit, 1, tx, Some(dec!(1)))
+                .with_timestamp(timestamp)
+        };
 
-                    disputed_tx.disputed = false;
-                }
+        // Rejected records leave the account untouched
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig {
+            out_of_order: OutOfOrderPolicy::Reject,
+            ..EngineConfig::default()
+        });
+        engine.execute(deposit(1, 10)).unwrap();
+        engine.execute(deposit(2, 10)).unwrap();
+        assert_eq!(
+            engine.execute(deposit(3, 5)),
+            Err(TransactionError::OutOfOrder { timestamp: 5, last_activity: 10 })
+        );
+        engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                1,
+                4,
+                Some(dec!(1)),
+            ))
+            .unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!((account.total, account.last_activity), (dec!(3), Some(10)));
 
-                let account = self.accounts.get_mut(&tx.client_id).unwrap();
-                handle_claim(&tx.kind, account, disputed_tx.amount.unwrap());
-            }
-        }
+        // Warnings apply the record, keeping the latest activity
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig {
+            out_of_order: OutOfOrderPolicy::Warn,
+            ..EngineConfig::default()
+        });
+        let events = engine.subscribe();
+        engine.execute(deposit(1, 10)).unwrap();
+        engine.execute(deposit(2, 5)).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!((account.total, account.last_activity), (dec!(2), Some(10)));
+        let warnings: Vec<_> = events
+            .try_iter()
+            .filter(|e| matches!(e, EngineEvent::OutOfOrder { .. }))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![EngineEvent::OutOfOrder { client: 1, tx: 2, timestamp: 5, last_activity: 10 }]
+        );
     }
-}
 
-/// Perform the actual transfer, that is: a deposit or a withdrawal. Returns
-/// true if the transaction should be added to the history.
-fn handle_transfer(kind: &TransactionKind, account: &mut Account, amount: Decimal) -> bool {
-    if *kind == TransactionKind::Deposit {
-        account.deposit(amount);
-    } else {
-        account.withdraw(amount);
+    #[test]
+    fn test_simulate() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)));
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(1))).with_recipient(2);
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 3, Some(dec!(3)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+
+        // Create test engine and listener
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        engine.add_listener(move |event: &EngineEvent| sender.send(event.clone()).unwrap());
+        let accounts = engine.accounts.clone();
+
+        // Both sides of the transfer are returned, in ascending id order
+        let simulated = engine.simulate(&transfer_tx).unwrap();
+        assert_eq!(simulated.len(), 2);
+        assert_eq!(simulated[0].available, dec!(1));
+        assert_eq!(simulated[1].available, dec!(1));
+        assert_eq!(
+            engine.simulate(&withdraw_tx),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.simulate(&dispute_tx).unwrap()[0].held, dec!(2));
+
+        // Nothing was applied nor notified, the dispute can still be opened
+        assert_eq!(engine.accounts, accounts);
+        assert_eq!(receiver.try_iter().count(), 0);
+        engine.execute(dispute_tx).unwrap();
     }
 
-    *kind == TransactionKind::Deposit
-}
+    #[test]
+    fn test_backfill() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(2)));
 
-/// Perform the actual claim, that is: a dispute, a resolve or a chargeback.
-fn handle_claim(kind: &TransactionKind, client: &mut Account, amount: Decimal) {
-    match kind {
-        TransactionKind::Dispute => client.dispute(amount),
-        TransactionKind::Resolve => client.resolve(amount),
-        _ => client.chargeback(amount),
+        // Create test engine in backfill mode, along with its listeners
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig { backfill: true, ..EngineConfig::default() });
+        let (sender, receiver) = std::sync::mpsc::channel();
+        engine.add_listener(move |event: &EngineEvent| sender.send(event.clone()).unwrap());
+        let collector = CollectRejects::default();
+        engine.set_reject_handler(collector.clone());
+
+        // The state is rebuilt as usual, without notifying anyone
+        engine.execute(deposit_tx).unwrap();
+        assert_eq!(
+            engine.execute(withdraw_tx),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(1));
+        assert_eq!(receiver.try_iter().count(), 0);
+        assert!(collector.rejects().is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use rust_decimal_macros::dec;
+    #[test]
+    fn test_assert_balance() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let valid_tx = Transaction::new(TransactionKind::AssertBalance, 1, 0, Some(dec!(1)));
+        let invalid_tx = Transaction::new(TransactionKind::AssertBalance, 1, 0, Some(dec!(2)));
+        let missing_tx = Transaction::new(TransactionKind::AssertBalance, 2, 0, Some(dec!(0)));
 
-    use super::*;
+        // Create test engine and collector
+        let mut engine = PaymentsEngine::new();
+        let collector = CollectRejects::default();
+        engine.set_reject_handler(collector.clone());
+
+        // Only the second assertion fails
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(valid_tx).unwrap();
+        engine.execute(invalid_tx.clone()).unwrap_err();
+        engine.execute(missing_tx).unwrap();
+        assert_eq!(
+            collector.rejects(),
+            vec![(
+                invalid_tx,
+                TransactionError::AssertionFailed { expected: dec!(2), actual: dec!(1) }
+            )]
+        );
+
+        // Assertions never create accounts
+        assert!(!engine.accounts.contains_key(&2));
+    }
 
     #[test]
-    fn test_deposit() {
-        // Create transaction
-        let tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+    fn test_missing_amount() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, None);
+        let assert_tx = Transaction::new(TransactionKind::AssertBalance, 1, 0, None);
 
-        // Create test engine and account
+        // Create test engine
         let mut engine = PaymentsEngine::new();
-        let mut expected = Account::new(1);
 
-        // Deposit on both sides
-        engine.execute(tx);
-        expected.deposit(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        // Both transactions are rejected without touching accounts
+        assert_eq!(
+            engine.execute(deposit_tx),
+            Err(TransactionError::MissingAmount)
+        );
+        assert_eq!(
+            engine.execute(assert_tx),
+            Err(TransactionError::MissingAmount)
+        );
+        assert!(engine.accounts.is_empty());
     }
 
     #[test]
-    fn test_withdraw() {
+    fn test_client_mismatch() {
         // Create transactions
         let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
-        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 1, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 2, 1, None);
 
         // Create test engine and account
         let mut engine = PaymentsEngine::new();
         let mut expected = Account::new(1);
 
         // Deposit on both sides
-        engine.execute(deposit_tx);
-        expected.deposit(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(1)).unwrap();
 
-        // Withdraw on both sides
-        engine.execute(withdraw_tx);
-        expected.withdraw(dec!(1));
+        // The dispute doesn't come from the transaction owner
+        assert_eq!(
+            engine.execute(dispute_tx),
+            Err(TransactionError::ClientMismatch)
+        );
         assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
     }
 
     #[test]
-    fn test_dispute() {
+    fn test_locked_account() {
         // Create transactions
         let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
         let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
+        let new_deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1)));
 
-        // Create test engine and account
+        // Create test engine
         let mut engine = PaymentsEngine::new();
-        let mut expected = Account::new(1);
 
-        // Deposit on both sides
-        engine.execute(deposit_tx);
-        expected.deposit(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        // Lock the account, then try to deposit again
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
+        engine.execute(chargeback_tx).unwrap();
+        assert_eq!(
+            engine.execute(new_deposit_tx),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(0));
+    }
 
-        // Dispute on both sides
-        engine.execute(dispute_tx);
-        expected.dispute(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    #[test]
+    fn test_chargeback_end_to_end() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(3)));
+        let other_deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
+
+        // Create test engine
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(other_deposit_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
+        engine.execute(chargeback_tx.clone()).unwrap();
+
+        // Only the disputed funds are charged back, and the account is locked
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, dec!(1));
+        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.total, dec!(1));
+        assert!(account.locked);
+
+        // The transaction is no longer under dispute and can't be charged back
+        // twice
+        let state = engine.history.get(&1).unwrap().dispute_state;
+        assert_eq!(state, DisputeState::ChargedBack);
+        assert!(engine.execute(chargeback_tx).is_err());
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(1));
     }
 
     #[test]
-    fn test_resolve() {
+    fn test_dispute_reference() {
         // Create transactions
         let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
-        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
-        let resolve_tx = Transaction::new(TransactionKind::Resolve, 1, 1, None);
+        let other_deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1)));
+        let dispute_tx =
+            Transaction::new(TransactionKind::Dispute, 1, 1, None).with_reference("C1");
 
-        // Create test engine and account
+        // Create test engine
         let mut engine = PaymentsEngine::new();
-        let mut expected = Account::new(1);
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(other_deposit_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
 
-        // Deposit on both sides
-        engine.execute(deposit_tx);
-        expected.deposit(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        // Only the disputed tx is listed, along with its reference
+        let disputes = engine.disputes().collect::<Vec<_>>();
+        assert_eq!(disputes.len(), 1);
+        assert_eq!(disputes[0].id, 1);
+        assert_eq!(disputes[0].dispute_reference.as_deref(), Some("C1"));
+    }
 
-        // Dispute on both sides
-        engine.execute(dispute_tx);
-        expected.dispute(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    #[test]
+    fn test_redispute_policy() {
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let dispute_tx = |timestamp| {
+            Transaction::new(TransactionKind::Dispute, 1, 1, None).with_timestamp(timestamp)
+        };
+        let resolve_tx = |timestamp| {
+            Transaction::new(TransactionKind::Resolve, 1, 1, None).with_timestamp(timestamp)
+        };
 
-        // Resolve on both sides
-        engine.execute(resolve_tx);
-        expected.resolve(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        // Allowing no new dispute still allows the first one
+        let mut engine = PaymentsEngine::new();
+        let config: EngineConfig = toml::from_str(r#"redisputes = "allow-0""#).unwrap();
+        engine.set_config(config);
+        engine.execute(deposit_tx.clone()).unwrap();
+        engine.execute(dispute_tx(1)).unwrap();
+        engine.execute(resolve_tx(2)).unwrap();
+        assert_eq!(
+            engine.execute(dispute_tx(3)),
+            Err(TransactionError::DisputeClosed)
+        );
+
+        // Create test engine allowing a single new dispute
+        let mut engine = PaymentsEngine::new();
+        let config: EngineConfig = toml::from_str(r#"redisputes = "allow-1""#).unwrap();
+        engine.set_config(config);
+        engine.execute(deposit_tx).unwrap();
+
+        // Two dispute and resolve cycles are allowed, not a third one
+        for cycle in 0..2 {
+            engine.execute(dispute_tx(cycle * 10)).unwrap();
+            engine.execute(resolve_tx(cycle * 10 + 5)).unwrap();
+        }
+        assert_eq!(
+            engine.execute(dispute_tx(20)),
+            Err(TransactionError::DisputeClosed)
+        );
+
+        // Each dispute is recorded along with how and when it was closed
+        let resolved = |opened_at, closed_at| DisputeRecord {
+            state: DisputeState::Resolved,
+            opened_at: Some(opened_at),
+            closed_at: Some(closed_at),
+            reference: None,
+        };
+        assert_eq!(
+            engine.transaction(1).unwrap().disputes,
+            vec![resolved(0, 5), resolved(10, 15)]
+        );
+
+        // The history is kept in the saved state
+        let path = std::env::temp_dir().join("payments-test-redisputes.json");
+        engine.save(&path).unwrap();
+        let mut restored = PaymentsEngine::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        restored.set_config(engine.config().clone());
+        assert_eq!(
+            restored.transaction(1).unwrap().disputes,
+            engine.transaction(1).unwrap().disputes
+        );
+        assert_eq!(
+            restored.execute(Transaction::new(TransactionKind::Dispute, 1, 1, None)),
+            Err(TransactionError::DisputeClosed)
+        );
     }
 
     #[test]
-    fn test_resolve_without_dispute() {
+    fn test_locked_account_policy() {
         // Create transactions
-        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
-        let resolve_tx = Transaction::new(TransactionKind::Resolve, 1, 1, None);
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(1)));
+        let new_deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 3, Some(dec!(1)));
+
+        // Create test engine with a locked account
+        let mut account = Account::new(1);
+        account.deposit(dec!(2)).unwrap();
+        account.locked = true;
+        let mut engine = PaymentsEngine::with_accounts([account]);
+
+        // Only withdrawals are rejected
+        let config = EngineConfig {
+            locked_accounts: LockedAccountPolicy::RejectWithdrawals,
+            ..EngineConfig::default()
+        };
+        engine.set_config(config);
+        engine.execute(deposit_tx).unwrap();
+        assert_eq!(
+            engine.execute(withdraw_tx.clone()),
+            Err(TransactionError::AccountLocked)
+        );
+
+        // Nothing is rejected
+        let config = EngineConfig {
+            locked_accounts: LockedAccountPolicy::Allow,
+            ..EngineConfig::default()
+        };
+        engine.set_config(config);
+        engine.execute(withdraw_tx).unwrap();
+        engine.execute(new_deposit_tx).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(4));
+    }
+
+    #[test]
+    fn test_group() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)));
+        let principal_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(1)));
+        let fee_tx = Transaction::new(TransactionKind::Withdrawal, 1, 3, Some(dec!(0.5)));
+        let tax_tx = Transaction::new(TransactionKind::Withdrawal, 1, 4, Some(dec!(1)));
 
         // Create test engine and account
         let mut engine = PaymentsEngine::new();
         let mut expected = Account::new(1);
+        engine.execute(deposit_tx).unwrap();
+        expected.deposit(dec!(2)).unwrap();
 
-        // Deposit on both sides
-        engine.execute(deposit_tx);
-        expected.deposit(dec!(1));
+        // The tax can't be covered, no leg is applied
+        let legs = vec![principal_tx.clone(), fee_tx.clone(), tax_tx];
+        assert_eq!(
+            engine.execute_group(legs),
+            Err(TransactionError::InsufficientFunds)
+        );
         assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
 
-        // Resolve on both sides
-        engine.execute(resolve_tx);
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        // Without the tax every leg is applied
+        engine.execute_group(vec![principal_tx, fee_tx]).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(0.5));
+
+        // Claims can't be part of a group
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        assert_eq!(
+            engine.execute_group(vec![dispute_tx]),
+            Err(TransactionError::InvalidGroup)
+        );
     }
 
     #[test]
-    fn test_chargeback() {
+    fn test_group_rollback_new_accounts() {
+        // The second leg fails, the first one must not leave an account behind
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 2, 2, Some(dec!(1)));
+
+        let mut engine = PaymentsEngine::new();
+        assert!(engine.execute_group(vec![deposit_tx, withdraw_tx]).is_err());
+        assert!(engine.accounts.is_empty());
+        assert!(engine.history.is_empty());
+    }
+
+    #[test]
+    fn test_group_dispute() {
         // Create transactions
+        let principal_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let bonus_tx = Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(0.5)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 3, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 2, None);
+
+        // Create test engine
+        let mut engine = PaymentsEngine::new();
+        engine.execute_group(vec![principal_tx, bonus_tx]).unwrap();
+
+        // Disputing one leg disputes the whole group
+        engine.execute(dispute_tx.clone()).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(1.5));
+        assert_eq!(engine.disputes().count(), 2);
+
+        // Resolve the whole group, then spend part of it
+        let resolve_tx = Transaction::new(TransactionKind::Resolve, 1, 1, None);
+        engine.execute(resolve_tx).unwrap();
+        engine.execute(withdraw_tx).unwrap();
+
+        // The group can't be disputed as a whole anymore, nothing is held
+        assert_eq!(
+            engine.execute(dispute_tx),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0));
+        assert_eq!(engine.disputes().count(), 0);
+    }
+
+    #[test]
+    fn test_client_mismatch_other_account() {
+        // Create transactions, both clients have an account
         let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let other_deposit_tx = Transaction::new(TransactionKind::Deposit, 2, 2, Some(dec!(1)));
         let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
-        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
+
+        // Create test engine and collector
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(other_deposit_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
+
+        // Somebody else's claims are rejected, whatever the kind
+        for kind in [
+            TransactionKind::Dispute,
+            TransactionKind::Resolve,
+            TransactionKind::Chargeback,
+        ] {
+            let claim_tx = Transaction::new(kind, 2, 1, None);
+            assert_eq!(
+                engine.execute(claim_tx),
+                Err(TransactionError::ClientMismatch)
+            );
+        }
+
+        // Neither account was touched
+        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(1));
+        assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(1));
+        assert!(!engine.accounts.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_withdrawal_dispute() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(4)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 2, None);
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 2, None);
 
         // Create test engine and account
         let mut engine = PaymentsEngine::new();
         let mut expected = Account::new(1);
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(withdraw_tx).unwrap();
+        expected.deposit(dec!(10)).unwrap();
+        expected.withdraw(dec!(4), dec!(0)).unwrap();
 
-        // Deposit on both sides
-        engine.execute(deposit_tx);
-        expected.deposit(dec!(1));
+        // Dispute on both sides, the withdrawn funds are held
+        engine.execute(dispute_tx).unwrap();
+        expected.dispute_withdrawal(dec!(4)).unwrap();
         assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        assert_eq!(expected.held, dec!(4));
 
-        // Dispute on both sides
-        engine.execute(dispute_tx);
-        expected.dispute(dec!(1));
+        // Charge back on both sides, the withdrawal is reversed
+        engine.execute(chargeback_tx).unwrap();
+        expected.chargeback_withdrawal(dec!(4)).unwrap();
         assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        assert_eq!(expected.available, dec!(10));
+    }
 
-        // Charge back on both sides
-        engine.execute(chargeback_tx);
-        expected.chargeback(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+    #[test]
+    fn test_withdrawal_dispute_denied() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(4)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 2, None);
+
+        // Create test engine with the legacy behavior
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig {
+            withdrawal_disputes: WithdrawalDisputePolicy::Deny,
+            ..EngineConfig::default()
+        });
+
+        // Withdrawals can't be disputed
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(withdraw_tx).unwrap();
+        assert_eq!(
+            engine.execute(dispute_tx),
+            Err(TransactionError::UnknownTransaction)
+        );
     }
 
     #[test]
-    fn test_chargeback_without_dispute() {
+    fn test_negative_balances() {
         // Create transactions
-        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(8)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
         let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
 
-        // Create test engine and account
+        // By default spent funds can't be disputed
         let mut engine = PaymentsEngine::new();
-        let mut expected = Account::new(1);
+        engine.execute(deposit_tx.clone()).unwrap();
+        engine.execute(withdraw_tx.clone()).unwrap();
+        assert_eq!(
+            engine.execute(dispute_tx.clone()),
+            Err(TransactionError::InsufficientFunds)
+        );
 
-        // Deposit on both sides
-        engine.execute(deposit_tx);
-        expected.deposit(dec!(1));
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        // Under spec-compliant mode the chargeback leaves a deficit
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig {
+            allow_negative_balances: true,
+            ..EngineConfig::default()
+        });
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(withdraw_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
+        engine.execute(chargeback_tx).unwrap();
 
-        // Resolve on both sides
-        engine.execute(chargeback_tx);
-        assert_eq!(engine.accounts.get(&1).unwrap(), &expected);
+        let negative = engine.negative_balances().collect::<Vec<_>>();
+        assert_eq!(negative.len(), 1);
+        assert_eq!(negative[0].deficit(), dec!(8));
     }
 
     #[test]
-    fn test_chargeback_clears_flag() {
-        // Create transactions
+    fn test_overflow() {
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(Decimal::MAX));
+        let overflow_tx = Transaction::new(TransactionKind::Deposit, 1, 2, Some(Decimal::MAX));
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 2, 3, Some(Decimal::MAX)).with_recipient(1);
+
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx).unwrap();
+        engine
+            .execute(Transaction::new(
+                TransactionKind::Deposit,
+                2,
+                4,
+                Some(Decimal::MAX),
+            ))
+            .unwrap();
+
+        // Overflowing balances are rejected, leaving both accounts untouched
+        assert_eq!(engine.execute(overflow_tx), Err(TransactionError::Overflow));
+        assert_eq!(engine.execute(transfer_tx), Err(TransactionError::Overflow));
+        assert_eq!(engine.accounts.get(&1).unwrap().total, Decimal::MAX);
+        assert_eq!(engine.accounts.get(&2).unwrap().total, Decimal::MAX);
+    }
+
+    #[test]
+    fn test_untrusted_input() {
+        // Every combination of kinds, clients, ids, amounts and recipients
+        let kinds = TransactionKind::ALL;
+        let amounts = [
+            None,
+            Some(Decimal::ZERO),
+            Some(dec!(-1)),
+            Some(dec!(0.00001)),
+            Some(Decimal::MAX),
+            Some(Decimal::MIN),
+        ];
+        let mut txs = Vec::new();
+        for &kind in &kinds {
+            for client in 1..=2 {
+                for id in 1..=3 {
+                    for amount in amounts {
+                        for to_client in [None, Some(1), Some(2)] {
+                            let mut tx = Transaction::new(kind, client, id, amount);
+                            tx.to_client = to_client;
+                            tx.group = (id == 3).then_some(1);
+                            txs.push(tx);
+                        }
+                    }
+                }
+            }
+        }
+
+        // No malformed input can panic, under any policy and without any
+        // containment
+        for allow_negative_balances in [false, true] {
+            for withdrawal_disputes in [
+                WithdrawalDisputePolicy::Reverse,
+                WithdrawalDisputePolicy::Deny,
+            ] {
+                let mut engine = PaymentsEngine::new();
+                engine.set_config(EngineConfig {
+                    allow_negative_balances,
+                    withdrawal_disputes,
+                    ..EngineConfig::default()
+                });
+                for tx in &txs {
+                    let _ = engine.simulate(tx);
+                    let result = engine.execute(tx.clone());
+
+                    // Zero and negative amounts never move any funds
+                    if is_transfer(tx.kind) && tx.amount.is_some_and(|a| a <= Decimal::ZERO) {
+                        assert!(result.is_err(), "applied {tx:?}");
+                    }
+                }
+                let negative_tx = Transaction::new(TransactionKind::Deposit, 3, 4, Some(dec!(-1)));
+                assert_eq!(
+                    engine.execute(negative_tx),
+                    Err(TransactionError::NonPositiveAmount)
+                );
+                let _ = engine.execute_group(txs[..4].to_vec());
+                assert_eq!(engine.quarantined_clients().count(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_containment() {
+        // Create transactions, the listener panics on the second deposit
         let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1)));
+        let panic_tx = Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1)));
+        let other_deposit_tx = Transaction::new(TransactionKind::Deposit, 2, 3, Some(dec!(1)));
+        let new_deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 4, Some(dec!(1)));
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 2, 5, Some(dec!(1))).with_recipient(1);
+
+        // Create test engine under containment mode
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig { contain_panics: true, ..EngineConfig::default() });
+        engine.add_listener(|event: &EngineEvent| {
+            let panics = matches!(event, EngineEvent::DepositApplied { tx: 2, .. });
+            assert!(!panics, "injected panic");
+        });
+        engine.execute(deposit_tx).unwrap();
+
+        // The offending client is quarantined
+        assert_eq!(
+            engine.execute(panic_tx),
+            Err(TransactionError::ClientQuarantined)
+        );
+        assert_eq!(engine.quarantined_clients().collect::<Vec<_>>(), vec![1]);
+
+        // Everyone else is still processed, the quarantined client is not
+        engine.execute(other_deposit_tx).unwrap();
+        assert_eq!(
+            engine.execute(new_deposit_tx),
+            Err(TransactionError::ClientQuarantined)
+        );
+
+        // Nor can it receive transfers, which leave both accounts untouched
+        let accounts: Vec<_> = engine.accounts_sorted().cloned().collect();
+        assert_eq!(
+            engine.execute(transfer_tx),
+            Err(TransactionError::ClientQuarantined)
+        );
+        assert_eq!(
+            engine.accounts_sorted().cloned().collect::<Vec<_>>(),
+            accounts
+        );
+    }
+
+    #[test]
+    fn test_save_load() {
+        // Create transactions, the dispute follows the restart
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)));
+        let other_deposit_tx =
+            Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1))).with_memo("payroll");
+        let dispute_tx =
+            Transaction::new(TransactionKind::Dispute, 1, 1, None).with_reference("CASE-1");
+        let resolve_tx = Transaction::new(TransactionKind::Resolve, 1, 1, None);
+
+        // Save the state of the first run
+        let path = std::env::temp_dir().join("payments-test-save-load.json");
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(other_deposit_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
+        engine.record_run("first", 1);
+        engine.save(&path).unwrap();
+
+        // The accounts, their version, the dispute state and runs are restored
+        let mut restored = PaymentsEngine::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.accounts, engine.accounts);
+        assert_eq!(restored.run_digest("first"), Some(1));
+        assert_eq!(restored.run_digest("second"), None);
+        assert_eq!(restored.last_transaction(1), Some(1));
+        assert_eq!(
+            restored.transaction(2).unwrap().memo.as_deref(),
+            Some("payroll")
+        );
+        assert_eq!(
+            restored.disputes().collect::<Vec<_>>(),
+            engine.disputes().collect::<Vec<_>>()
+        );
+
+        // The transactions of the first run can still be claimed
+        restored.execute(resolve_tx).unwrap();
+        assert_eq!(restored.accounts.get(&1).unwrap().available, dec!(3));
+    }
+
+    #[test]
+    fn test_excess_precision() {
+        // Create transactions with more than four decimal places
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1.00006)));
+        let withdrawal_tx =
+            Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(0.00001)));
         let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
-        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 1, 1, None);
 
-        // Create test engine and account
+        // Amounts are rounded by default and recorded as such
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx.clone()).unwrap();
+        engine.execute(dispute_tx).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.held, dec!(1.0001));
+        assert_eq!(account.available, dec!(0));
+
+        // Or rejected
         let mut engine = PaymentsEngine::new();
+        let excess_precision = ExcessPrecisionPolicy::Reject;
+        engine.set_config(EngineConfig { excess_precision, ..EngineConfig::default() });
+        assert_eq!(
+            engine.execute(deposit_tx.clone()),
+            Err(TransactionError::ExcessPrecision)
+        );
+        assert_eq!(
+            engine.execute(withdrawal_tx),
+            Err(TransactionError::ExcessPrecision)
+        );
+        assert!(engine.accounts.is_empty());
 
-        // Toggle flag state
-        engine.execute(deposit_tx);
-        engine.execute(dispute_tx);
-        assert_eq!(engine.history.get(&1).unwrap().disputed, true);
+        // Or kept, e.g. for assets with more decimal places
+        let mut engine = PaymentsEngine::new();
+        let excess_precision = ExcessPrecisionPolicy::Keep;
+        engine.set_config(EngineConfig { excess_precision, ..EngineConfig::default() });
+        engine.execute(deposit_tx).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(1.00006));
+    }
+
+    #[test]
+    fn test_duplicate_transaction() {
+        // Create transactions, the withdrawal reuses the deposit id
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 1, Some(dec!(4)));
+        let other_deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 2, Some(dec!(1)));
+
+        // Duplicates are rejected by default, even within a group
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx.clone()).unwrap();
+        assert_eq!(
+            engine.execute(withdraw_tx.clone()),
+            Err(TransactionError::DuplicateTransaction)
+        );
+        assert_eq!(
+            engine.execute_group(vec![other_deposit_tx.clone(), other_deposit_tx.clone()]),
+            Err(TransactionError::DuplicateTransaction)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(10));
+
+        // Or skipped
+        let duplicate_transactions = DuplicatePolicy::Ignore;
+        engine.set_config(EngineConfig { duplicate_transactions, ..EngineConfig::default() });
+        engine.execute(withdraw_tx).unwrap();
+        engine
+            .execute_group(vec![deposit_tx, other_deposit_tx])
+            .unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(11));
+    }
+
+    #[test]
+    fn test_transfer() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10)));
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(4))).with_recipient(2);
+        let overdraft_tx =
+            Transaction::new(TransactionKind::Transfer, 1, 3, Some(dec!(7))).with_recipient(2);
+        let self_transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 1, 4, Some(dec!(1))).with_recipient(1);
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 1, 2, None);
+
+        // Funds move between the accounts
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(transfer_tx).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(6));
+        assert_eq!(engine.accounts.get(&2).unwrap().total, dec!(4));
+
+        // Invalid transfers alter neither account
+        assert_eq!(
+            engine.execute(overdraft_tx),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.execute(self_transfer_tx),
+            Err(TransactionError::InvalidRecipient)
+        );
+        assert_eq!(
+            engine.execute(dispute_tx),
+            Err(TransactionError::UnknownTransaction)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(6));
+        assert_eq!(engine.accounts.get(&2).unwrap().total, dec!(4));
+    }
+
+    #[test]
+    fn test_transfer_locked_recipient() {
+        // Create transactions, the recipient gets locked by a chargeback
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10)));
+        let other_deposit_tx = Transaction::new(TransactionKind::Deposit, 2, 2, Some(dec!(1)));
+        let dispute_tx = Transaction::new(TransactionKind::Dispute, 2, 2, None);
+        let chargeback_tx = Transaction::new(TransactionKind::Chargeback, 2, 2, None);
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 1, 3, Some(dec!(4))).with_recipient(2);
+
+        // Create test engine
+        let mut engine = PaymentsEngine::new();
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(other_deposit_tx).unwrap();
+        engine.execute(dispute_tx).unwrap();
+        engine.execute(chargeback_tx).unwrap();
+
+        // The sender keeps the funds
+        assert_eq!(
+            engine.execute(transfer_tx),
+            Err(TransactionError::AccountLocked)
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(10));
+    }
+
+    #[test]
+    fn test_soft_delete() {
+        // Create transactions
+        let deposit_tx = Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10)));
+        let withdraw_tx = Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(4)));
+        let transfer_tx =
+            Transaction::new(TransactionKind::Transfer, 2, 3, Some(dec!(1))).with_recipient(1);
+        let other_deposit_tx = Transaction::new(TransactionKind::Deposit, 2, 4, Some(dec!(1)));
+
+        // Create test engine, auditing the operations
+        let mut engine = PaymentsEngine::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        engine.add_listener(move |event: &EngineEvent| sender.send(event.clone()).unwrap());
+        engine.execute(deposit_tx).unwrap();
+        engine.execute(other_deposit_tx).unwrap();
+
+        // Deleted accounts reject transactions, even as recipients
+        assert!(engine.delete_account(1));
+        assert!(!engine.delete_account(1));
+        assert_eq!(
+            engine.execute(withdraw_tx.clone()),
+            Err(TransactionError::AccountDeleted)
+        );
+        assert_eq!(
+            engine.execute(transfer_tx),
+            Err(TransactionError::AccountDeleted)
+        );
+        assert_eq!(
+            engine.accounts_sorted().map(|a| a.id).collect::<Vec<_>>(),
+            vec![2]
+        );
+
+        // Restored accounts are back to normal
+        assert!(engine.restore_account(1));
+        engine.execute(withdraw_tx).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(6));
+
+        let events = receiver.try_iter().collect::<Vec<_>>();
+        assert!(events.contains(&EngineEvent::AccountDeleted { client: 1 }));
+        assert!(events.contains(&EngineEvent::AccountRestored { client: 1 }));
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut engine = PaymentsEngine::with_accounts([Account::new(1), Account::new(2)]);
 
-        // Toggle flag state back
-        engine.execute(chargeback_tx);
-        assert_eq!(engine.history.get(&1).unwrap().disputed, false);
+        // Tags are kept once per account, sorted
+        assert!(engine.tag_account(1, "vip"));
+        assert!(engine.tag_account(1, "under-investigation"));
+        assert!(!engine.tag_account(1, "vip"));
+        assert_eq!(
+            engine.account_tags(1).collect::<Vec<_>>(),
+            vec!["under-investigation", "vip"]
+        );
+
+        // Accounts can be filtered by tag
+        engine.tag_account(2, "vip");
+        assert_eq!(engine.tagged_accounts("vip").count(), 2);
+        assert!(engine.untag_account(2, "vip"));
+        assert!(!engine.untag_account(2, "vip"));
+        assert_eq!(
+            engine
+                .tagged_accounts("vip")
+                .map(|a| a.id)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(engine.account_tags(2).count(), 0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut engine = PaymentsEngine::new();
+        let mut other = PaymentsEngine::new();
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(5))),
+            Transaction::new(TransactionKind::Deposit, 2, 2, Some(dec!(1))),
+        ];
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 3, Some(dec!(2))),
+            Transaction::new(TransactionKind::Dispute, 1, 3, None),
+            Transaction::new(TransactionKind::Deposit, 3, 2, Some(dec!(4))),
+        ];
+        for tx in txs {
+            other.execute(tx).unwrap();
+        }
+        engine.tag_account(1, "vip");
+        other.tag_account(1, "under-investigation");
+
+        // Conflicting transaction ids are rejected by default
+        let mut copy = PaymentsEngine::new();
+        copy.execute(Transaction::new(
+            TransactionKind::Deposit,
+            3,
+            2,
+            Some(dec!(4)),
+        ))
+        .unwrap();
+        assert_eq!(engine.merge(copy), Err(MergeConflict { tx: 2 }));
+        assert_eq!(engine.accounts.len(), 2);
+
+        // Or resolved by keeping the entries of the merging engine
+        engine.set_config(EngineConfig {
+            duplicate_transactions: DuplicatePolicy::Ignore,
+            ..EngineConfig::default()
+        });
+        engine.merge(other).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (dec!(5), dec!(2), dec!(7))
+        );
+        assert_eq!(account.version, 3);
+        assert_eq!(engine.history.get(&2).unwrap().client_id, 2);
+        assert_eq!(engine.accounts.get(&3).unwrap().total, dec!(4));
+        assert_eq!(
+            engine.account_tags(1).collect::<Vec<_>>(),
+            vec!["under-investigation", "vip"]
+        );
+
+        // Histories are united, so disputes can be resolved after merging
+        engine
+            .execute(Transaction::new(TransactionKind::Resolve, 1, 3, None))
+            .unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(7));
+        assert!(crate::reconciliation::reconcile(&engine).is_empty());
+
+        // An account locked by either engine stays locked
+        let mut locked = PaymentsEngine::new();
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 2, 10, Some(dec!(3))),
+            Transaction::new(TransactionKind::Dispute, 2, 10, None),
+            Transaction::new(TransactionKind::Chargeback, 2, 10, None),
+        ];
+        for tx in txs {
+            locked.execute(tx).unwrap();
+        }
+        let version =
+            engine.accounts.get(&2).unwrap().version + locked.accounts.get(&2).unwrap().version;
+        engine.merge(locked).unwrap();
+        let account = engine.accounts.get(&2).unwrap();
+        assert!(account.locked);
+        assert_eq!((account.total, account.version), (dec!(1), version));
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut engine = PaymentsEngine::new();
+        engine.set_config(EngineConfig {
+            check_invariants: true,
+            contain_panics: true,
+            ..EngineConfig::default()
+        });
+        for tx in Generator::new(3, 4).take(500) {
+            let _ = engine.execute(tx);
+        }
+        assert_eq!(engine.quarantined_clients().count(), 0);
+
+        // A violation is caught by the next transaction, whatever its client
+        engine.accounts.get_mut(&1).unwrap().total += dec!(1);
+        let deposit = Transaction::new(TransactionKind::Deposit, 2, 1_000, Some(dec!(1)));
+        assert_eq!(
+            engine.execute(deposit),
+            Err(TransactionError::ClientQuarantined)
+        );
+        assert_eq!(engine.quarantined_clients().collect::<Vec<_>>(), vec![2]);
+    }
+
+    /// Generate a randomized workload over a few clients, mixing transfers,
+    /// composite transactions, claims of other clients, assertions and
+    /// reused ids into the soak test stream.
+    fn workload(seed: u64) -> Vec<Transaction> {
+        let mut txs = Vec::new();
+        for (i, tx) in Generator::new(seed, 12).take(1_500).enumerate() {
+            let other = tx.client_id % 12 + 1;
+            match i % 101 {
+                0..=6 => txs.push(
+                    Transaction::new(TransactionKind::Transfer, tx.client_id, tx.id, tx.amount)
+                        .with_recipient(other),
+                ),
+                7..=10 => txs.push(Transaction { client_id: other, ..tx }),
+                11..=13 => txs.push(Transaction { id: tx.id / 2 + 1, ..tx }),
+                14 | 15 => {
+                    let total = Some(Decimal::from(i % 3));
+                    txs.push(Transaction::new(
+                        TransactionKind::AssertBalance,
+                        other,
+                        0,
+                        total,
+                    ));
+                }
+                16 => {
+                    for (leg, recipient) in [(0, other), (1_000_000, tx.client_id)] {
+                        let amount = Some(dec!(1));
+                        let leg =
+                            Transaction::new(TransactionKind::Transfer, 1, tx.id + leg, amount);
+                        txs.push(Transaction {
+                            group: Some(tx.id),
+                            ..leg.with_recipient(recipient)
+                        });
+                    }
+                }
+                _ => txs.push(tx),
+            }
+        }
+        txs
+    }
+
+    #[test]
+    fn test_execute_parallel() {
+        type Outcome = (
+            Vec<Result<(), TransactionError>>,
+            Vec<Account>,
+            Vec<Transaction>,
+            Vec<(LedgerAccount, Decimal)>,
+            Vec<EngineEvent>,
+            Vec<(Transaction, TransactionError)>,
+            Vec<AuditEntry>,
+        );
+        let run = |config: &EngineConfig, txs: &[Transaction], threads: usize| -> Outcome {
+            let mut engine = PaymentsEngine::new();
+            engine.set_config(config.clone());
+            let events = engine.subscribe();
+            let entries = engine.audit();
+            let rejects = CollectRejects::default();
+            engine.set_reject_handler(rejects.clone());
+
+            let results = if threads == 0 {
+                txs.chunk_by(|a, b| a.group.is_some() && a.group == b.group)
+                    .flat_map(|legs| {
+                        let result = match legs {
+                            [tx] if tx.group.is_none() => engine.execute(tx.clone()),
+                            _ => engine.execute_group(legs.to_vec()),
+                        };
+                        vec![result; legs.len()]
+                    })
+                    .collect()
+            } else {
+                engine.execute_parallel(txs, threads)
+            };
+            let mut history: Vec<_> = engine.transactions().cloned().collect();
+            history.sort_unstable_by_key(|tx| tx.id);
+            (
+                results,
+                engine.accounts_sorted().cloned().collect(),
+                history,
+                engine.ledger().balances().collect(),
+                events.try_iter().collect(),
+                rejects.rejects(),
+                entries.try_iter().collect(),
+            )
+        };
+
+        let configs = [
+            EngineConfig::default(),
+            EngineConfig {
+                duplicate_transactions: DuplicatePolicy::Ignore,
+                fees: [(
+                    TransactionKind::Withdrawal,
+                    Fee { flat: dec!(0.1), percentage: dec!(0) },
+                )]
+                .into(),
+                ..EngineConfig::default()
+            },
+        ];
+        for (seed, config) in (0..4).zip(configs.iter().cycle()) {
+            let txs = workload(seed);
+            let sequential = run(config, &txs, 0);
+            assert!(sequential.0.iter().any(Result::is_err));
+            for threads in [1, 3, 4] {
+                assert!(
+                    run(config, &txs, threads) == sequential,
+                    "seed {seed}, {threads} threads"
+                );
+            }
+        }
     }
 }
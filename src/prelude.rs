@@ -0,0 +1,93 @@
+//! The stable facade of the crate, covering what most users need to execute
+//! transactions and query the accounts:
+//!
+//! ```
+//! use payments::prelude::*;
+//! use rust_decimal_macros::dec;
+//!
+//! let mut engine = Engine::new();
+//! engine.set_config(EngineConfig { max_amount: Some(dec!(100)), ..EngineConfig::default() });
+//! engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(5)))).unwrap();
+//!
+//! let account: &Account = engine.account(1).unwrap();
+//! assert_eq!(account.total, dec!(5));
+//! ```
+//!
+//! Items of the prelude only change along with the major version, while the
+//! other modules may evolve faster, e.g. to extend the reports.
+
+pub use rust_decimal::Decimal;
+
+pub use crate::{
+    account::Account,
+    admin::AdminError,
+    engine_config::EngineConfig,
+    ids::{ClientId, TxId},
+    payments_engine::{MergeConflict, PaymentsEngine, PaymentsEngine as Engine},
+    transaction::Transaction,
+    transaction_error::TransactionError,
+    transaction_kind::TransactionKind,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::{AdminAction, AdminBatch};
+
+    /// A snapshot of the facade: breaking changes to its signatures fail to
+    /// compile, and changes to its serialized forms fail the test.
+    #[test]
+    fn test_api() {
+        let _: fn() -> Engine = Engine::new;
+        let _: fn(&mut Engine, EngineConfig) = Engine::set_config;
+        let _: fn(&mut Engine, Transaction) -> Result<(), TransactionError> = Engine::execute;
+        let _: fn(&mut Engine, Vec<Transaction>) -> Result<(), TransactionError> =
+            Engine::execute_group;
+        let _: fn(&mut Engine, &[AdminAction]) -> Result<AdminBatch, AdminError> =
+            Engine::administer;
+        let _: fn(&mut Engine, Engine) -> Result<(), MergeConflict> = Engine::merge;
+        let _: fn(&Engine, u16) -> Option<&Account> = Engine::account;
+        let _: fn(&Engine, u32) -> Option<&Transaction> = Engine::transaction;
+        let _: fn(TransactionKind, u16, u32, Option<Decimal>) -> Transaction = Transaction::new;
+        let _: fn(u16) -> Account = Account::new;
+        let _: fn(u64) -> Account<u64> = Account::open;
+        let _: fn() -> Engine<u64, u128> = Engine::default;
+        let _: fn(&TransactionError) -> &'static str = TransactionError::code;
+
+        // The accounts output and the input columns
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(Account::new(1)).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "id,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n"
+        );
+        let tx: Transaction =
+            csv::Reader::from_reader("type,client,tx,amount\ndeposit,1,2,3\n".as_bytes())
+                .deserialize()
+                .next()
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            tx,
+            Transaction::new(TransactionKind::Deposit, 1, 2, Some(Decimal::from(3)))
+        );
+
+        // The transaction types and the error codes are never renamed
+        let kinds = TransactionKind::ALL.map(TransactionKind::as_str);
+        assert_eq!(
+            kinds,
+            [
+                "deposit",
+                "withdrawal",
+                "transfer",
+                "dispute",
+                "resolve",
+                "chargeback",
+                "assert_balance"
+            ]
+        );
+        assert_eq!(TransactionError::InsufficientFunds.code(), "PE001");
+        assert_eq!(TransactionError::Overflow.code(), "PE017");
+    }
+}
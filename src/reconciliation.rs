@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    ids::{ClientId, TxId},
+    ledger::LedgerAccount,
+    payments_engine::PaymentsEngine,
+};
+
+/// A check of the engine state failing, the expected value being the one
+/// derived from the movements of the funds. Checks spanning every account
+/// have no client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discrepancy<C = u16> {
+    pub client: Option<C>,
+    pub check: &'static str,
+    pub expected: Decimal,
+    pub actual: Decimal,
+}
+
+/// Verify the invariants of every account against the ledger, getting the
+/// discrepancies sorted by client:
+///
+/// - `total`: the total is the sum of the available and held funds;
+/// - `available` and `held`: the funds are the sum of their postings;
+/// - `movements`: the total funds of the clients are the accepted deposits,
+///   net of the withdrawals, the chargebacks and the fees, plus the opening
+///   balances and the administrative adjustments.
+///
+/// # Example
+/// ```
+/// use payments::account::Account;
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::reconciliation;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let mut engine = PaymentsEngine::new();
+/// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)))).unwrap();
+/// assert!(reconciliation::reconcile(&engine).is_empty());
+///
+/// // A seeded account whose total isn't the sum of its funds
+/// let mut account = Account::new(1);
+/// account.total = dec!(3);
+/// let discrepancies = reconciliation::reconcile(&PaymentsEngine::with_accounts([account]));
+/// assert_eq!(discrepancies[0].client, Some(1));
+/// assert_eq!(discrepancies[0].check, "total");
+/// ```
+#[must_use]
+pub fn reconcile<C: ClientId, T: TxId>(engine: &PaymentsEngine<C, T>) -> Vec<Discrepancy<C>> {
+    let ledger = engine.ledger();
+    let mut discrepancies = Vec::new();
+    let mut total = Decimal::ZERO;
+
+    let mut accounts: Vec<_> = engine.accounts().collect();
+    accounts.sort_unstable_by_key(|account| account.id);
+    for account in accounts {
+        let client = Some(account.id);
+        let derived = ledger.account(account.id);
+        let checks = [
+            (
+                "total",
+                account.available.saturating_add(account.held),
+                account.total,
+            ),
+            ("available", derived.available, account.available),
+            ("held", derived.held, account.held),
+        ];
+        for (check, expected, actual) in checks {
+            if expected != actual {
+                discrepancies.push(Discrepancy { client, check, expected, actual });
+            }
+        }
+        total = total.saturating_add(account.total);
+    }
+
+    // Funds enter and leave the clients through the internal accounts only
+    let movements = [
+        LedgerAccount::Settlement,
+        LedgerAccount::Chargebacks,
+        LedgerAccount::Fees,
+        LedgerAccount::Adjustments,
+        LedgerAccount::Suspense,
+    ]
+    .into_iter()
+    .fold(Decimal::ZERO, |sum, account| {
+        sum.saturating_sub(ledger.balance(account))
+    });
+    if movements != total {
+        discrepancies.push(Discrepancy {
+            client: None,
+            check: "movements",
+            expected: movements,
+            actual: total,
+        });
+    }
+
+    discrepancies
+}
+
+/// Check the invariants of every account, returning a description of the
+/// violations:
+///
+/// - the total is the sum of the available and held funds;
+/// - the held funds are the sum of the amounts currently under dispute;
+/// - no funds are negative, unless negative balances are allowed, except for
+///   the available funds of an account drawing on its credit limit.
+#[must_use]
+pub fn check_invariants<C: ClientId, T: TxId>(
+    engine: &PaymentsEngine<C, T>,
+    allow_negative: bool,
+) -> Vec<String> {
+    let mut disputed: HashMap<C, Decimal> = HashMap::new();
+    for tx in engine.disputes() {
+        let sum = disputed.entry(tx.client_id).or_default();
+        *sum = sum.saturating_add(tx.amount.unwrap_or_default());
+    }
+
+    let mut violations = Vec::new();
+    for account in engine.accounts() {
+        let id = account.id;
+        if account.available.checked_add(account.held) != Some(account.total) {
+            violations.push(format!(
+                "Client {id}: total {} is not available {} plus held {}",
+                account.total, account.available, account.held
+            ));
+        }
+
+        let disputed = disputed.get(&id).copied().unwrap_or_default();
+        if account.held != disputed {
+            violations.push(format!(
+                "Client {id}: held {} is not the disputed {disputed}",
+                account.held
+            ));
+        }
+
+        let credit_limit = account.credit_limit.unwrap_or(engine.config().credit_limit);
+        let overdrawn = account.available < -credit_limit;
+        if !allow_negative && (overdrawn || account.held < Decimal::ZERO) {
+            violations.push(format!(
+                "Client {id}: negative funds, available {} and held {}",
+                account.available, account.held
+            ));
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        account::Account, soak::Generator, transaction::Transaction,
+        transaction_kind::TransactionKind,
+    };
+
+    #[test]
+    fn test_reconcile() {
+        let mut opening = Account::new(3);
+        opening.deposit(dec!(4)).unwrap();
+        let mut engine = PaymentsEngine::with_accounts([opening]);
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(5))),
+            Transaction::new(TransactionKind::Withdrawal, 1, 2, Some(dec!(1))),
+            Transaction::new(TransactionKind::Transfer, 1, 3, Some(dec!(1))).with_recipient(2),
+            Transaction::new(TransactionKind::Deposit, 2, 4, Some(dec!(2))),
+            Transaction::new(TransactionKind::Dispute, 2, 4, None),
+            Transaction::new(TransactionKind::Chargeback, 2, 4, None),
+        ];
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        assert_eq!(reconcile(&engine), vec![]);
+
+        // Funds appearing outside of any transaction are caught
+        let account = engine.accounts.get_mut(&2).unwrap();
+        account.available += dec!(1);
+        account.total += dec!(1);
+        let checks: Vec<_> = reconcile(&engine)
+            .into_iter()
+            .map(|d| (d.client, d.check))
+            .collect();
+        assert_eq!(checks, vec![(Some(2), "available"), (None, "movements")]);
+    }
+
+    #[test]
+    fn test_invariants() {
+        let mut engine = PaymentsEngine::new();
+        for tx in Generator::new(7, 20).take(20_000) {
+            let _ = engine.execute(tx);
+        }
+        assert!(check_invariants(&engine, false).is_empty());
+
+        // Tamper with an account
+        let account = engine.accounts.values_mut().next().unwrap();
+        account.held += Decimal::ONE;
+        assert_eq!(check_invariants(&engine, false).len(), 2);
+    }
+}
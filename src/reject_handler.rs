@@ -0,0 +1,143 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::{
+    ids::{ClientId, TxId},
+    transaction::Transaction,
+    transaction_error::TransactionError,
+};
+
+/// A strategy deciding what happens to the transactions rejected by the
+/// `PaymentsEngine`.
+///
+/// Any `FnMut(&Transaction, &TransactionError)` closure is a handler too,
+/// which makes it easy to forward rejections to a channel or any other sink.
+pub trait RejectHandler<C = u16, T = u32>: Send {
+    fn reject(&mut self, tx: &Transaction<C, T>, error: &TransactionError);
+}
+
+impl<C, T, F: FnMut(&Transaction<C, T>, &TransactionError) + Send> RejectHandler<C, T> for F {
+    fn reject(&mut self, tx: &Transaction<C, T>, error: &TransactionError) {
+        self(tx, error);
+    }
+}
+
+/// Silently drop rejected transactions, this is the default handler.
+#[derive(Debug, Default)]
+pub struct IgnoreRejects;
+
+impl<C, T> RejectHandler<C, T> for IgnoreRejects {
+    fn reject(&mut self, _tx: &Transaction<C, T>, _error: &TransactionError) {}
+}
+
+/// Log rejected transactions to the standard error.
+#[derive(Debug, Default)]
+pub struct LogRejects;
+
+impl<C: ClientId, T: TxId> RejectHandler<C, T> for LogRejects {
+    fn reject(&mut self, tx: &Transaction<C, T>, error: &TransactionError) {
+        eprintln!(
+            "Rejected tx {} for client {}: {}",
+            tx.id, tx.client_id, error
+        );
+    }
+}
+
+/// Collect rejected transactions along with their error. The handler can be
+/// cloned before handing it to the engine, all clones share the same storage.
+///
+/// # Example
+/// ```
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::reject_handler::CollectRejects;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_error::TransactionError;
+/// use payments::transaction_kind::TransactionKind;
+///
+/// let collector = CollectRejects::default();
+/// let mut engine = PaymentsEngine::new();
+/// engine.set_reject_handler(collector.clone());
+///
+/// let result = engine.execute(Transaction::new(TransactionKind::Dispute, 1, 1, None));
+/// assert!(result.is_err());
+/// assert_eq!(collector.rejects()[0].1, TransactionError::UnknownTransaction);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CollectRejects {
+    rejects: Arc<Mutex<Vec<(Transaction, TransactionError)>>>,
+}
+
+impl CollectRejects {
+    /// Get a copy of the rejected transactions collected so far, including
+    /// those collected before a thread panicked while holding the storage.
+    #[must_use]
+    pub fn rejects(&self) -> Vec<(Transaction, TransactionError)> {
+        self.rejects
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl RejectHandler for CollectRejects {
+    fn reject(&mut self, tx: &Transaction, error: &TransactionError) {
+        self.rejects
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((tx.clone(), error.clone()));
+    }
+}
+
+/// Panic on the first rejected transaction, meant to be used in tests.
+#[derive(Debug, Default)]
+pub struct PanicOnReject;
+
+impl<C: ClientId, T: TxId> RejectHandler<C, T> for PanicOnReject {
+    // Panicking is the whole point of this handler, which the engine never
+    // installs by itself
+    #[allow(clippy::panic)]
+    fn reject(&mut self, tx: &Transaction<C, T>, error: &TransactionError) {
+        panic!(
+            "Rejected tx {} for client {}: {}",
+            tx.id, tx.client_id, error
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{panic, thread};
+
+    use super::*;
+    use crate::transaction_kind::TransactionKind;
+
+    #[test]
+    fn test_handlers() {
+        let tx = Transaction::new(TransactionKind::Dispute, 1, 1, None);
+        let error = TransactionError::UnknownTransaction;
+
+        // Clones share the same storage
+        let collector = CollectRejects::default();
+        let mut handler = collector.clone();
+        handler.reject(&tx, &error);
+        assert_eq!(collector.rejects(), vec![(tx.clone(), error.clone())]);
+
+        // Which outlives a thread panicking while holding it
+        let rejects = Arc::clone(&collector.rejects);
+        let _ = thread::spawn(move || {
+            let _guard = rejects.lock();
+            panic!("poisoning the storage");
+        })
+        .join();
+        handler.reject(&tx, &error);
+        assert_eq!(collector.rejects().len(), 2);
+
+        // Closures are handlers too
+        let mut count = 0;
+        let mut closure = |_: &Transaction, _: &TransactionError| count += 1;
+        closure.reject(&tx, &error);
+        assert_eq!(count, 1);
+
+        let panicked = panic::catch_unwind(|| PanicOnReject.reject(&tx, &error));
+        assert!(panicked.is_err());
+    }
+}
@@ -0,0 +1,298 @@
+//! A REST API over an `AsyncPaymentsEngine`, enabled by the `server` feature.
+//!
+//! - `POST /transactions` executes the JSON transaction in the body, e.g.
+//!   `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`, replying
+//!   with `204 No Content` or `422 Unprocessable Entity` along with the code
+//!   and reason of the rejection;
+//! - `POST /transactions:simulate` evaluates the transaction without applying
+//!   it, replying with the resulting accounts or with the rejection;
+//! - `GET /accounts` lists the accounts in ascending id order;
+//! - `GET /accounts/{id}` gets a single account, or `404 Not Found`;
+//! - `GET /accounts/{id}/tags` lists the tags of an account, while
+//!   `PUT /accounts/{id}/tags/{tag}` and `DELETE /accounts/{id}/tags/{tag}`
+//!   attach and remove a tag;
+//! - `POST /admin` applies the JSON array of administrative actions in the
+//!   body atomically, e.g. `[{"action": "lock", "client": 1}]`, replying with
+//!   the audit record of the batch or with the rejection of the failed action.
+//!
+//! Amounts are strings, to avoid any loss of precision. Transactions and
+//! administrative batches are queued as interactive requests, unless the
+//! `X-Priority: backfill` header states otherwise, see `Priority`.
+
+use std::io;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::Serialize;
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::{
+    account::Account,
+    admin::{AdminAction, AdminError},
+    async_engine::{AsyncPaymentsEngine, Priority},
+    transaction::Transaction,
+    transaction_error::TransactionError,
+};
+
+/// The body of a rejected transaction response.
+#[derive(Serialize)]
+struct Rejection {
+    code: &'static str,
+    reason: String,
+}
+
+/// Build the routes of the API, to be served or nested in another router.
+pub fn router(engine: AsyncPaymentsEngine) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/transactions:simulate", post(simulate_transaction))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{id}", get(get_account))
+        .route("/accounts/{id}/tags", get(get_tags))
+        .route("/accounts/{id}/tags/{tag}", put(put_tag).delete(delete_tag))
+        .route("/admin", post(administer))
+        .with_state(engine)
+}
+
+/// Serve the API on the address until the task is cancelled.
+///
+/// # Errors
+///
+/// Returns an error if the address can't be bound.
+pub async fn serve(addr: impl ToSocketAddrs, engine: AsyncPaymentsEngine) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, router(engine)).await
+}
+
+/// Get a handle queuing the requests with the priority stated by the
+/// `X-Priority` header, interactive by default.
+fn prioritized(
+    engine: &AsyncPaymentsEngine,
+    headers: &HeaderMap,
+) -> Result<AsyncPaymentsEngine, (StatusCode, String)> {
+    let Some(priority) = headers.get("x-priority") else {
+        return Ok(engine.clone());
+    };
+    let priority: Priority = priority
+        .to_str()
+        .map_err(|error| error.to_string())
+        .and_then(str::parse)
+        .map_err(|error| (StatusCode::BAD_REQUEST, error))?;
+    Ok(engine.with_priority(priority))
+}
+
+async fn submit_transaction(
+    State(engine): State<AsyncPaymentsEngine>,
+    headers: HeaderMap,
+    Json(tx): Json<Transaction>,
+) -> Response {
+    let engine = match prioritized(&engine, &headers) {
+        Ok(engine) => engine,
+        Err(rejection) => return rejection.into_response(),
+    };
+    match engine.execute(tx).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => rejection(&error),
+    }
+}
+
+async fn simulate_transaction(
+    State(engine): State<AsyncPaymentsEngine>,
+    headers: HeaderMap,
+    Json(tx): Json<Transaction>,
+) -> Response {
+    let engine = match prioritized(&engine, &headers) {
+        Ok(engine) => engine,
+        Err(rejection) => return rejection.into_response(),
+    };
+    match engine.simulate(tx).await {
+        Ok(accounts) => Json(accounts).into_response(),
+        Err(error) => rejection(&error),
+    }
+}
+
+/// Reply to a rejected transaction with its code and reason, or to a change
+/// which couldn't be saved with a server error.
+fn rejection(error: &TransactionError) -> Response {
+    let rejection = Rejection { code: error.code(), reason: error.to_string() };
+    (status(error), Json(rejection)).into_response()
+}
+
+/// Get the status answering the error.
+const fn status(error: &TransactionError) -> StatusCode {
+    match error {
+        TransactionError::StateNotSaved { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::UNPROCESSABLE_ENTITY,
+    }
+}
+
+async fn list_accounts(State(engine): State<AsyncPaymentsEngine>) -> Json<Vec<Account>> {
+    Json(engine.accounts().await)
+}
+
+async fn get_account(
+    State(engine): State<AsyncPaymentsEngine>,
+    Path(id): Path<u16>,
+) -> Result<Json<Account>, StatusCode> {
+    engine
+        .account(id)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_tags(
+    State(engine): State<AsyncPaymentsEngine>,
+    Path(id): Path<u16>,
+) -> Json<Vec<String>> {
+    Json(engine.account_tags(id).await)
+}
+
+async fn put_tag(
+    State(engine): State<AsyncPaymentsEngine>,
+    Path((id, tag)): Path<(u16, String)>,
+) -> Response {
+    match engine.tag_account(id, tag).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => rejection(&error),
+    }
+}
+
+async fn delete_tag(
+    State(engine): State<AsyncPaymentsEngine>,
+    Path((id, tag)): Path<(u16, String)>,
+) -> Response {
+    match engine.untag_account(id, tag).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(error) => rejection(&error),
+    }
+}
+
+async fn administer(
+    State(engine): State<AsyncPaymentsEngine>,
+    headers: HeaderMap,
+    Json(actions): Json<Vec<AdminAction>>,
+) -> Response {
+    let engine = match prioritized(&engine, &headers) {
+        Ok(engine) => engine,
+        Err(rejection) => return rejection.into_response(),
+    };
+    match engine.administer(actions).await {
+        Ok(batch) => Json(batch).into_response(),
+        Err(error) => admin_rejection(&error),
+    }
+}
+
+/// Reply to a rejected batch with the code of the failed action, the reason
+/// stating which one it is.
+fn admin_rejection(error: &AdminError) -> Response {
+    let rejection = Rejection {
+        code: error.error.code(),
+        reason: error.to_string(),
+    };
+    (status(&error.error), Json(rejection)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::payments_engine::PaymentsEngine;
+
+    /// Send a request to the server and get the raw response back.
+    async fn request(addr: std::net::SocketAddr, method: &str, path: &str, body: &str) -> String {
+        request_with(addr, method, path, "", body).await
+    }
+
+    /// Send a request along with extra header lines, each ending with CRLF.
+    async fn request_with(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/json\r\n{headers}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_api() {
+        let engine = AsyncPaymentsEngine::spawn(PaymentsEngine::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(engine)).await });
+
+        // Submit a deposit, then an invalid withdrawal
+        let deposit = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}"#;
+        let response = request(addr, "POST", "/transactions", deposit).await;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        let withdrawal = r#"{"type":"withdrawal","client":1,"tx":2,"amount":"2"}"#;
+        let response = request(addr, "POST", "/transactions", withdrawal).await;
+        assert!(response.starts_with("HTTP/1.1 422"));
+        assert!(response.ends_with(r#"{"code":"PE001","reason":"insufficient funds"}"#));
+
+        // Submit a backfilled deposit, then one of an unknown priority
+        let deposit = r#"{"type":"deposit","client":3,"tx":3,"amount":"1"}"#;
+        let backfill = "X-Priority: backfill\r\n";
+        let response = request_with(addr, "POST", "/transactions", backfill, deposit).await;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        let urgent = "X-Priority: urgent\r\n";
+        let response = request_with(addr, "POST", "/transactions", urgent, deposit).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        // Simulate a withdrawal, then an invalid one
+        let withdrawal = r#"{"type":"withdrawal","client":1,"tx":2,"amount":"1"}"#;
+        let response = request(addr, "POST", "/transactions:simulate", withdrawal).await;
+        assert!(response.contains(r#"[{"id":1,"available":"0.5000","#));
+        let withdrawal = r#"{"type":"withdrawal","client":1,"tx":2,"amount":"2"}"#;
+        let response = request(addr, "POST", "/transactions:simulate", withdrawal).await;
+        assert!(response.starts_with("HTTP/1.1 422"));
+
+        // Query the accounts
+        let response = request(addr, "GET", "/accounts/1", "").await;
+        assert!(response.ends_with(
+            r#"{"id":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        ));
+        let response = request(addr, "GET", "/accounts", "").await;
+        assert!(response.contains(r#"[{"id":1,"#));
+        let response = request(addr, "GET", "/accounts/2", "").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        // Tag the account, then untag it
+        let response = request(addr, "PUT", "/accounts/1/tags/vip", "").await;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        let response = request(addr, "GET", "/accounts/1/tags", "").await;
+        assert!(response.ends_with(r#"["vip"]"#));
+        let response = request(addr, "DELETE", "/accounts/1/tags/vip", "").await;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        let response = request(addr, "DELETE", "/accounts/1/tags/vip", "").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        // Apply an administrative batch, then an invalid one
+        let actions =
+            r#"[{"action":"lock","client":1},{"action":"adjust","client":2,"amount":"1"}]"#;
+        let response = request(addr, "POST", "/admin", actions).await;
+        assert!(response.contains(r#"{"clients":[1,2],"locks":1,"#));
+        let actions = r#"[{"action":"adjust","client":2,"amount":"-2"}]"#;
+        let response = request(addr, "POST", "/admin", actions).await;
+        assert!(response
+            .ends_with(r#"{"code":"PE001","reason":"action 0 on client 2: insufficient funds"}"#));
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    panic,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    ids::ClientId, payments_engine::PaymentsEngine, transaction::Transaction,
+    transaction_error::TransactionError, transaction_kind::TransactionKind,
+};
+
+/// Get the shard of a client among the given number of shards, the same way
+/// `ShardedEngine` routes transactions, e.g. to split files beforehand.
+///
+/// # Panics
+///
+/// Panics if there are no shards.
+///
+/// # Example
+/// ```
+/// use payments::sharded_engine::shard_of;
+///
+/// assert_eq!(shard_of(17, 16), 1);
+/// ```
+#[must_use]
+pub fn shard_of(client: u16, shards: usize) -> usize {
+    client.shard(shards)
+}
+
+/// A payments engine spreading the clients over several worker threads, each
+/// owning its own `PaymentsEngine`. Transactions are routed by client id, so
+/// those of a given client are executed in the order they are received while
+/// different clients are processed in parallel.
+///
+/// Transactions can only refer to transactions of the same client, disputes
+/// of another client's transaction are rejected as unknown, and composite
+/// transactions and transfers are refused, since each shard would credit its
+/// own copy of the accounts of the other clients.
+///
+/// # Example
+/// ```
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::sharded_engine::ShardedEngine;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let engine = ShardedEngine::spawn(4, PaymentsEngine::new);
+/// for client in 1..=8 {
+///     let tx = Transaction::new(TransactionKind::Deposit, client, client.into(), Some(dec!(1)));
+///     engine.execute(tx).unwrap();
+/// }
+///
+/// let engine = engine.join();
+/// assert_eq!(engine.accounts().len(), 8);
+/// ```
+pub struct ShardedEngine {
+    senders: Vec<mpsc::Sender<Transaction>>,
+    workers: Vec<JoinHandle<PaymentsEngine>>,
+}
+
+impl ShardedEngine {
+    /// Start the given number of worker threads, each with an engine built by
+    /// `new_engine`. Rejections go to the reject handler of each engine.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no shard is requested.
+    #[must_use]
+    pub fn spawn(shards: usize, new_engine: impl Fn() -> PaymentsEngine) -> Self {
+        assert!(shards > 0, "at least one shard is required");
+        let (senders, workers) = (0..shards)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel::<Transaction>();
+                let mut engine = new_engine();
+                let worker = thread::spawn(move || {
+                    for tx in receiver {
+                        let _ = engine.execute(tx);
+                    }
+                    engine
+                });
+                (sender, worker)
+            })
+            .unzip();
+
+        Self { senders, workers }
+    }
+
+    /// Queue the transaction on the shard of its client. The outcome of the
+    /// queued transaction goes to the reject handler of its engine.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransactionError::CrossShard` for transfers and composite
+    /// transactions, which are not queued.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the worker thread of the shard has panicked.
+    pub fn execute(&self, tx: Transaction) -> Result<(), TransactionError> {
+        if tx.kind == TransactionKind::Transfer || tx.group.is_some() {
+            return Err(TransactionError::CrossShard);
+        }
+
+        let shard = shard_of(tx.client_id, self.senders.len());
+        let sent = self.senders[shard].send(tx);
+        assert!(sent.is_ok(), "shard worker stopped");
+        Ok(())
+    }
+
+    /// Wait for the queued transactions to be executed, and merge the shards
+    /// into the engine of the first one, keeping its configuration, listeners
+    /// and reject handler.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the panic of a worker thread, if one has panicked.
+    #[must_use]
+    pub fn join(self) -> PaymentsEngine {
+        // Closing the channels lets the workers finish
+        drop(self.senders);
+
+        let mut engines = self.workers.into_iter().map(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|payload| panic::resume_unwind(payload))
+        });
+        // There is at least one shard
+        let mut merged = engines.next().unwrap_or_default();
+        for engine in engines {
+            merged.merge_disjoint(engine);
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{engine_config::EngineConfig, transaction_kind::TransactionKind};
+
+    #[test]
+    fn test_per_client_ordering() {
+        let engine = ShardedEngine::spawn(3, PaymentsEngine::new);
+
+        // Each withdrawal and dispute only succeeds if the deposits went first
+        for client in 0..30 {
+            let id = u32::from(client) * 3;
+            let deposits = [id, id + 1]
+                .map(|id| Transaction::new(TransactionKind::Deposit, client, id, Some(dec!(1))));
+            let withdrawal =
+                Transaction::new(TransactionKind::Withdrawal, client, id + 2, Some(dec!(1)));
+            let dispute = Transaction::new(TransactionKind::Dispute, client, id + 1, None);
+            for tx in deposits.into_iter().chain([withdrawal, dispute]) {
+                engine.execute(tx).unwrap();
+            }
+        }
+
+        let engine = engine.join();
+        assert_eq!(engine.accounts().len(), 30);
+        for account in engine.accounts() {
+            assert_eq!(account.available, dec!(0));
+            assert_eq!(account.held, dec!(1));
+        }
+
+        // The history of every shard is kept
+        assert_eq!(engine.disputes().count(), 30);
+    }
+
+    #[test]
+    fn test_cross_shard() {
+        let engine = ShardedEngine::spawn(2, PaymentsEngine::new);
+        for client in [1, 2] {
+            let tx = Transaction::new(
+                TransactionKind::Deposit,
+                client,
+                client.into(),
+                Some(dec!(5)),
+            );
+            engine.execute(tx).unwrap();
+        }
+
+        // Transfers and composite transactions are refused, not queued
+        let transfer =
+            Transaction::new(TransactionKind::Transfer, 1, 3, Some(dec!(1))).with_recipient(2);
+        assert_eq!(engine.execute(transfer), Err(TransactionError::CrossShard));
+        let mut leg = Transaction::new(TransactionKind::Withdrawal, 1, 4, Some(dec!(1)));
+        leg.group = Some(4);
+        assert_eq!(engine.execute(leg), Err(TransactionError::CrossShard));
+
+        // The balances of both clients are intact after the merge
+        let engine = engine.join();
+        for client in [1, 2] {
+            assert_eq!(engine.account(client).unwrap().total, dec!(5));
+        }
+        assert!(engine.transaction(3).is_none());
+    }
+
+    #[test]
+    fn test_join_keeps_config() {
+        let config = EngineConfig {
+            max_amount: Some(dec!(10)),
+            ..EngineConfig::default()
+        };
+        let engine = ShardedEngine::spawn(2, || {
+            let mut engine = PaymentsEngine::new();
+            engine.set_config(config.clone());
+            engine
+        });
+        for client in [1, 2] {
+            let tx = Transaction::new(
+                TransactionKind::Deposit,
+                client,
+                client.into(),
+                Some(dec!(5)),
+            );
+            engine.execute(tx).unwrap();
+        }
+
+        let mut engine = engine.join();
+        assert_eq!(engine.config(), &config);
+        let deposit = Transaction::new(TransactionKind::Deposit, 1, 3, Some(dec!(20)));
+        assert_eq!(
+            engine.execute(deposit),
+            Err(TransactionError::AmountTooLarge)
+        );
+        assert_eq!(engine.accounts().len(), 2);
+    }
+}
@@ -0,0 +1,200 @@
+//! Serialized form of the engine state, see `PaymentsEngine::save`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    dispute_state::{DisputeRecord, DisputeState},
+    ids::{ClientId, TxId},
+    ledger::LedgerAccount,
+    transaction::Transaction,
+    transaction_kind::TransactionKind,
+};
+
+/// Version of the snapshot format, bumped on incompatible changes.
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// The state of an engine: its accounts, history, quarantined clients,
+/// soft-deleted accounts, account tags, last transactions of the accounts,
+/// recorded runs and ledger balances.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "C: ClientId, T: TxId"))]
+pub struct Snapshot<C, T> {
+    pub version: u16,
+    pub accounts: Vec<AccountState<C>>,
+    pub history: Vec<HistoryEntry<C, T>>,
+    pub quarantined: Vec<C>,
+    #[serde(default)]
+    pub deleted: Vec<C>,
+    #[serde(default)]
+    pub tags: BTreeMap<C, BTreeSet<String>>,
+    #[serde(default)]
+    pub last_txs: BTreeMap<C, T>,
+    #[serde(default)]
+    pub runs: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub ledger: Vec<(LedgerAccount<C>, Decimal)>,
+}
+
+/// An account along with its version, the total is derived on load.
+#[derive(Serialize, Deserialize)]
+pub struct AccountState<C> {
+    id: C,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    version: u64,
+    #[serde(default)]
+    last_activity: Option<i64>,
+    #[serde(default)]
+    fees: Decimal,
+    #[serde(default)]
+    credit_limit: Option<Decimal>,
+}
+
+impl<C: ClientId> From<&Account<C>> for AccountState<C> {
+    fn from(account: &Account<C>) -> Self {
+        Self {
+            id: account.id,
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            version: account.version,
+            last_activity: account.last_activity,
+            fees: account.fees,
+            credit_limit: account.credit_limit,
+        }
+    }
+}
+
+impl<C: ClientId> From<AccountState<C>> for Account<C> {
+    fn from(state: AccountState<C>) -> Self {
+        Self {
+            id: state.id,
+            available: state.available,
+            held: state.held,
+            total: state.available + state.held,
+            locked: state.locked,
+            version: state.version,
+            last_activity: state.last_activity,
+            fees: state.fees,
+            credit_limit: state.credit_limit,
+        }
+    }
+}
+
+/// A transaction of the history along with its dispute state.
+#[derive(Serialize, Deserialize)]
+pub struct HistoryEntry<C, T> {
+    kind: TransactionKind,
+    client: C,
+    tx: T,
+    amount: Option<Decimal>,
+    #[serde(default)]
+    to_client: Option<C>,
+    group: Option<T>,
+    reference: Option<String>,
+    #[serde(default)]
+    timestamp: Option<i64>,
+    #[serde(default)]
+    memo: Option<String>,
+    dispute_state: DisputeState,
+    dispute_reference: Option<String>,
+    #[serde(default)]
+    disputes: Vec<DisputeRecord>,
+}
+
+impl<C: ClientId, T: TxId> From<&Transaction<C, T>> for HistoryEntry<C, T> {
+    fn from(tx: &Transaction<C, T>) -> Self {
+        Self {
+            kind: tx.kind,
+            client: tx.client_id,
+            tx: tx.id,
+            amount: tx.amount,
+            to_client: tx.to_client,
+            group: tx.group,
+            reference: tx.reference.clone(),
+            timestamp: tx.timestamp,
+            memo: tx.memo.clone(),
+            dispute_state: tx.dispute_state,
+            dispute_reference: tx.dispute_reference.clone(),
+            disputes: tx.disputes.clone(),
+        }
+    }
+}
+
+impl<C: ClientId, T: TxId> From<HistoryEntry<C, T>> for Transaction<C, T> {
+    fn from(entry: HistoryEntry<C, T>) -> Self {
+        // States saved before the dispute history were disputed once if ever
+        let mut disputes = entry.disputes;
+        if disputes.is_empty() && entry.dispute_state != DisputeState::None {
+            let reference = entry.dispute_reference.clone();
+            let mut dispute = DisputeRecord::open(None, reference);
+            dispute.state = entry.dispute_state;
+            disputes.push(dispute);
+        }
+
+        Self {
+            kind: entry.kind,
+            client_id: entry.client,
+            id: entry.tx,
+            amount: entry.amount,
+            to_client: entry.to_client,
+            group: entry.group,
+            reference: entry.reference,
+            timestamp: entry.timestamp,
+            memo: entry.memo,
+            dispute_state: entry.dispute_state,
+            dispute_reference: entry.dispute_reference,
+            disputes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_account_state() {
+        let mut account = Account::new(1);
+        account.deposit(dec!(5)).unwrap();
+        account.locked = true;
+        account.credit_limit = Some(dec!(2));
+        let restored = Account::from(AccountState::from(&account));
+        assert_eq!(restored, account);
+
+        // The total is derived rather than restored
+        let state: AccountState<u16> = serde_json::from_str(
+            r#"{"id":1,"available":"2","held":"1","locked":false,"version":3}"#,
+        )
+        .unwrap();
+        let account = Account::from(state);
+        assert_eq!((account.total, account.version), (dec!(3), 3));
+        assert_eq!(account.credit_limit, None);
+    }
+
+    #[test]
+    fn test_history_entry() {
+        let mut tx: Transaction =
+            Transaction::new(TransactionKind::Transfer, 1, 2, Some(dec!(1))).with_recipient(3);
+        tx.memo = Some("rent".to_string());
+        let restored = Transaction::from(HistoryEntry::from(&tx));
+        assert_eq!(restored, tx);
+
+        // Entries saved before the dispute history were disputed once
+        let entry: HistoryEntry<u16, u32> = serde_json::from_str(
+            r#"{"kind":"deposit","client":1,"tx":2,"amount":"3","group":null,"reference":null,"dispute_state":"resolved","dispute_reference":"case-1"}"#,
+        )
+        .unwrap();
+        let tx = Transaction::from(entry);
+        assert_eq!(tx.disputes.len(), 1);
+        assert_eq!(tx.disputes[0].state, DisputeState::Resolved);
+        assert_eq!(tx.disputes[0].reference.as_deref(), Some("case-1"));
+    }
+}
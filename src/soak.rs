@@ -0,0 +1,349 @@
+//! Building blocks of soak tests: a synthetic transaction load, a latency
+//! histogram and a transaction generator, the engine state being checked
+//! with `reconciliation::check_invariants`.
+
+use std::{fs, time::Duration};
+
+use rust_decimal::Decimal;
+
+use crate::{transaction::Transaction, transaction_kind::TransactionKind};
+
+/// An endless, deterministic stream of transactions over a fixed number of
+/// clients: mostly deposits and withdrawals, along with disputes, resolutions
+/// and a few chargebacks of earlier transactions.
+///
+/// Claims pick earlier transactions at random, and may be rejected as any
+/// real one, e.g. when disputing a transaction twice. The stream ends once
+/// every transaction id is used.
+///
+/// # Example
+/// ```
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::reconciliation;
+/// use payments::soak::Generator;
+///
+/// let mut engine = PaymentsEngine::new();
+/// for tx in Generator::new(42, 100).take(10_000) {
+///     let _ = engine.execute(tx);
+/// }
+/// assert!(reconciliation::check_invariants(&engine, false).is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Generator {
+    seed: u64,
+    state: u64,
+    clients: u16,
+    next_id: u32,
+}
+
+impl Generator {
+    /// Create a generator over the clients `1..=clients`, the same seed
+    /// always generating the same transactions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no clients.
+    #[must_use]
+    pub fn new(seed: u64, clients: u16) -> Self {
+        assert!(clients > 0, "the generator requires at least one client");
+        Self { seed, state: seed, clients, next_id: 1 }
+    }
+
+    /// Get the next pseudo-random number.
+    fn random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    /// Get the client of a transaction, so that claims can be routed to the
+    /// client of the transaction they refer to.
+    fn client_of(&self, id: u32) -> u16 {
+        let client = mix(self.seed ^ u64::from(id)) % u64::from(self.clients);
+        u16::try_from(client).map_or(self.clients, |client| client + 1)
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let roll = self.random() % 10_000;
+
+        // Claim an earlier transaction, rarely charging it back
+        if self.next_id > 1 && roll < 1_500 {
+            let earlier = self.random() % u64::from(self.next_id - 1);
+            let id = u32::try_from(earlier).ok()? + 1;
+            let kind = match roll {
+                0..=9 => TransactionKind::Chargeback,
+                10..=799 => TransactionKind::Dispute,
+                _ => TransactionKind::Resolve,
+            };
+            return Some(Transaction::new(kind, self.client_of(id), id, None));
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.checked_add(1)?;
+        let kind = if roll < 7_000 {
+            TransactionKind::Deposit
+        } else {
+            TransactionKind::Withdrawal
+        };
+        let cents = i64::try_from(1 + self.random() % 100_000).ok()?;
+        let amount = Decimal::new(cents, 2);
+        Some(Transaction::new(kind, self.client_of(id), id, Some(amount)))
+    }
+}
+
+/// Scramble the bits of a number, as done by SplitMix64.
+const fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// The number of buckets per power of two of a `Histogram`, bounding the
+/// error of the percentiles to about 3%.
+const SUB_BUCKETS: usize = 32;
+
+/// The number of buckets of a `Histogram`, up to the one of `u64::MAX`.
+const BUCKETS: usize = 60 * SUB_BUCKETS;
+
+/// A histogram of latencies, recording any number of them in constant
+/// memory, with a bounded relative error.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use payments::soak::Histogram;
+///
+/// let mut histogram = Histogram::default();
+/// for micros in 1..=100 {
+///     histogram.record(Duration::from_micros(micros));
+/// }
+///
+/// let median = histogram.percentile(50.0).as_secs_f64() * 1e6;
+/// assert!((median - 50.0).abs() < 2.0);
+/// assert_eq!(histogram.max(), Duration::from_micros(100));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self { counts: vec![0; BUCKETS], count: 0, max: 0 }
+    }
+}
+
+impl Histogram {
+    /// Record a latency, saturating at about 584 years.
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.counts[bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(nanos);
+    }
+
+    /// Merge the latencies of another histogram into this one.
+    pub fn merge(&mut self, other: &Self) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    /// Get the number of recorded latencies.
+    #[must_use]
+    pub const fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the longest recorded latency.
+    #[must_use]
+    pub const fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// Get the latency below which the percentage of the recorded ones fall,
+    /// as the lower bound of its bucket, or zero if nothing was recorded.
+    #[must_use]
+    pub fn percentile(&self, percentage: f64) -> Duration {
+        let rank = ((percentage / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(lower_bound(index).min(self.max));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// Get the bucket of a latency in nanoseconds: values below `SUB_BUCKETS`
+/// have their own buckets, the others are split in `SUB_BUCKETS` buckets per
+/// power of two.
+const fn bucket(nanos: u64) -> usize {
+    let exponent = 63 - (nanos | 1).leading_zeros() as usize;
+    let shift = exponent.saturating_sub(SUB_BUCKETS.trailing_zeros() as usize);
+    let sub = (nanos >> shift) as usize & (SUB_BUCKETS - 1);
+    match shift {
+        0 => nanos as usize,
+        _ => (shift + 1) * SUB_BUCKETS + sub,
+    }
+}
+
+/// Get the lowest latency in nanoseconds of a bucket.
+const fn lower_bound(index: usize) -> u64 {
+    if index < 2 * SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let sub = (index % SUB_BUCKETS + SUB_BUCKETS) as u64;
+    sub << shift
+}
+
+/// Get the resident memory of the process in bytes, only available on
+/// Linux.
+#[must_use]
+pub fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Parse a rate of transactions per second, e.g. `50k/s`, `2m` or `500`.
+///
+/// # Errors
+///
+/// Returns an error if the rate is not a positive number with an optional
+/// `k` or `m` suffix.
+///
+/// # Example
+/// ```
+/// use payments::soak;
+///
+/// assert_eq!(soak::parse_rate("50k/s"), Ok(50_000));
+/// assert!(soak::parse_rate("0").is_err());
+/// ```
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let number = s.strip_suffix("/s").unwrap_or(s);
+    let (number, multiplier) = match number.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&number[..i], 1_000),
+        Some((i, 'm' | 'M')) => (&number[..i], 1_000_000),
+        _ => (number, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|rate| *rate > 0)
+        .ok_or_else(|| format!("Invalid rate: {s}"))
+}
+
+/// Parse a duration made of a number of hours, minutes or seconds, e.g. `2h`,
+/// `1h30m` or `90s`.
+///
+/// # Errors
+///
+/// Returns an error if the duration is empty or a part has no unit.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use payments::soak;
+///
+/// assert_eq!(soak::parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+/// assert!(soak::parse_duration("90").is_err());
+/// ```
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let error = || format!("Invalid duration: {s}");
+    let mut secs: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+        let number: u64 = rest[..digits].parse().map_err(|_| error())?;
+        let unit = match rest.as_bytes()[digits] {
+            b'h' => 3600,
+            b'm' => 60,
+            b's' => 1,
+            _ => return Err(error()),
+        };
+        secs = number
+            .checked_mul(unit)
+            .and_then(|part| secs.checked_add(part))
+            .ok_or_else(error)?;
+        rest = &rest[digits + 1..];
+    }
+
+    if s.is_empty() {
+        return Err(error());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator() {
+        // The same seed generates the same transactions
+        let first: Vec<_> = Generator::new(1, 10).take(1_000).collect();
+        let second: Vec<_> = Generator::new(1, 10).take(1_000).collect();
+        assert_eq!(first, second);
+
+        // Claims are routed to the client of the claimed transaction
+        for tx in &first {
+            assert!((1..=10).contains(&tx.client_id));
+            if tx.amount.is_none() {
+                let claimed = first.iter().find(|other| other.id == tx.id).unwrap();
+                assert_eq!(claimed.client_id, tx.client_id);
+            }
+        }
+        assert!(first.iter().any(|tx| tx.kind == TransactionKind::Dispute));
+    }
+
+    #[test]
+    fn test_histogram() {
+        // Every bucket starts right after the previous one
+        for index in 1..BUCKETS - 1 {
+            assert_eq!(bucket(lower_bound(index)), index);
+            assert_eq!(bucket(lower_bound(index + 1) - 1), index);
+        }
+
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(99.0), Duration::ZERO);
+        for nanos in [10, 20, 30, 1_000_000] {
+            histogram.record(Duration::from_nanos(nanos));
+        }
+        let mut other = Histogram::default();
+        other.record(Duration::from_nanos(40));
+        histogram.merge(&other);
+
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.percentile(50.0), Duration::from_nanos(30));
+        assert_eq!(histogram.max(), Duration::from_millis(1));
+        assert!(histogram.percentile(99.0) > Duration::from_micros(960));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_rate("2M"), Ok(2_000_000));
+        assert_eq!(parse_rate("500"), Ok(500));
+        assert!(parse_rate("k/s").is_err());
+
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1x").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use rust_decimal::Decimal;
+
+use crate::{
+    dispute_state::DisputeState, payments_engine::PaymentsEngine, transaction_kind::TransactionKind,
+};
+
+/// The number and the sum of the amounts of some transactions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub count: usize,
+    pub amount: Decimal,
+}
+
+/// Totals of the engine state, for quick reconciliation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// The number of active accounts.
+    pub clients: usize,
+    pub locked_accounts: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// The fees charged to the accounts, not part of their balances anymore.
+    pub fees: Decimal,
+    /// The transactions applied so far, regardless of their dispute state.
+    pub deposits: Tally,
+    pub withdrawals: Tally,
+    pub transfers: Tally,
+    /// The transactions currently under dispute, and the ones charged back.
+    pub open_disputes: Tally,
+    pub chargebacks: Tally,
+}
+
+/// Summarize the accounts and the transaction history of the engine. Sums
+/// saturate at the bounds of the amounts instead of overflowing.
+///
+/// # Example
+/// ```
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::summary;
+/// use payments::transaction::Transaction;
+/// use payments::transaction_kind::TransactionKind;
+/// use rust_decimal_macros::dec;
+///
+/// let mut engine = PaymentsEngine::new();
+/// engine.execute(Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(2)))).unwrap();
+/// engine.execute(Transaction::new(TransactionKind::Dispute, 1, 1, None)).unwrap();
+///
+/// let summary = summary::summarize(&engine);
+/// assert_eq!(summary.deposits.amount, dec!(2));
+/// assert_eq!(summary.held, dec!(2));
+/// ```
+#[must_use]
+pub fn summarize(engine: &PaymentsEngine) -> Summary {
+    let mut summary = Summary::default();
+
+    for account in engine.accounts_sorted() {
+        summary.clients += 1;
+        summary.locked_accounts += usize::from(account.locked);
+        summary.available = summary.available.saturating_add(account.available);
+        summary.held = summary.held.saturating_add(account.held);
+        summary.total = summary.total.saturating_add(account.total);
+        summary.fees = summary.fees.saturating_add(account.fees);
+    }
+
+    for tx in engine.transactions() {
+        let amount = tx.amount.unwrap_or_default();
+        let tallies = [
+            match tx.kind {
+                TransactionKind::Deposit => Some(&mut summary.deposits),
+                TransactionKind::Withdrawal => Some(&mut summary.withdrawals),
+                TransactionKind::Transfer => Some(&mut summary.transfers),
+                _ => None,
+            },
+            match tx.dispute_state {
+                DisputeState::Open => Some(&mut summary.open_disputes),
+                DisputeState::ChargedBack => Some(&mut summary.chargebacks),
+                DisputeState::None | DisputeState::Resolved => None,
+            },
+        ];
+        for tally in tallies.into_iter().flatten() {
+            tally.count += 1;
+            tally.amount = tally.amount.saturating_add(amount);
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn test_summarize() {
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(TransactionKind::Deposit, 2, 2, Some(dec!(5))),
+            Transaction::new(TransactionKind::Withdrawal, 1, 3, Some(dec!(1))),
+            Transaction::new(TransactionKind::Transfer, 1, 4, Some(dec!(2))).with_recipient(3),
+            Transaction::new(TransactionKind::Dispute, 2, 2, None),
+            Transaction::new(TransactionKind::Chargeback, 2, 2, None),
+            Transaction::new(TransactionKind::Dispute, 1, 3, None),
+        ];
+        let mut engine = PaymentsEngine::new();
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+
+        // Charged back deposits still count as deposits
+        let summary = summarize(&engine);
+        assert_eq!((summary.clients, summary.locked_accounts), (3, 1));
+        assert_eq!(summary.deposits, Tally { count: 2, amount: dec!(15) });
+        assert_eq!(summary.withdrawals, Tally { count: 1, amount: dec!(1) });
+        assert_eq!(summary.transfers, Tally { count: 1, amount: dec!(2) });
+        assert_eq!(summary.open_disputes, Tally { count: 1, amount: dec!(1) });
+        assert_eq!(summary.chargebacks, Tally { count: 1, amount: dec!(5) });
+
+        // Disputing the withdrawal holds the withdrawn funds back
+        assert_eq!((summary.held, summary.total), (dec!(1), dec!(10)));
+    }
+}
@@ -1,26 +1,134 @@
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::transaction_kind::TransactionKind;
+use crate::{
+    dispute_state::{DisputeRecord, DisputeState},
+    ids::{ClientId, TxId},
+    transaction_kind::TransactionKind,
+};
 
 /// Represents a single transaction, this type is meant to be constructed from
-/// the CSV file, except for the `disputed` field.
-#[derive(Deserialize)]
-pub struct Transaction {
+/// the CSV file, except for the `dispute_state`, `dispute_reference` and
+/// `disputes` fields, which are never serialized either.
+///
+/// Transfers state the receiving client in the `to_client` column.
+///
+/// Rows sharing the optional `group` column form a composite transaction,
+/// applied atomically. The optional `reference` column links dispute-related
+/// rows to an external case (e.g. a case id or an evidence URL), it's stored
+/// with the dispute state of the disputed transaction. The optional
+/// `timestamp` column states when the transaction happened, as Unix time in
+/// seconds. The optional `memo` column is free text the engine never
+/// interprets, it's only passed through to the history, the audit log and the
+/// rejects along with the `reference`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "C: ClientId, T: TxId"))]
+pub struct Transaction<C = u16, T = u32> {
     #[serde(rename = "type")]
     pub kind: TransactionKind,
     #[serde(rename = "client")]
-    pub client_id: u16,
-    #[serde(rename = "tx")]
-    pub id: u32,
+    pub client_id: C,
+    #[serde(rename = "tx", deserialize_with = "empty_as_default")]
+    pub id: T,
     pub amount: Option<Decimal>,
+    pub to_client: Option<C>,
+    pub group: Option<T>,
+    pub reference: Option<String>,
+    pub timestamp: Option<i64>,
+    pub memo: Option<String>,
     #[serde(skip)]
-    pub disputed: bool,
+    pub dispute_state: DisputeState,
+    #[serde(skip)]
+    pub dispute_reference: Option<String>,
+    /// The disputes opened on the transaction so far, oldest first.
+    #[serde(skip)]
+    pub disputes: Vec<DisputeRecord>,
 }
 
-impl Transaction {
+impl<C: ClientId, T: TxId> Transaction<C, T> {
     #[must_use]
-    pub fn new(kind: TransactionKind, client_id: u16, id: u32, amount: Option<Decimal>) -> Self {
-        Self { kind, client_id, id, amount, disputed: false }
+    pub fn new(kind: TransactionKind, client_id: C, id: T, amount: Option<Decimal>) -> Self {
+        Self {
+            kind,
+            client_id,
+            id,
+            amount,
+            to_client: None,
+            group: None,
+            reference: None,
+            timestamp: None,
+            memo: None,
+            dispute_state: DisputeState::None,
+            dispute_reference: None,
+            disputes: Vec::new(),
+        }
+    }
+
+    /// Set the receiving client of a transfer.
+    #[must_use]
+    pub const fn with_recipient(mut self, to_client: C) -> Self {
+        self.to_client = Some(to_client);
+        self
+    }
+
+    /// Set when the transaction happened, as Unix time in seconds.
+    #[must_use]
+    pub const fn with_timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Set the external reference of the transaction.
+    #[must_use]
+    pub fn with_reference(mut self, reference: impl Into<String>) -> Self {
+        self.reference = Some(reference.into());
+        self
+    }
+
+    /// Set the free text memo of the transaction.
+    #[must_use]
+    pub fn with_memo(mut self, memo: impl Into<String>) -> Self {
+        self.memo = Some(memo.into());
+        self
+    }
+}
+
+/// Deserialize an empty field as the default id, zero for integers, used for
+/// rows such as balance assertions which don't have a transaction identifier.
+fn empty_as_default<'de, D: Deserializer<'de>, T: TxId>(deserializer: D) -> Result<T, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_precision() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 1.000000000000000001";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let tx: Transaction = reader.deserialize().next().unwrap().unwrap();
+
+        // Amounts are parsed from their textual form, not through a float
+        assert_eq!(tx.amount, Some(dec!(1.000000000000000001)));
+    }
+
+    #[test]
+    fn test_parse_reference() {
+        let data = "type, client, tx, amount, reference\ndispute, 1, 1, , CASE-1\ndeposit, 1, 2, 1";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut txs = reader.deserialize::<Transaction>().map(Result::unwrap);
+
+        // The column is optional, even within the same file
+        assert_eq!(txs.next().unwrap().reference.as_deref(), Some("CASE-1"));
+        assert_eq!(txs.next().unwrap().reference, None);
     }
 }
@@ -0,0 +1,177 @@
+use std::{error::Error, fmt};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Reasons for which the `PaymentsEngine` can reject a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionError {
+    /// A deposit, withdrawal or assertion doesn't state the amount.
+    MissingAmount,
+    /// The account doesn't have enough available or held funds.
+    InsufficientFunds,
+    /// The transaction refers to a transaction missing from the history.
+    UnknownTransaction,
+    /// A composite transaction contains a leg which isn't a deposit or a
+    /// withdrawal.
+    InvalidGroup,
+    /// The referred transaction belongs to a different client.
+    ClientMismatch,
+    /// The client account is locked.
+    AccountLocked,
+    /// The referred transaction is already under dispute.
+    AlreadyDisputed,
+    /// The referred transaction is not under dispute.
+    NotDisputed,
+    /// The client was quarantined after a panic under containment mode.
+    ClientQuarantined,
+    /// A deposit, withdrawal or transfer reuses the id of a previous one.
+    DuplicateTransaction,
+    /// A deposit, withdrawal or transfer states more decimal places than
+    /// allowed by the engine.
+    ExcessPrecision,
+    /// A transfer doesn't state another client as recipient.
+    InvalidRecipient,
+    /// The dispute process of the referred transaction is closed.
+    DisputeClosed,
+    /// The account was soft-deleted.
+    AccountDeleted,
+    /// A deposit, withdrawal or transfer moves more than the maximum amount
+    /// allowed by the engine.
+    AmountTooLarge,
+    /// The balances of the account would exceed the range of the amounts.
+    Overflow,
+    /// A balance assertion found a different total.
+    AssertionFailed { expected: Decimal, actual: Decimal },
+    /// The transaction is older than the last activity of the account.
+    OutOfOrder { timestamp: i64, last_activity: i64 },
+    /// A deposit, withdrawal or transfer moves a zero or negative amount.
+    NonPositiveAmount,
+    /// A transfer or composite transaction spans the shards of a
+    /// `ShardedEngine`.
+    CrossShard,
+    /// The change was applied but the state of a persistent
+    /// `AsyncPaymentsEngine` couldn't be saved, so it may be lost on a crash.
+    StateNotSaved { reason: String },
+}
+
+impl TransactionError {
+    /// Get the stable code of the error, meant for logs and reports. Codes are
+    /// never changed nor reused once assigned.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::transaction_error::TransactionError;
+    ///
+    /// assert_eq!(TransactionError::InsufficientFunds.code(), "PE001");
+    /// ```
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::InsufficientFunds => "PE001",
+            Self::AccountLocked => "PE002",
+            Self::MissingAmount => "PE003",
+            Self::UnknownTransaction => "PE004",
+            Self::InvalidGroup => "PE005",
+            Self::ClientMismatch => "PE006",
+            Self::AlreadyDisputed => "PE007",
+            Self::NotDisputed => "PE008",
+            Self::ClientQuarantined => "PE009",
+            Self::DuplicateTransaction => "PE010",
+            Self::ExcessPrecision => "PE011",
+            Self::AssertionFailed { .. } => "PE012",
+            Self::InvalidRecipient => "PE013",
+            Self::DisputeClosed => "PE014",
+            Self::AccountDeleted => "PE015",
+            Self::AmountTooLarge => "PE016",
+            Self::Overflow => "PE017",
+            Self::OutOfOrder { .. } => "PE018",
+            Self::NonPositiveAmount => "PE019",
+            Self::CrossShard => "PE020",
+            Self::StateNotSaved { .. } => "PE021",
+        }
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingAmount => write!(f, "missing amount"),
+            Self::InsufficientFunds => write!(f, "insufficient funds"),
+            Self::UnknownTransaction => write!(f, "unknown transaction"),
+            Self::InvalidGroup => write!(
+                f,
+                "composite transactions only allow deposits and withdrawals"
+            ),
+            Self::ClientMismatch => write!(f, "transaction belongs to a different client"),
+            Self::AccountLocked => write!(f, "account locked"),
+            Self::AlreadyDisputed => write!(f, "transaction already disputed"),
+            Self::NotDisputed => write!(f, "transaction not disputed"),
+            Self::ClientQuarantined => write!(f, "client quarantined"),
+            Self::DuplicateTransaction => write!(f, "duplicate transaction id"),
+            Self::ExcessPrecision => write!(f, "amount has too many decimal places"),
+            Self::InvalidRecipient => write!(f, "transfer to a missing or the same client"),
+            Self::DisputeClosed => write!(f, "dispute process closed"),
+            Self::AccountDeleted => write!(f, "account deleted"),
+            Self::AmountTooLarge => write!(f, "amount exceeds the maximum allowed"),
+            Self::Overflow => write!(f, "balance overflow"),
+            Self::NonPositiveAmount => write!(f, "amount must be positive"),
+            Self::CrossShard => write!(f, "transaction spans several shards"),
+            Self::StateNotSaved { reason } => write!(f, "state not saved: {reason}"),
+            Self::OutOfOrder { timestamp, last_activity } => {
+                write!(
+                    f,
+                    "timestamp {timestamp} is earlier than the last activity {last_activity}"
+                )
+            }
+            Self::AssertionFailed { expected, actual } => {
+                write!(
+                    f,
+                    "balance assertion failed: expected {expected}, found {actual}"
+                )
+            }
+        }
+    }
+}
+
+impl Error for TransactionError {}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        let errors = [
+            TransactionError::InsufficientFunds,
+            TransactionError::AccountLocked,
+            TransactionError::MissingAmount,
+            TransactionError::UnknownTransaction,
+            TransactionError::InvalidGroup,
+            TransactionError::ClientMismatch,
+            TransactionError::AlreadyDisputed,
+            TransactionError::NotDisputed,
+            TransactionError::ClientQuarantined,
+            TransactionError::DuplicateTransaction,
+            TransactionError::ExcessPrecision,
+            TransactionError::AssertionFailed { expected: dec!(1), actual: dec!(0) },
+            TransactionError::InvalidRecipient,
+            TransactionError::DisputeClosed,
+            TransactionError::AccountDeleted,
+            TransactionError::AmountTooLarge,
+            TransactionError::Overflow,
+            TransactionError::OutOfOrder { timestamp: 0, last_activity: 1 },
+            TransactionError::NonPositiveAmount,
+            TransactionError::CrossShard,
+            TransactionError::StateNotSaved { reason: String::new() },
+        ];
+
+        // Codes are assigned in sequence, and never shared
+        for (i, error) in errors.iter().enumerate() {
+            assert_eq!(error.code(), format!("PE{:03}", i + 1));
+        }
+    }
+}
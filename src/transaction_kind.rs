@@ -1,12 +1,78 @@
-use serde::Deserialize;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
 
 /// Possible transaction types, used for the `kind` field in the `Transaction` type.
-#[derive(Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "snake_case")]
 pub enum TransactionKind {
     Deposit,
     Withdrawal,
+    /// Move funds from the client to the `to_client` account.
+    Transfer,
     Dispute,
     Resolve,
     Chargeback,
+    /// Not an actual transaction: check the client total against the amount.
+    AssertBalance,
+}
+
+impl TransactionKind {
+    /// Every transaction type, in the order they are documented.
+    pub const ALL: [Self; 7] = [
+        Self::Deposit,
+        Self::Withdrawal,
+        Self::Transfer,
+        Self::Dispute,
+        Self::Resolve,
+        Self::Chargeback,
+        Self::AssertBalance,
+    ];
+
+    /// Get the name of the type, as written in the `type` column.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Transfer => "transfer",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::AssertBalance => "assert_balance",
+        }
+    }
+}
+
+impl FromStr for TransactionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(Self::Deposit),
+            "withdrawal" => Ok(Self::Withdrawal),
+            "transfer" => Ok(Self::Transfer),
+            "dispute" => Ok(Self::Dispute),
+            "resolve" => Ok(Self::Resolve),
+            "chargeback" => Ok(Self::Chargeback),
+            "assert_balance" => Ok(Self::AssertBalance),
+            _ => Err(format!("Unknown transaction type: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        // The names are the same in every format
+        for kind in TransactionKind::ALL {
+            assert_eq!(kind.as_str().parse(), Ok(kind));
+            let json = serde_json::to_string(&kind).unwrap();
+            assert_eq!(json, format!("\"{}\"", kind.as_str()));
+        }
+        assert!("Deposit".parse::<TransactionKind>().is_err());
+    }
 }
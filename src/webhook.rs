@@ -0,0 +1,407 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::engine_event::{EngineEvent, EventListener};
+
+/// The number of events waiting to be posted by default, further events are
+/// dropped.
+pub const DEFAULT_QUEUE: usize = 1024;
+
+/// How many times a failed post is retried by default.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The delay before the first retry, doubled on every further one.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How long to wait for the webhook to connect, accept or answer a post.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The outcome of the notifications of a `Webhook`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeliveryStats {
+    pub delivered: usize,
+    /// The events still failing once retried.
+    pub failed: usize,
+    /// The events dropped because the queue was full, or because they
+    /// couldn't be serialized.
+    pub dropped: usize,
+}
+
+/// A listener posting the chargebacks and the account locks, as versioned
+/// JSON events, to a plain HTTP URL from a background thread.
+///
+/// Events are queued without ever blocking the engine: once the queue is
+/// full, further events are dropped and counted. The thread stops once the
+/// listener is dropped along with its engine and the queue is drained.
+///
+/// # Example
+/// ```no_run
+/// use payments::payments_engine::PaymentsEngine;
+/// use payments::webhook::{self, Webhook};
+///
+/// let url = "http://localhost:8080/alerts";
+/// let (webhook, worker) =
+///     Webhook::spawn(url, webhook::DEFAULT_QUEUE, webhook::DEFAULT_RETRIES).unwrap();
+/// let mut engine = PaymentsEngine::new();
+/// engine.add_listener(webhook);
+///
+/// // Process the transactions, then wait for the pending notifications
+/// drop(engine);
+/// let stats = worker.join().unwrap();
+/// assert_eq!(stats.failed, 0);
+/// ```
+#[derive(Debug)]
+pub struct Webhook {
+    sender: SyncSender<Vec<u8>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Webhook {
+    /// Start posting to the URL, returning the listener along with the
+    /// thread posting the events, which returns the outcome once done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL isn't a valid `http://` URL, HTTPS is not
+    /// supported.
+    pub fn spawn(
+        url: &str,
+        queue: usize,
+        retries: u32,
+    ) -> Result<(Self, JoinHandle<DeliveryStats>), String> {
+        let target = Target::parse(url)?;
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&dropped);
+        let worker = thread::spawn(move || {
+            let mut stats = deliver(&target, &receiver, retries);
+            stats.dropped = counter.load(Ordering::Relaxed);
+            stats
+        });
+
+        Ok((Self { sender, dropped }, worker))
+    }
+}
+
+impl EventListener for Webhook {
+    fn on_event(&mut self, event: &EngineEvent) {
+        if !matches!(
+            event,
+            EngineEvent::ChargebackApplied { .. } | EngineEvent::AccountLocked { .. }
+        ) {
+            return;
+        }
+
+        let Ok(payload) = serde_json::to_vec(&event.clone().versioned()) else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(payload) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Post every queued payload until the queue is closed, retrying the failed
+/// ones with an exponential backoff.
+fn deliver(target: &Target, receiver: &Receiver<Vec<u8>>, retries: u32) -> DeliveryStats {
+    let mut stats = DeliveryStats::default();
+
+    for payload in receiver {
+        let mut delay = RETRY_DELAY;
+        let mut result = target.post(&payload);
+        for _ in 0..retries {
+            if result.is_ok() {
+                break;
+            }
+            thread::sleep(delay);
+            delay *= 2;
+            result = target.post(&payload);
+        }
+
+        match result {
+            Ok(()) => stats.delivered += 1,
+            Err(error) => {
+                eprintln!("Failed to post to the webhook: {error}");
+                stats.failed += 1;
+            }
+        }
+    }
+
+    stats
+}
+
+/// Where the events are posted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    /// The host and the port if any, as stated by the URL.
+    authority: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Target {
+    /// Parse an `http://host[:port][/path]` URL, IPv6 hosts being bracketed
+    /// as in `http://[::1]:8080/`.
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported webhook URL: {url}"))?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        // IPv6 hosts are bracketed, their colons aren't port separators
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let invalid = || format!("Invalid webhook host: {url}");
+                let (host, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+                match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None if rest.is_empty() => (host, None),
+                    None => return Err(invalid()),
+                }
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("Invalid webhook port: {port}"))?,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(format!("Missing webhook host: {url}"));
+        }
+
+        Ok(Self {
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Connect to the first reachable address of the host, waiting at most
+    /// `TIMEOUT` for each of them.
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(error) => last_error = Some(error),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address for {}", self.host),
+            )
+        }))
+    }
+
+    /// Post the JSON payload, succeeding on any 2xx status.
+    fn post(&self, payload: &[u8]) -> io::Result<()> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.authority,
+            payload.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(payload);
+        stream.write_all(&request)?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1).map(str::parse::<u16>) {
+            Some(Ok(200..=299)) => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "unexpected response {:?}",
+                status.trim_end()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener};
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{
+        payments_engine::PaymentsEngine, transaction::Transaction,
+        transaction_kind::TransactionKind,
+    };
+
+    #[test]
+    fn test_parse() {
+        let target = Target::parse("http://localhost:8080/alerts").unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("localhost", 8080));
+        assert_eq!(target.path, "/alerts");
+
+        let target = Target::parse("http://example.com").unwrap();
+        assert_eq!((target.port, target.path.as_str()), (80, "/"));
+
+        // IPv6 hosts are connected to without their brackets
+        let target = Target::parse("http://[::1]:8080/alerts").unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("::1", 8080));
+        assert_eq!(target.authority, "[::1]:8080");
+        let target = Target::parse("http://[::1]/").unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("::1", 80));
+        assert!(Target::parse("http://[::1").is_err());
+        assert!(Target::parse("http://[::1]8080/").is_err());
+        assert!(Target::parse("http://[]/").is_err());
+
+        assert!(Target::parse("https://example.com").is_err());
+        assert!(Target::parse("http://example.com:http").is_err());
+    }
+
+    #[test]
+    fn test_webhook() {
+        // Fail the first post, then accept the following ones
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", server.local_addr().unwrap());
+        let requests = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (i, stream) in server.incoming().take(3).enumerate() {
+                let mut stream = stream.unwrap();
+                // Read until the end of the JSON body
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while request.last() != Some(&b'}') {
+                    let read = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                requests.push(String::from_utf8(request).unwrap());
+
+                let status = if i == 0 {
+                    "500 Internal Server Error"
+                } else {
+                    "204 No Content"
+                };
+                write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            requests
+        });
+
+        let (webhook, worker) = Webhook::spawn(&url, DEFAULT_QUEUE, 1).unwrap();
+        let mut engine = PaymentsEngine::new();
+        engine.add_listener(webhook);
+        let txs = [
+            Transaction::new(TransactionKind::Deposit, 1, 1, Some(dec!(1))),
+            Transaction::new(TransactionKind::Dispute, 1, 1, None),
+            Transaction::new(TransactionKind::Chargeback, 1, 1, None),
+        ];
+        for tx in txs {
+            engine.execute(tx).unwrap();
+        }
+        drop(engine);
+
+        // Only the chargeback and the lock are posted, the first one twice
+        let stats = worker.join().unwrap();
+        assert_eq!(stats, DeliveryStats { delivered: 2, failed: 0, dropped: 0 });
+        let requests = requests.join().unwrap();
+        assert!(requests[0].starts_with("POST /alerts HTTP/1.1\r\n"));
+        assert!(requests[0].contains(r#""event":"chargeback_applied""#));
+        assert_eq!(requests[0], requests[1]);
+        assert!(requests[2].contains(r#""event":"account_locked""#));
+    }
+
+    /// Charge back a deposit of each client, emitting two events per client.
+    fn charge_back(webhook: Webhook, clients: u16) {
+        let mut engine = PaymentsEngine::new();
+        engine.add_listener(webhook);
+        for client in 1..=clients {
+            let tx = u32::from(client);
+            let txs = [
+                Transaction::new(TransactionKind::Deposit, client, tx, Some(dec!(1))),
+                Transaction::new(TransactionKind::Dispute, client, tx, None),
+                Transaction::new(TransactionKind::Chargeback, client, tx, None),
+            ];
+            for tx in txs {
+                engine.execute(tx).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_delivery_failure() {
+        // Answer every post with an error, counting them
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", server.local_addr().unwrap());
+        let posts = thread::spawn(move || {
+            let mut posts = 0;
+            for stream in server.incoming().take(6) {
+                let mut stream = stream.unwrap();
+                stream.read_exact(&mut [0; 1]).unwrap();
+                write!(stream, "HTTP/1.1 503 Service Unavailable\r\n\r\n").unwrap();
+                posts += 1;
+            }
+            posts
+        });
+
+        // Each event is tried once, then retried twice before giving up
+        let (webhook, worker) = Webhook::spawn(&url, DEFAULT_QUEUE, 2).unwrap();
+        charge_back(webhook, 1);
+        let stats = worker.join().unwrap();
+        assert_eq!(stats, DeliveryStats { delivered: 0, failed: 2, dropped: 0 });
+        assert_eq!(posts.join().unwrap(), 6);
+
+        // Unreachable webhooks fail the same way
+        let url = {
+            let server = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/alerts", server.local_addr().unwrap())
+        };
+        let (webhook, worker) = Webhook::spawn(&url, DEFAULT_QUEUE, 0).unwrap();
+        charge_back(webhook, 1);
+        let stats = worker.join().unwrap();
+        assert_eq!(stats, DeliveryStats { delivered: 0, failed: 2, dropped: 0 });
+    }
+
+    #[test]
+    fn test_full_queue() {
+        // Hold the first post until every event was emitted
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", server.local_addr().unwrap());
+        let (release, released) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            for (i, stream) in server.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                if i == 0 {
+                    let _ = released.recv();
+                }
+                stream.read_exact(&mut [0; 1]).unwrap();
+                write!(stream, "HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            }
+        });
+
+        // At most one event is being posted and one queued, the others are
+        // dropped without blocking the engine
+        let (webhook, worker) = Webhook::spawn(&url, 1, 0).unwrap();
+        charge_back(webhook, 10);
+        drop(release);
+        let stats = worker.join().unwrap();
+        assert_eq!(stats.failed, 0);
+        assert_eq!(stats.delivered + stats.dropped, 20);
+        assert!(stats.dropped >= 18, "{stats:?}");
+    }
+}
@@ -0,0 +1,666 @@
+//! End-to-end tests running the binary on the fixtures of the `csv` directory.
+
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// Get the path of a fixture.
+fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("csv").join(name);
+    path.to_string_lossy().into_owned()
+}
+
+/// Create an empty directory for the files of a test.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("payments-cli-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run the binary with the arguments from the directory.
+fn payments(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_payments"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run the binary with the arguments from the directory, piping the input to
+/// its standard input.
+fn payments_with_input(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_payments"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Get the standard output of a successful run.
+fn stdout(output: Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "the run failed: {stderr}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Get the standard error of a failed run.
+fn stderr(output: Output) -> String {
+    assert!(!output.status.success(), "the run succeeded");
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn test_standard_input() {
+    let dir = temp_dir("stdin");
+    let expected = stdout(payments(&dir, &[&fixture("deposit.csv")]));
+
+    // Both `-` and no file read the standard input
+    let input = fs::read(fixture("deposit.csv")).unwrap();
+    assert_eq!(stdout(payments_with_input(&dir, &["-"], &input)), expected);
+    assert_eq!(stdout(payments_with_input(&dir, &[], &input)), expected);
+    assert_eq!(
+        stdout(payments_with_input(&dir, &["process", "-"], &input)),
+        expected
+    );
+
+    // Malformed input still fails the run
+    let error = stderr(payments_with_input(
+        &dir,
+        &["-"],
+        b"type,client,tx,amount\nfoo,1,1,1\n",
+    ));
+    assert!(error.contains("unknown variant `foo`"), "{error}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_quarantine_and_requeue() {
+    let dir = temp_dir("quarantine");
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1\n\
+        withdrawal,1,2,1.5\n\
+        foo,1,3,1\n";
+    fs::write(dir.join("transactions.csv"), input).unwrap();
+    let run = |command, input, quarantine| {
+        let args = [
+            command,
+            input,
+            "--state",
+            "state.json",
+            "--quarantine",
+            quarantine,
+        ];
+        let accounts = stdout(payments(&dir, &args));
+        let quarantine = fs::read_to_string(dir.join(quarantine)).unwrap();
+        (accounts, quarantine)
+    };
+
+    // Records failing to be parsed or applied are quarantined with their error
+    let (accounts, quarantine) = run("process", "transactions.csv", "quarantine.csv");
+    assert_eq!(
+        accounts,
+        "id,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+    let lines: Vec<_> = quarantine.lines().collect();
+    assert_eq!(lines[0], "type,client,tx,amount,error");
+    assert_eq!(lines[1], "withdrawal,1,2,1.5,PE001 insufficient funds");
+    assert!(lines[2].starts_with("foo,1,3,1,\"PE100 "), "{quarantine}");
+    assert_eq!(lines.len(), 3);
+
+    // Once fixed, requeuing applies them, what fails again is quarantined anew
+    let fixed = quarantine.replace("foo,", "deposit,");
+    fs::write(dir.join("quarantine.csv"), fixed).unwrap();
+    let (accounts, quarantine) = run("requeue", "quarantine.csv", "quarantine-2.csv");
+    assert_eq!(
+        accounts,
+        "id,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+    );
+    assert_eq!(
+        quarantine,
+        "type,client,tx,amount,error\nwithdrawal,1,2,1.5,PE001 insufficient funds\n"
+    );
+
+    // Until nothing is left
+    let (accounts, quarantine) = run("requeue", "quarantine-2.csv", "quarantine-3.csv");
+    assert_eq!(
+        accounts,
+        "id,available,held,total,locked\n1,0.5000,0.0000,0.5000,false\n"
+    );
+    assert_eq!(quarantine, "type,client,tx,amount,error\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_rejects_report() {
+    let dir = temp_dir("rejects");
+    let input = "type,client,tx,amount,reference,memo\n\
+        deposit,1,1,1,,\n\
+        withdrawal,1,2,5,,rent\n\
+        dispute,1,9,,CASE-7,\n";
+    fs::write(dir.join("transactions.csv"), input).unwrap();
+
+    // Rejected transactions are listed with their line and reason
+    let accounts = stdout(payments(
+        &dir,
+        &["transactions.csv", "--rejects", "rejects.csv"],
+    ));
+    assert_eq!(
+        accounts,
+        "id,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+    let rejects = fs::read_to_string(dir.join("rejects.csv")).unwrap();
+    assert_eq!(
+        rejects,
+        "row,type,client,tx,code,reason,reference,memo\n\
+        3,withdrawal,1,2,PE001,insufficient funds,,rent\n\
+        4,dispute,1,9,PE004,unknown transaction,CASE-7,\n"
+    );
+
+    // JSON lines have no headers
+    let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1\"}\n\
+        {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"5\"}\n";
+    fs::write(dir.join("transactions.json"), input).unwrap();
+    let args = [
+        "transactions.json",
+        "--format",
+        "json",
+        "--rejects",
+        "rejects.csv",
+    ];
+    stdout(payments(&dir, &args));
+    let rejects = fs::read_to_string(dir.join("rejects.csv")).unwrap();
+    assert_eq!(
+        rejects,
+        "row,type,client,tx,code,reason,reference,memo\n\
+        2,withdrawal,1,2,PE001,insufficient funds,,\n"
+    );
+
+    // Nothing rejected leaves the report empty, headers included
+    let args = [&fixture("deposit.csv"), "--rejects", "rejects.csv"];
+    stdout(payments(&dir, &args));
+    let rejects = fs::read_to_string(dir.join("rejects.csv")).unwrap();
+    assert_eq!(rejects, "");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_run_id() {
+    let dir = temp_dir("run-id");
+    let input = fixture("deposit.csv");
+    let run = |run_id, input: &str| {
+        let args = [
+            input,
+            "--state",
+            "state.json",
+            "--run-id",
+            run_id,
+            "--rejects",
+            "rejects.csv",
+        ];
+        payments(&dir, &args)
+    };
+    let expected = stdout(run("2024-01-01", &input));
+
+    // Re-running the same run only regenerates the output, applying nothing
+    let output = run("2024-01-01", &input);
+    let message = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert_eq!(stdout(output), expected);
+    assert!(
+        message.contains("Run 2024-01-01 was already processed"),
+        "{message}"
+    );
+    assert_eq!(fs::read_to_string(dir.join("rejects.csv")).unwrap(), "");
+
+    // While another run applies the transactions again, rejecting the ids
+    assert_eq!(stdout(run("2024-01-02", &input)), expected);
+    let rejects = fs::read_to_string(dir.join("rejects.csv")).unwrap();
+    assert_eq!(rejects.lines().count(), 6, "{rejects}");
+
+    // The same run with different inputs fails, leaving the state untouched
+    let state = fs::read(dir.join("state.json")).unwrap();
+    let error = stderr(run("2024-01-01", &fixture("withdraw.csv")));
+    assert!(
+        error.contains("Run 2024-01-01 was already processed with different inputs"),
+        "{error}"
+    );
+    assert_eq!(fs::read(dir.join("state.json")).unwrap(), state);
+
+    // Run ids are only recorded in a state
+    let error = stderr(payments(&dir, &[&input, "--run-id", "2024-01-01"]));
+    assert!(error.contains("--state"), "{error}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_json_format() {
+    let dir = temp_dir("json");
+    let run = |input: &str| payments_with_input(&dir, &["-", "--format", "json"], input.as_bytes());
+
+    // Transactions are read as JSON lines, skipping blank ones
+    let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+
+{"type":"deposit","client":2,"tx":2,"amount":"2"}
+{"type":"withdrawal","client":1,"tx":3,"amount":"0.5"}
+"#;
+    let expected = r#"{"id":1,"available":"1.0000","held":"0.0000","total":"1.0000","locked":false}
+{"id":2,"available":"2.0000","held":"0.0000","total":"2.0000","locked":false}
+"#;
+    assert_eq!(stdout(run(input)), expected);
+
+    // Malformed lines fail the run, pointing at their line, and so do
+    // amounts given as numbers, which would lose their precision
+    let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+not json
+"#;
+    let error = stderr(run(input));
+    assert!(error.contains("Line 2:"), "{error}");
+    let error = stderr(run(r#"{"type":"deposit","client":1,"tx":1,"amount":1.5}"#));
+    assert!(error.contains("Line 1: invalid type"), "{error}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_fields() {
+    let dir = temp_dir("fields");
+    let run = |fields: &str| payments(&dir, &[&fixture("deposit.csv"), "--fields", fields]);
+
+    // Columns are written in the given order, even when repeated
+    let output = stdout(run("total,id,id"));
+    assert!(output.starts_with("total,id,id\n"), "{output}");
+
+    // Unknown and empty columns are usage errors
+    let output = run("id,fees");
+    assert_eq!(output.status.code(), Some(2));
+    let error = stderr(output);
+    assert!(error.contains("Unknown account field: fees"), "{error}");
+    let error = stderr(run(""));
+    assert!(error.contains("Unknown account field: \n"), "{error}");
+    let error = stderr(run("id,"));
+    assert!(error.contains("Unknown account field: \n"), "{error}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_compressed_input() {
+    let dir = temp_dir("compression");
+    let input = fs::read(fixture("deposit.csv")).unwrap();
+    let expected = stdout(payments(&dir, &[&fixture("deposit.csv")]));
+
+    // Compress the fixture with both formats
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(&input).unwrap();
+    let gzip = gzip.finish().unwrap();
+    fs::write(dir.join("transactions.csv.gz"), &gzip).unwrap();
+    let zstd = zstd::encode_all(&input[..], 0).unwrap();
+    fs::write(dir.join("transactions.csv.zst"), &zstd).unwrap();
+
+    // The compression is detected from the extension
+    assert_eq!(stdout(payments(&dir, &["transactions.csv.gz"])), expected);
+    assert_eq!(stdout(payments(&dir, &["transactions.csv.zst"])), expected);
+
+    // Or stated, e.g. for the standard input
+    let output = payments_with_input(&dir, &["-", "--compression", "gzip"], &gzip);
+    assert_eq!(stdout(output), expected);
+    let output = payments_with_input(&dir, &["-", "--compression", "zstd"], &zstd);
+    assert_eq!(stdout(output), expected);
+
+    // Which takes precedence over the extension
+    stderr(payments(
+        &dir,
+        &["transactions.csv.gz", "--compression", "none"],
+    ));
+
+    // Truncated files fail the run
+    fs::write(dir.join("truncated.csv.gz"), &gzip[..gzip.len() / 2]).unwrap();
+    let error = stderr(payments(&dir, &["truncated.csv.gz"]));
+    assert!(error.contains("UnexpectedEof"), "{error}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_subcommands() {
+    let dir = temp_dir("subcommands");
+    let input = fixture("deposit.csv");
+
+    // Processing is the default subcommand
+    let accounts = stdout(payments(
+        &dir,
+        &["process", &input, "--state", "state.json"],
+    ));
+    assert_eq!(stdout(payments(&dir, &[&input])), accounts);
+
+    // Validating reports every invalid transaction with its line
+    let output = payments(&dir, &["validate", &input]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "5 transaction(s) are valid\n"
+    );
+    assert!(output.status.success());
+    fs::write(
+        dir.join("invalid.csv"),
+        "type,client,tx,amount\ndeposit,1,1,\nfoo,1,2,1\n",
+    )
+    .unwrap();
+    let error = stderr(payments(&dir, &["validate", "invalid.csv"]));
+    assert!(
+        error.starts_with("Line 2, client 1: PE003 missing amount\nLine 3: PE100 "),
+        "{error}"
+    );
+    assert!(
+        error.ends_with("2 of 2 transaction(s) are invalid\"\n"),
+        "{error}"
+    );
+
+    // Reporting prints the accounts of the saved state, along with the reports
+    let args = [
+        "report",
+        "--state",
+        "state.json",
+        "--summary",
+        "summary.csv",
+    ];
+    assert_eq!(stdout(payments(&dir, &args)), accounts);
+    let summary = fs::read_to_string(dir.join("summary.csv")).unwrap();
+    assert!(
+        summary.contains("\ndeposits,5\ndeposits_amount,15.0\n"),
+        "{summary}"
+    );
+    let error = stderr(payments(&dir, &["report"]));
+    assert!(error.contains("report requires --state"), "{error}");
+
+    // Read-only subcommands don't start afresh from a missing state
+    for command in ["report", "reconcile"] {
+        let error = stderr(payments(&dir, &[command, "--state", "missing.json"]));
+        assert!(error.contains("No saved state at missing.json"), "{error}");
+    }
+    assert!(!dir.join("missing.json").exists());
+
+    // Unknown flags are usage errors
+    let output = payments(&dir, &["--bogus"]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_skip_invalid() {
+    let dir = temp_dir("skip-invalid");
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1\n\
+        foo,1,2,1\n\
+        deposit,1,3,abc\n\
+        deposit,2,4,2\n";
+    fs::write(dir.join("transactions.csv"), input).unwrap();
+
+    // Malformed rows abort the run by default
+    let error = stderr(payments(&dir, &["transactions.csv"]));
+    assert!(error.contains("unknown variant `foo`"), "{error}");
+
+    // Or are skipped, then listed by line along with a summary
+    let output = payments(&dir, &["transactions.csv", "--skip-invalid"]);
+    let diagnostics = String::from_utf8_lossy(&output.stderr).into_owned();
+    let accounts = stdout(output);
+    assert_eq!(
+        accounts,
+        "id,available,held,total,locked\n\
+        1,1.0000,0.0000,1.0000,false\n\
+        2,2.0000,0.0000,2.0000,false\n"
+    );
+    let lines: Vec<_> = diagnostics.lines().collect();
+    assert!(lines[0].starts_with("Line 3: PE100 "), "{diagnostics}");
+    assert!(lines[1].starts_with("Line 4: PE100 "), "{diagnostics}");
+    assert!(lines[1].contains("\"abc\""), "{diagnostics}");
+    assert_eq!(lines[2..], ["Skipped 2 invalid row(s)"]);
+
+    // Valid inputs skip nothing
+    let output = payments(&dir, &[&fixture("deposit.csv"), "--skip-invalid"]);
+    assert_eq!(output.stderr, b"");
+    stdout(output);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_output_and_errors_files() {
+    let dir = temp_dir("output");
+    let input = "type,client,tx,amount\ndeposit,1,1,1\nfoo,1,2,1\n";
+    fs::write(dir.join("transactions.csv"), input).unwrap();
+    let expected = "id,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n";
+
+    // The accounts and the diagnostics go to their own files, if any
+    let args = [
+        "transactions.csv",
+        "--skip-invalid",
+        "-o",
+        "accounts.csv",
+        "--errors",
+        "errors.csv",
+    ];
+    let output = payments(&dir, &args);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Skipped 1 invalid row(s)\n"
+    );
+    assert_eq!(stdout(output), "");
+    assert_eq!(
+        fs::read_to_string(dir.join("accounts.csv")).unwrap(),
+        expected
+    );
+    let errors = fs::read_to_string(dir.join("errors.csv")).unwrap();
+    let lines: Vec<_> = errors.lines().collect();
+    assert_eq!(lines[0], "line,client,code,message");
+    assert!(lines[1].starts_with("3,,PE100,"), "{errors}");
+    assert_eq!(lines.len(), 2);
+
+    // Otherwise they go to the standard output and error respectively
+    let output = payments(&dir, &["transactions.csv", "--skip-invalid"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Line 3: PE100 "));
+    assert_eq!(stdout(output), expected);
+
+    // Failed runs write no accounts
+    fs::remove_file(dir.join("accounts.csv")).unwrap();
+    stderr(payments(&dir, &["transactions.csv", "-o", "accounts.csv"]));
+    assert!(!dir.join("accounts.csv").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_split() {
+    let dir = temp_dir("split-order");
+    let input = "type, client, tx, amount\n\
+        deposit, 1, 1, 1.0\n\
+        deposit, 2, 2, 2.0\n\
+        withdrawal, 1, 3, 0.5\n\
+        deposit, 3, 4, 3.0\n\
+        dispute, 1, 1,\n";
+    fs::write(dir.join("transactions.csv"), input).unwrap();
+
+    // Records keep their order within their shard, the prefix defaulting to
+    // the input path
+    let output = payments(&dir, &["split", "transactions.csv", "--shards", "2"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Split 5 transaction(s) into 2 shard(s)\n"
+    );
+    stdout(output);
+    let shard = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(
+        shard("transactions-0.csv"),
+        "type,client,tx,amount\ndeposit,2,2,2.0\n"
+    );
+    assert_eq!(
+        shard("transactions-1.csv"),
+        "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        withdrawal,1,3,0.5\n\
+        deposit,3,4,3.0\n\
+        dispute,1,1,\n"
+    );
+    assert_eq!(
+        shard("transactions-manifest.csv"),
+        "shard,file,rows,clients,total\n\
+        0,transactions-0.csv,1,1,\n\
+        1,transactions-1.csv,4,2,\n"
+    );
+
+    // Transfers and groups across shards can't be split, nor can the
+    // standard input without a prefix
+    let args = [
+        "split",
+        &fixture("transfer.csv"),
+        "--shards",
+        "2",
+        "--prefix",
+        "transfer",
+    ];
+    let error = stderr(payments(&dir, &args));
+    assert!(
+        error.contains("Line 3: transaction 2 spans shards 1 and 0"),
+        "{error}"
+    );
+    let input = "type,client,tx,amount,group\nwithdrawal,1,1,1,1\nwithdrawal,2,2,1,1\n";
+    fs::write(dir.join("group.csv"), input).unwrap();
+    let error = stderr(payments(&dir, &["split", "group.csv", "--shards", "2"]));
+    assert!(
+        error.contains("Line 3: transaction 2 spans shards 0 and 1"),
+        "{error}"
+    );
+    let input = fs::read(fixture("deposit.csv")).unwrap();
+    let error = stderr(payments_with_input(
+        &dir,
+        &["split", "-", "--shards", "2"],
+        &input,
+    ));
+    assert!(error.contains("split requires --prefix"), "{error}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_diff() {
+    let dir = temp_dir("diff");
+    let accounts = stdout(payments(
+        &dir,
+        &[&fixture("deposit.csv"), "--state", "state.json"],
+    ));
+    fs::write(dir.join("expected.csv"), &accounts).unwrap();
+    let accounts = stdout(payments(&dir, &[&fixture("withdraw.csv")]));
+    fs::write(dir.join("accounts.csv"), accounts).unwrap();
+
+    // Identical accounts don't differ, be they outputs or saved states
+    let output = payments(&dir, &["diff", "expected.csv", "state.json"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "No differences across 3 account(s)\n"
+    );
+    assert_eq!(stdout(output), "");
+
+    // Otherwise the differing columns are printed by client, missing accounts
+    // having empty values, and the run fails
+    let output = payments(&dir, &["diff", "expected.csv", "accounts.csv"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,column,baseline,candidate\n\
+        1,available,3,1.5\n\
+        1,total,3,1.5\n\
+        2,available,7,2\n\
+        2,total,7,2\n\
+        3,available,5,\n\
+        3,held,0,\n\
+        3,total,5,\n\
+        3,locked,false,\n"
+    );
+    let error = stderr(output);
+    assert!(
+        error.contains("3 client(s) differ between the accounts"),
+        "{error}"
+    );
+
+    // Missing files fail the run too
+    stderr(payments(&dir, &["diff", "expected.csv", "missing.csv"]));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_split_and_merge_reports() {
+    let dir = temp_dir("split");
+    fs::write(dir.join("totals.csv"), "client,total\n1,3\n2,7\n3,5\n").unwrap();
+
+    // Split the transactions, stating the expected total of each shard
+    let output = payments(
+        &dir,
+        &[
+            "split",
+            &fixture("deposit.csv"),
+            "--shards",
+            "2",
+            "--prefix",
+            "shard",
+            "--control-totals",
+            "totals.csv",
+        ],
+    );
+    stdout(output);
+    let manifest = fs::read_to_string(dir.join("shard-manifest.csv")).unwrap();
+    assert_eq!(
+        manifest,
+        "shard,file,rows,clients,total\n0,shard-0.csv,2,1,7\n1,shard-1.csv,3,2,8\n"
+    );
+
+    // Process each shard, merging the reports gives the accounts of the whole
+    for shard in ["0", "1"] {
+        let accounts = stdout(payments(&dir, &[&format!("shard-{shard}.csv")]));
+        fs::write(dir.join(format!("accounts-{shard}.csv")), accounts).unwrap();
+    }
+    let merged = stdout(payments(
+        &dir,
+        &[
+            "merge-reports",
+            "accounts-0.csv",
+            "accounts-1.csv",
+            "--manifest",
+            "shard-manifest.csv",
+            "--control-totals",
+            "totals.csv",
+        ],
+    ));
+    assert_eq!(merged, stdout(payments(&dir, &[&fixture("deposit.csv")])));
+
+    // Clients can't be in several reports
+    let error = stderr(payments(
+        &dir,
+        &[
+            "merge-reports",
+            "accounts-1.csv",
+            "accounts-1.csv",
+            "--manifest",
+            "shard-manifest.csv",
+        ],
+    ));
+    assert!(error.contains("Client 1 is in both"), "{error}");
+
+    // Nor can reports differ from the total of their shard
+    let manifest = manifest.replace(",2,8\n", ",2,9\n");
+    fs::write(dir.join("shard-manifest.csv"), manifest).unwrap();
+    let error = stderr(payments(
+        &dir,
+        &[
+            "merge-reports",
+            "accounts-0.csv",
+            "accounts-1.csv",
+            "--manifest",
+            "shard-manifest.csv",
+        ],
+    ));
+    assert!(
+        error.contains("the control totals of shard 1 sum up to 9"),
+        "{error}"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}